@@ -1,7 +1,8 @@
 use criterion::{criterion_group, criterion_main, Criterion};
 use criterion::{BenchmarkId, Throughput};
+use rscompress_huffman::block::{BlockWriter, DEFAULT_BLOCK_SIZE};
 use rscompress_huffman::huffman::encode::Encoder;
-use rscompress_huffman::huffman::{generate_extended_codewords, Huffman};
+use rscompress_huffman::huffman::{generate_extended_codewords, Huffman, MAX_CODE_LENGTH};
 use rscompress_huffman::stats::generate_histogram;
 use std::io::prelude::*;
 use std::io::Cursor;
@@ -37,7 +38,7 @@ fn benchmark_io(c: &mut Criterion) {
 
     let mut reader = BufReader::with_capacity(buf, sfile);
 
-    let h = Huffman::from_reader(&mut reader);
+    let h = Huffman::from_reader(&mut reader).expect("Could not read source file");
     let mut writer = Encoder::new(dfile, &h);
     reader
         .seek(std::io::SeekFrom::Start(0))
@@ -81,7 +82,7 @@ fn benchmark_histogram_generation(c: &mut Criterion) {
     group.throughput(Throughput::Bytes(bytes.len() as u64));
     group.bench_function("histogram", |b| {
         b.iter(|| {
-            generate_histogram(&mut bytes.as_slice());
+            generate_histogram(&mut bytes.as_slice()).unwrap();
         })
     });
     group.finish();
@@ -92,12 +93,12 @@ fn benchmark_codeword_generation(c: &mut Criterion) {
     let bytes: Vec<u8> = vec![
         3, 12, 24, 222, 131, 151, 23, 141, 24, 234, 11, 1, 1, 1, 24, 242, 52, 231,
     ];
-    let histogram = generate_histogram(&mut bytes.as_slice());
+    let histogram = generate_histogram(&mut bytes.as_slice()).unwrap();
 
     let mut group = c.benchmark_group("throughput_encoding");
     group.throughput(Throughput::Bytes(bytes.len() as u64));
     group.bench_function("codewords", |b| {
-        b.iter(|| generate_extended_codewords(&histogram))
+        b.iter(|| generate_extended_codewords(&histogram, MAX_CODE_LENGTH))
     });
     group.finish();
 }
@@ -126,7 +127,7 @@ fn benchmark_codeoword_generation_splits(c: &mut Criterion) {
     let bytes: Vec<u8> = vec![
         3, 12, 24, 222, 131, 151, 23, 141, 24, 234, 11, 1, 1, 1, 24, 242, 52, 231,
     ];
-    let histogram = generate_histogram(&mut bytes.as_slice());
+    let histogram = generate_histogram(&mut bytes.as_slice()).unwrap();
 
     let mut group = c.benchmark_group("codeword_generation");
     group.throughput(Throughput::Bytes(bytes.len() as u64));
@@ -152,7 +153,7 @@ fn benchmark_codeoword_generation_excl1(c: &mut Criterion) {
     let bytes: Vec<u8> = vec![
         3, 12, 24, 222, 131, 151, 23, 141, 24, 234, 11, 1, 1, 1, 24, 242, 52, 231,
     ];
-    let histogram = generate_histogram(&mut bytes.as_slice());
+    let histogram = generate_histogram(&mut bytes.as_slice()).unwrap();
 
     let mut group = c.benchmark_group("codeword_generation");
     group.throughput(Throughput::Bytes(bytes.len() as u64));
@@ -169,7 +170,7 @@ fn benchmark_codeoword_generation_excl2(c: &mut Criterion) {
     let bytes: Vec<u8> = vec![
         3, 12, 24, 222, 131, 151, 23, 141, 24, 234, 11, 1, 1, 1, 24, 242, 52, 231,
     ];
-    let histogram = generate_histogram(&mut bytes.as_slice());
+    let histogram = generate_histogram(&mut bytes.as_slice()).unwrap();
     let sorted_tuple = sort_by_value(&histogram); // Step 1
 
     let mut group = c.benchmark_group("codeword_generation");
@@ -187,7 +188,7 @@ fn benchmark_codeoword_generation_excl3(c: &mut Criterion) {
     let bytes: Vec<u8> = vec![
         3, 12, 24, 222, 131, 151, 23, 141, 24, 234, 11, 1, 1, 1, 24, 242, 52, 231,
     ];
-    let histogram = generate_histogram(&mut bytes.as_slice());
+    let histogram = generate_histogram(&mut bytes.as_slice()).unwrap();
     let sorted_tuple = sort_by_value(&histogram); // Step 1
     let mut weights = extract_values(&sorted_tuple); // Step 2
 
@@ -206,7 +207,7 @@ fn benchmark_codeoword_generation_excl4(c: &mut Criterion) {
     let bytes: Vec<u8> = vec![
         3, 12, 24, 222, 131, 151, 23, 141, 24, 234, 11, 1, 1, 1, 24, 242, 52, 231,
     ];
-    let histogram = generate_histogram(&mut bytes.as_slice());
+    let histogram = generate_histogram(&mut bytes.as_slice()).unwrap();
     let sorted_tuple = sort_by_value(&histogram); // Step 1
     let mut weights = extract_values(&sorted_tuple); // Step 2
     calculate_codeword_length_inplace(&mut weights); // Step 3
@@ -226,7 +227,7 @@ fn benchmark_codeoword_generation_excl5(c: &mut Criterion) {
     let bytes: Vec<u8> = vec![
         3, 12, 24, 222, 131, 151, 23, 141, 24, 234, 11, 1, 1, 1, 24, 242, 52, 231,
     ];
-    let histogram = generate_histogram(&mut bytes.as_slice());
+    let histogram = generate_histogram(&mut bytes.as_slice()).unwrap();
     let sorted_tuple = sort_by_value(&histogram); // Step 1
     let mut weights = extract_values(&sorted_tuple); // Step 2
     calculate_codeword_length_inplace(&mut weights); // Step 3
@@ -257,7 +258,7 @@ fn benchmark_packing_of_bits_encode(c: &mut Criterion) {
         codewords[*word as usize] = *word as usize;
         length[*word as usize] = calculate_length(*word as usize);
     }
-    let h = Huffman::new(codewords, length);
+    let h = Huffman::new(codewords.to_vec(), length.to_vec());
     let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
 
     let mut group = c.benchmark_group("packing");
@@ -271,7 +272,7 @@ fn benchmark_packing_of_bits_encode(c: &mut Criterion) {
     group.finish();
 }
 
-use rscompress_huffman::huffman::decode::{read, search_key_or_next_small_key};
+use rscompress_huffman::huffman::decode::{read, search_key_or_next_small_key, vault, Decoder};
 use rscompress_huffman::model::Model;
 use std::io::Write;
 
@@ -282,7 +283,7 @@ fn benchmark_packing_of_bits_decode(c: &mut Criterion) {
     for i in 0..words.len() {
         histogram[i] = words[i] as usize;
     }
-    let h = Huffman::from_histogram(&histogram);
+    let h = Huffman::from_histogram(&histogram, MAX_CODE_LENGTH).unwrap();
     let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
 
     // Encode `words`
@@ -299,7 +300,7 @@ fn benchmark_packing_of_bits_decode(c: &mut Criterion) {
         &inputs,
         |b, &inputs| {
             b.iter(|| {
-                read(inputs.0, inputs.1, inputs.2);
+                read(inputs.0, inputs.1, inputs.2).unwrap();
             })
         },
     );
@@ -309,9 +310,9 @@ fn benchmark_packing_of_bits_decode(c: &mut Criterion) {
 use std::collections::BTreeMap;
 use std::io::BufRead;
 
-fn iter_search_key_or_next_small_key(bt: &BTreeMap<usize, (u8, u8)>, data: &[u8]) {
+fn iter_search_key_or_next_small_key(bt: &BTreeMap<usize, (u16, u8)>, data: &[u8]) {
     for key in data {
-        search_key_or_next_small_key(bt, *key as usize);
+        search_key_or_next_small_key(bt, *key as usize).unwrap();
     }
 }
 
@@ -321,7 +322,7 @@ fn benchmark_searching_for_key_value(c: &mut Criterion) {
     for i in 0..words.len() {
         histogram[i] = words[i] as usize;
     }
-    let h = Huffman::from_histogram(&histogram);
+    let h = Huffman::from_histogram(&histogram, MAX_CODE_LENGTH).unwrap();
     let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
     let sfile = File::open("test.raw").expect("Failed to open source file");
     let mut b = BufReader::new(sfile);
@@ -339,6 +340,112 @@ fn benchmark_searching_for_key_value(c: &mut Criterion) {
     group.finish();
 }
 
+use rscompress_huffman::huffman::decode::table::DecodeTable;
+use rscompress_huffman::huffman::decode::{prepare_lookup, rank_lookup};
+use rscompress_huffman::stats::generate_random_byte_vector;
+
+// Lookup of the symbol for the next `sentinel` bits: rank query vs. lookup table
+fn benchmark_lookup_rank_vs_table(c: &mut Criterion) {
+    let mut histogram = [1usize; 256];
+    let (mut x, mut y) = (1usize, 1usize);
+    for count in histogram.iter_mut().take(30) {
+        *count = x;
+        let next = x + y;
+        x = y;
+        y = next;
+    }
+    let h = Huffman::from_histogram(&histogram, MAX_CODE_LENGTH).unwrap();
+    let bt = h.to_btreemap();
+    let sentinel = h.sentinel();
+    let (table, rbv) = prepare_lookup(&bt).unwrap();
+    let decode_table = DecodeTable::new(&bt, sentinel).unwrap();
+    let searchvalues: Vec<u64> = (0..100_000u64)
+        .map(|i| i.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - sentinel))
+        .collect();
+
+    let mut group = c.benchmark_group("lookup");
+    group.throughput(Throughput::Elements(searchvalues.len() as u64));
+    group.bench_function("rank", |b| {
+        b.iter(|| {
+            for v in searchvalues.iter() {
+                rank_lookup(&rbv, &table, *v).unwrap();
+            }
+        })
+    });
+    group.bench_function("table", |b| {
+        b.iter(|| {
+            for v in searchvalues.iter() {
+                decode_table.lookup(*v).unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+// Decoding of random data with the lookup table based decoders
+fn benchmark_decoding_with_table(c: &mut Criterion) {
+    let words: Vec<u8> = vec![20, 17, 6, 3, 2, 2, 2, 1, 1, 1];
+    let origin = generate_random_byte_vector(0, words.len() as u8, 1_000_000, &words);
+    let h = Huffman::from_slice(&origin);
+    let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
+    enc.write(&origin).expect("");
+    enc.flush().expect("");
+    let encoded = enc.inner.get_ref().clone();
+
+    let mut group = c.benchmark_group("decoding");
+    group.throughput(Throughput::Bytes(origin.len() as u64));
+    group.bench_function("read", |b| {
+        b.iter(|| read(&encoded, &h, origin.len()).unwrap())
+    });
+    group.bench_function("decoder", |b| {
+        b.iter(|| {
+            let mut decoder = Decoder::new(encoded.as_slice(), &enc).unwrap();
+            let mut result = Vec::with_capacity(origin.len());
+            decoder.read_to_end(&mut result).unwrap();
+            result
+        })
+    });
+    group.bench_function("decoder_multi", |b| {
+        b.iter(|| {
+            let mut decoder = Decoder::new(encoded.as_slice(), &enc).unwrap();
+            decoder.use_multi_symbol_table(true);
+            let mut result = Vec::with_capacity(origin.len());
+            decoder.read_to_end(&mut result).unwrap();
+            result
+        })
+    });
+    group.bench_function("vault", |b| {
+        b.iter(|| {
+            let mut decoder =
+                vault::Decoder::new(encoded.as_slice(), &h, origin.len() as u64).unwrap();
+            let mut result = Vec::with_capacity(origin.len());
+            decoder.read_to_end(&mut result).unwrap();
+            result
+        })
+    });
+    group.finish();
+}
+
+fn benchmark_parallel_block_compression(c: &mut Criterion) {
+    let words: Vec<u8> = vec![20, 17, 6, 3, 2, 2, 2, 1, 1, 1];
+    let origin = generate_random_byte_vector(0, words.len() as u8, 8_000_000, &words);
+
+    let mut group = c.benchmark_group("block_compression");
+    group.throughput(Throughput::Bytes(origin.len() as u64));
+    group.sample_size(10);
+    for threads in [1, 2, 4, 8].iter() {
+        group.bench_with_input(BenchmarkId::from_parameter(threads), threads, |b, &threads| {
+            b.iter(|| {
+                let mut writer = BlockWriter::new(Vec::new(), DEFAULT_BLOCK_SIZE).unwrap();
+                writer.use_threads(threads);
+                writer.write_all(&origin).unwrap();
+                writer.finish().unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
 criterion_group!(
     benches,
     benchmark_histogram_generation,
@@ -363,5 +470,12 @@ criterion_group!(
 );
 criterion_group!(io, benchmark_io);
 criterion_group!(search, benchmark_searching_for_key_value);
+criterion_group!(
+    decoding,
+    benchmark_lookup_rank_vs_table,
+    benchmark_decoding_with_table
+);
+
+criterion_group!(parallel, benchmark_parallel_block_compression);
 
-criterion_main!(search);
+criterion_main!(search, decoding, parallel);
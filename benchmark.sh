@@ -48,18 +48,18 @@ elif [ `git rev-parse --abbrev-ref HEAD` == "master" ]; then
     if [ "${HYPERFINE_DROP_CACHES}" = true ]; then
       DROP_CACHE_CMD="sync; echo 3 | sudo tee /proc/sys/vm/drop_caches"
       OUTPUT="${HYPERFINE_EXPORT_FILE::(-5)}.${i}.cachedropped.json"
-      hyperfine "${TARGET} ${i} {outs}" -s none -p "${DROP_CACHE_CMD}" -r $HYPERFINE_RUNS  --export-json $OUTPUT -L outs "${HYPERFINE_OUTPUTS}"
+      hyperfine "${TARGET} compress -c ${i} > {outs}" -s none -p "${DROP_CACHE_CMD}" -r $HYPERFINE_RUNS  --export-json $OUTPUT -L outs "${HYPERFINE_OUTPUTS}"
     else
       OUTPUT="${HYPERFINE_EXPORT_FILE::(-5)}.${i}.cachevalid.json"
-      hyperfine "${TARGET} ${i} {outs}" -s none -r $HYPERFINE_RUNS  --export-json $OUTPUT -L outs "${HYPERFINE_OUTPUTS}"
+      hyperfine "${TARGET} compress -c ${i} > {outs}" -s none -r $HYPERFINE_RUNS  --export-json $OUTPUT -L outs "${HYPERFINE_OUTPUTS}"
     fi
     for o in "${OUTPUTS[@]}"; do
       echo "Running flamegraph for input: ${i} & output: ${o}"
       OUTPUT="${FLAMEGRAPH_EXPORT_FILE::(-4)}.${i}.${o//\//\-}.svg"
-      cargo-flamegraph flamegraph --bin $FLAMEGRAPH_TARGET -o $OUTPUT -- "${i}" "${o}"
+      cargo-flamegraph flamegraph --bin $FLAMEGRAPH_TARGET -o $OUTPUT -- compress -c "${i}" > /dev/null
       echo "Running valgrind for input: ${i} & output: ${o}"
       OUTPUT="${VALGRIND_EXPORT_FILE::(-7)}.${i}.${o//\//\-}.massif"
-      valgrind --tool=massif --time-unit=B --massif-out-file=$OUTPUT $TARGET $i $o
+      valgrind --tool=massif --time-unit=B --massif-out-file=$OUTPUT $TARGET compress -c $i > $o
     done
   done
 elif [ `git rev-parse --abbrev-ref HEAD` == "staging" ]; then
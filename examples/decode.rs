@@ -2,7 +2,7 @@
 use log::info;
 use rscompress_huffman::huffman::decode::{read, Decoder};
 use rscompress_huffman::huffman::encode::Encoder;
-use rscompress_huffman::huffman::Huffman;
+use rscompress_huffman::huffman::{Huffman, MAX_CODE_LENGTH};
 use rscompress_huffman::stats::generate_random_byte_vector;
 use std::fs::File;
 use std::io::prelude::*;
@@ -37,7 +37,7 @@ fn main() {
         // info!("Size: {}", origin.len());
 
         // Generate Huffman Model
-        let h = Huffman::from_histogram(&histogram);
+        let h = Huffman::from_histogram(&histogram, MAX_CODE_LENGTH).expect("");
 
         // Generate Encoder and apply to data
         let now = Instant::now();
@@ -48,12 +48,12 @@ fn main() {
 
         // Old decoding method
         let now = Instant::now();
-        let decoded_words = read(enc.inner.get_ref(), &h, enc.readbytes);
+        let decoded_words = read(enc.inner.get_ref(), &h, enc.readbytes).expect("");
         info!("O {}", now.elapsed().as_secs_f32());
 
         // Read decoding method
         let reader = BufReader::new(Cursor::new(enc.inner.get_ref()));
-        let mut decoder = Decoder::new(reader, &enc);
+        let mut decoder = Decoder::new(reader, &enc).expect("");
         let mut buf = [0u8; 15];
         let mut full: Vec<u8> = Vec::with_capacity(origin.len());
 
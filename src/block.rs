@@ -0,0 +1,694 @@
+//! Block-based container with a separate Huffman table per block
+//!
+//! One table for a whole file compresses badly, if the statistics of the data
+//! drift, e.g. for concatenated logs or mixed binary and text data. The
+//! `BlockWriter` therefore splits the input into blocks of `block_size` bytes
+//! and encodes every block with its own table. The `BlockReader` decodes the
+//! blocks one after another and hides the block boundaries.
+//!
+//! # Layout of a block
+//! - 4 byte number of symbols in the block (big endian)
+//! - 4 byte length of the encoded data (big endian)
+//! - 2 byte length of the table (big endian)
+//! - x byte run-length encoded codeword lengths, see `huffman::header`
+//! - x byte encoded data, padded to a full byte
+//!
+//! The last block is followed by an end marker, i.e. a block with zero
+//! symbols and no further fields.
+//!
+//! # Index
+//! With `BlockWriter::use_index` the end marker is followed by an index of
+//! all blocks, i.e. the size of every block in the file and its number of
+//! symbols (4 byte each, big endian), and the number of blocks (8 byte, big
+//! endian). The index can be found from the end of the file and allows
+//! decoding the blocks in parallel, see `decompress_parallel`.
+//!
+//! Blocks are independent of each other. With `BlockWriter::use_threads` up
+//! to `threads` blocks are encoded at the same time on separate threads. The
+//! block boundaries do not depend on the number of threads, therefore the
+//! output is byte-identical.
+use crate::checksum::Crc32;
+use crate::error::{HuffmanError, Result};
+use crate::format::read_exact_or_truncated;
+use crate::huffman::decode::{Decoder, MAX_SENTINEL};
+use crate::huffman::encode::Encoder;
+use crate::huffman::header::{decode_lengths, encode_lengths, model_lengths};
+use crate::huffman::Huffman;
+use log::debug;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Default number of symbols per block
+pub const DEFAULT_BLOCK_SIZE: usize = 1 << 20;
+
+/// Largest number of symbols per block. Ensures that the encoded length of a
+/// block fits into its 4 byte field.
+pub const MAX_BLOCK_SIZE: usize = 1 << 28;
+
+/// Size of the fields of a block in front of its table
+const BLOCK_FIELDS: usize = 10;
+
+/// Check that `block_size` is usable for the container
+pub fn validate_block_size(block_size: usize) -> Result<()> {
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+        return Err(HuffmanError::InvalidCode(format!(
+            "Block size {} not in 1..={}",
+            block_size, MAX_BLOCK_SIZE
+        )));
+    }
+    Ok(())
+}
+
+/// Encode `data` as a single block with its own table
+pub fn compress_block(data: &[u8]) -> Result<Vec<u8>> {
+    if data.is_empty() {
+        return Err(HuffmanError::InvalidCode("Empty block".to_string()));
+    }
+    validate_block_size(data.len())?;
+    let h = Huffman::from_slice(data);
+    let mut enc = Encoder::new(Vec::new(), &h);
+    // `Encoder::write` consumes all of `data`
+    let _output_bytes = enc.write(data)?;
+    enc.flush()?;
+    let mut table = Vec::new();
+    encode_lengths(&model_lengths(&h), &mut table)?;
+
+    let mut block = Vec::with_capacity(BLOCK_FIELDS + table.len() + enc.inner.len());
+    block.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    block.extend_from_slice(&(enc.inner.len() as u32).to_be_bytes());
+    block.extend_from_slice(&(table.len() as u16).to_be_bytes());
+    block.extend_from_slice(&table);
+    block.extend_from_slice(&enc.inner);
+    debug!(
+        "Block: {} symbols, {} bytes table, {} bytes data",
+        data.len(),
+        table.len(),
+        enc.inner.len()
+    );
+    Ok(block)
+}
+
+/// Fields of a block preceding the encoded data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockHeader {
+    /// Number of symbols, i.e. size of the decoded block
+    pub symbols: usize,
+    /// Size of the encoded data in bytes
+    pub encoded: usize,
+    /// Codeword length of every byte, `0` for bytes which do not occur
+    pub lengths: Vec<u8>,
+}
+
+impl BlockHeader {
+    /// Read the fields of the next block up to its encoded data, `None` for
+    /// the end marker
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+        let mut field = [0u8; 4];
+        read_exact_or_truncated(reader, &mut field)?;
+        let symbols = u32::from_be_bytes(field) as usize;
+        if symbols == 0 {
+            return Ok(None);
+        }
+        read_exact_or_truncated(reader, &mut field)?;
+        let encoded = u32::from_be_bytes(field) as usize;
+        if symbols > MAX_BLOCK_SIZE || encoded > (symbols * MAX_SENTINEL).div_ceil(8) {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Block of {} symbols with {} bytes encoded data",
+                symbols, encoded
+            )));
+        }
+        let mut table_length = [0u8; 2];
+        read_exact_or_truncated(reader, &mut table_length)?;
+        let mut table = vec![0u8; u16::from_be_bytes(table_length) as usize];
+        read_exact_or_truncated(reader, &mut table)?;
+        let mut bytes = table.iter().cloned();
+        let lengths = decode_lengths(&mut bytes)?;
+        if bytes.next().is_some() {
+            return Err(HuffmanError::CorruptHeader(
+                "Trailing bytes after the block table".to_string(),
+            ));
+        }
+        Ok(Some(BlockHeader {
+            symbols,
+            encoded,
+            lengths,
+        }))
+    }
+}
+
+/// Model and encoded data of a single block
+struct EncodedBlock {
+    symbols: usize,
+    model: Huffman,
+    data: Vec<u8>,
+}
+
+impl EncodedBlock {
+    /// Read the next block, `None` for the end marker
+    fn read_from<R: Read>(reader: &mut R) -> Result<Option<Self>> {
+        let header = match BlockHeader::read_from(reader)? {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+        let model = Huffman::from_lengths(&header.lengths)?;
+
+        let mut data = vec![0u8; header.encoded];
+        read_exact_or_truncated(reader, &mut data)?;
+        Ok(Some(EncodedBlock {
+            symbols: header.symbols,
+            model,
+            data,
+        }))
+    }
+
+    fn decoder(&self) -> Result<Decoder<&[u8]>> {
+        Decoder::with_model(self.data.as_slice(), &self.model, self.symbols)
+    }
+}
+
+/// Read the next block from `reader` and append the decoded data to
+/// `output`. Returns the number of decoded bytes, `0` for the end marker.
+pub fn decompress_block<R: Read>(reader: &mut R, output: &mut Vec<u8>) -> Result<usize> {
+    let block = match EncodedBlock::read_from(reader)? {
+        Some(block) => block,
+        None => return Ok(0),
+    };
+    let before = output.len();
+    block.decoder()?.read_to_end(output)?;
+    if output.len() - before != block.symbols {
+        return Err(HuffmanError::TruncatedStream);
+    }
+    Ok(block.symbols)
+}
+
+/// Decode the encoded `block` into `output`, which must have the size of the
+/// decoded block.
+pub fn decompress_block_into(block: &[u8], output: &mut [u8]) -> Result<()> {
+    let mut reader = block;
+    let block = EncodedBlock::read_from(&mut reader)?
+        .ok_or_else(|| HuffmanError::CorruptHeader("Unexpected end marker".to_string()))?;
+    if block.symbols != output.len() || !reader.is_empty() {
+        return Err(HuffmanError::CorruptHeader(format!(
+            "Block of {} symbols does not match the index",
+            block.symbols
+        )));
+    }
+    block.decoder()?.read_exact(output)?;
+    Ok(())
+}
+
+/// Position of a block in the compressed and the decompressed data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// Size of the encoded block in bytes, including its table
+    pub size: u32,
+    /// Number of symbols, i.e. size of the decoded block
+    pub symbols: u32,
+}
+
+impl IndexEntry {
+    /// Check the entry against the limits of `BlockHeader::read_from`, before
+    /// any memory is allocated for the block
+    fn validate(&self) -> Result<()> {
+        let symbols = self.symbols as usize;
+        let size = self.size as usize;
+        let largest = BLOCK_FIELDS + u16::MAX as usize + (symbols * MAX_SENTINEL).div_ceil(8);
+        if symbols == 0 || symbols > MAX_BLOCK_SIZE || size <= BLOCK_FIELDS || size > largest {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Index entry of {} symbols in {} bytes",
+                symbols, size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Index of all blocks of a file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockIndex {
+    pub entries: Vec<IndexEntry>,
+}
+
+impl BlockIndex {
+    /// Size of the encoded blocks including the end marker
+    pub fn encoded_size(&self) -> u64 {
+        self.entries.iter().map(|e| e.size as u64).sum::<u64>() + 4
+    }
+    /// Size of the decoded data
+    pub fn decoded_size(&self) -> u64 {
+        self.entries.iter().map(|e| e.symbols as u64).sum()
+    }
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        for entry in self.entries.iter() {
+            writer.write_all(&entry.size.to_be_bytes())?;
+            writer.write_all(&entry.symbols.to_be_bytes())?;
+        }
+        writer.write_all(&(self.entries.len() as u64).to_be_bytes())?;
+        Ok(())
+    }
+    /// Read the index of `blocks` blocks following the end marker. Rejects
+    /// entries which no valid block can have.
+    pub fn read_from<R: Read>(reader: &mut R, blocks: u64) -> Result<Self> {
+        let mut entries = Vec::new();
+        let mut field = [0u8; 8];
+        for _ in 0..blocks {
+            read_exact_or_truncated(reader, &mut field)?;
+            let [a, b, c, d, e, f, g, h] = field;
+            let entry = IndexEntry {
+                size: u32::from_be_bytes([a, b, c, d]),
+                symbols: u32::from_be_bytes([e, f, g, h]),
+            };
+            entry.validate()?;
+            entries.push(entry);
+        }
+        read_exact_or_truncated(reader, &mut field)?;
+        if u64::from_be_bytes(field) != blocks {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Index of {} blocks expected",
+                blocks
+            )));
+        }
+        Ok(BlockIndex { entries })
+    }
+    /// Read the index from the end of a file, whose encoded blocks start at
+    /// `start`. `trailer` is the number of bytes after the index, e.g. `4` for
+    /// a CRC-32 footer. The entries are validated like in `read_from`.
+    pub fn load<R: Read + Seek>(reader: &mut R, start: u64, trailer: u64) -> Result<Self> {
+        let end = reader.seek(SeekFrom::End(0))?;
+        let corrupt = || HuffmanError::CorruptHeader("Invalid block index".to_string());
+        let count_position = end.checked_sub(trailer + 8).ok_or_else(corrupt)?;
+        reader.seek(SeekFrom::Start(count_position))?;
+        let mut field = [0u8; 8];
+        read_exact_or_truncated(reader, &mut field)?;
+        let blocks = u64::from_be_bytes(field);
+        let index_position = blocks
+            .checked_mul(8)
+            .and_then(|size| count_position.checked_sub(size))
+            .filter(|&position| position >= start)
+            .ok_or_else(corrupt)?;
+        reader.seek(SeekFrom::Start(index_position))?;
+        let index = BlockIndex::read_from(reader, blocks)?;
+        if start + index.encoded_size() != index_position {
+            return Err(corrupt());
+        }
+        Ok(index)
+    }
+}
+
+/// Number of available cores, more threads do not encode or decode faster
+fn max_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Decode all blocks described by `index` from `reader`, which is positioned
+/// at the first block, and write them to `writer`. Up to `threads` blocks are
+/// decoded at the same time, each directly into its position of the output.
+/// Returns the CRC-32 of the decoded data.
+pub fn decompress_parallel<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    index: &BlockIndex,
+    threads: usize,
+) -> Result<u32> {
+    let mut crc = Crc32::new();
+    let mut output = Vec::new();
+    for batch in index.entries.chunks(threads.clamp(1, max_threads())) {
+        let mut blocks = Vec::with_capacity(batch.len());
+        for entry in batch {
+            let mut block = vec![0u8; entry.size as usize];
+            read_exact_or_truncated(reader, &mut block)?;
+            blocks.push(block);
+        }
+        let symbols: usize = batch.iter().map(|e| e.symbols as usize).sum();
+        output.resize(symbols, 0);
+        let mut slots = Vec::with_capacity(batch.len());
+        let mut rest = output.as_mut_slice();
+        for entry in batch {
+            let (slot, tail) = rest.split_at_mut(entry.symbols as usize);
+            slots.push(slot);
+            rest = tail;
+        }
+        let results: Vec<Result<()>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = blocks
+                .iter()
+                .zip(slots)
+                .map(|(block, slot)| scope.spawn(move || decompress_block_into(block, slot)))
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("Block decompression panicked"))
+                .collect()
+        });
+        results.into_iter().collect::<Result<Vec<()>>>()?;
+        crc.update(&output);
+        writer.write_all(&output)?;
+    }
+    let mut marker = [0u8; 4];
+    read_exact_or_truncated(reader, &mut marker)?;
+    if marker != [0u8; 4] {
+        return Err(HuffmanError::CorruptHeader("Missing end marker".to_string()));
+    }
+    Ok(crc.finalize())
+}
+
+/// Splits the written data into blocks and encodes each with its own table
+pub struct BlockWriter<W: Write> {
+    inner: W,
+    block_size: usize,
+    buffer: Vec<u8>,
+    checksum: Option<Crc32>,
+    threads: usize,
+    index: Option<BlockIndex>,
+    pub readbytes: u64,
+}
+
+impl<W: Write> BlockWriter<W> {
+    pub fn new(writer: W, block_size: usize) -> Result<Self> {
+        validate_block_size(block_size)?;
+        Ok(BlockWriter {
+            inner: writer,
+            block_size,
+            buffer: Vec::with_capacity(block_size),
+            checksum: None,
+            threads: 1,
+            index: None,
+            readbytes: 0,
+        })
+    }
+    /// Append a CRC-32 of all written data after the end marker
+    pub fn use_checksum(&mut self, enabled: bool) {
+        self.checksum = if enabled { Some(Crc32::new()) } else { None };
+    }
+    /// Encode up to `threads` blocks in parallel, at most one per available
+    /// core. Values below `1` are treated as `1`. The buffer of the blocks
+    /// grows with the written data.
+    pub fn use_threads(&mut self, threads: usize) {
+        self.threads = threads.clamp(1, max_threads());
+    }
+    /// Write an index of all blocks after the end marker
+    pub fn use_index(&mut self, enabled: bool) {
+        self.index = if enabled {
+            Some(BlockIndex::default())
+        } else {
+            None
+        };
+    }
+    fn emit(&mut self, block: &[u8], symbols: usize) -> Result<()> {
+        self.inner.write_all(block)?;
+        if let Some(index) = self.index.as_mut() {
+            index.entries.push(IndexEntry {
+                size: block.len() as u32,
+                symbols: symbols as u32,
+            });
+        }
+        Ok(())
+    }
+    /// Number of bytes buffered before the blocks are encoded
+    fn batch_size(&self) -> usize {
+        self.block_size.saturating_mul(self.threads)
+    }
+    fn write_block(&mut self) -> Result<()> {
+        if self.buffer.len() <= self.block_size {
+            if !self.buffer.is_empty() {
+                let block = compress_block(&self.buffer)?;
+                self.emit(&block, self.buffer.len())?;
+            }
+        } else {
+            let blocks: Vec<Result<Vec<u8>>> = std::thread::scope(|scope| {
+                let workers: Vec<_> = self
+                    .buffer
+                    .chunks(self.block_size)
+                    .map(|chunk| scope.spawn(move || compress_block(chunk)))
+                    .collect();
+                workers
+                    .into_iter()
+                    .map(|worker| worker.join().expect("Block compression panicked"))
+                    .collect()
+            });
+            let buffer = std::mem::take(&mut self.buffer);
+            for (block, chunk) in blocks.into_iter().zip(buffer.chunks(self.block_size)) {
+                self.emit(&block?, chunk.len())?;
+            }
+            self.buffer = buffer;
+        }
+        self.buffer.clear();
+        Ok(())
+    }
+    /// Encode the remaining data, write the end marker, the optional index
+    /// and the optional checksum. Returns the inner writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.write_block()?;
+        self.inner.write_all(&[0u8; 4])?;
+        if let Some(index) = self.index.as_ref() {
+            index.write_to(&mut self.inner)?;
+        }
+        if let Some(crc) = self.checksum {
+            self.inner.write_all(&crc.finalize().to_be_bytes())?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for BlockWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.batch_size() - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if let Some(crc) = self.checksum.as_mut() {
+            crc.update(&buf[..n]);
+        }
+        self.readbytes += n as u64;
+        if self.buffer.len() == self.batch_size() {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    /// Only flushes the inner writer. Buffered data is encoded as soon as a
+    /// block is full or on `finish`, since every flush would start a new block.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decodes the blocks written by a `BlockWriter`
+pub struct BlockReader<R: Read> {
+    inner: R,
+    block: Vec<u8>,
+    position: usize,
+    finished: bool,
+    /// Number of decoded blocks
+    pub blocks: u64,
+    pub writeout: u64,
+}
+
+impl<R: Read> BlockReader<R> {
+    pub fn new(reader: R) -> Self {
+        BlockReader {
+            inner: reader,
+            block: Vec::new(),
+            position: 0,
+            finished: false,
+            blocks: 0,
+            writeout: 0,
+        }
+    }
+    /// Returns the inner reader, positioned after the end marker once all
+    /// data has been read.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for BlockReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.block.len() && !self.finished {
+            self.block.clear();
+            self.position = 0;
+            if decompress_block(&mut self.inner, &mut self.block)? == 0 {
+                self.finished = true;
+            } else {
+                self.blocks += 1;
+            }
+        }
+        let n = buf.len().min(self.block.len() - self.position);
+        buf[..n].copy_from_slice(&self.block[self.position..self.position + n]);
+        self.position += n;
+        self.writeout += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::generate_random_byte_vector;
+    use std::io::Cursor;
+
+    /// Skewed text followed by uniformly distributed binary data
+    fn drifting_data() -> Vec<u8> {
+        let mut data = "aaaaaaaabbbbccd".repeat(4000).into_bytes();
+        data.extend((0..60_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8));
+        data
+    }
+
+    fn compress(data: &[u8], block_size: usize) -> Vec<u8> {
+        compress_with_threads(data, block_size, 1)
+    }
+
+    fn compress_with_threads(data: &[u8], block_size: usize, threads: usize) -> Vec<u8> {
+        let mut writer = BlockWriter::new(Vec::new(), block_size).unwrap();
+        writer.use_checksum(true);
+        writer.use_threads(threads);
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn decompress(encoded: &[u8]) -> Result<Vec<u8>> {
+        let mut reader = BlockReader::new(Cursor::new(encoded));
+        let mut result = Vec::new();
+        reader.read_to_end(&mut result)?;
+        Ok(result)
+    }
+
+    #[test]
+    fn roundtrip_block_sizes() {
+        let words: Vec<u8> = vec![20, 17, 6, 3, 2, 2, 2, 1, 1, 1];
+        let data = generate_random_byte_vector(0, words.len() as u8, 50_000, &words);
+        for &(block_size, count) in [(1, 300), (7, 5000), (4096, 50_000), (50_000, 50_000)].iter() {
+            let encoded = compress(&data[..count], block_size);
+            assert_eq!(decompress(&encoded).unwrap(), &data[..count]);
+        }
+        let encoded = compress(&data, DEFAULT_BLOCK_SIZE);
+        assert_eq!(decompress(&encoded).unwrap(), data);
+        assert_eq!(decompress(&compress(&[], 10)).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn separate_tables_for_drifting_data() {
+        let data = drifting_data();
+        let single = compress(&data, data.len());
+        let blocks = compress(&data, 30_000);
+        assert!(blocks.len() < single.len(), "{} >= {}", blocks.len(), single.len());
+        assert_eq!(decompress(&blocks).unwrap(), data);
+    }
+
+    #[test]
+    fn identical_output_for_all_thread_counts() {
+        let data = drifting_data();
+        for &block_size in [1000, 4096, 30_000, DEFAULT_BLOCK_SIZE].iter() {
+            let expected = compress(&data, block_size);
+            for &threads in [0, 2, 3, 8, usize::MAX].iter() {
+                assert_eq!(compress_with_threads(&data, block_size, threads), expected);
+            }
+        }
+        let encoded = compress_with_threads(&data, 1000, 4);
+        assert_eq!(decompress(&encoded).unwrap(), data);
+    }
+
+    #[test]
+    fn footer_follows_end_marker() {
+        let data = drifting_data();
+        let encoded = compress(&data, 10_000);
+        let mut reader = BlockReader::new(Cursor::new(&encoded));
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        assert_eq!(reader.writeout, data.len() as u64);
+        let mut footer = Vec::new();
+        reader.into_inner().read_to_end(&mut footer).unwrap();
+        assert_eq!(footer, crate::checksum::crc32(&data).to_be_bytes());
+    }
+
+    fn compress_with_index(data: &[u8], block_size: usize) -> Vec<u8> {
+        let mut writer = BlockWriter::new(Vec::new(), block_size).unwrap();
+        writer.use_index(true);
+        writer.use_threads(3);
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn parallel_decompression_with_index() {
+        let data = drifting_data();
+        let encoded = compress_with_index(&data, 7000);
+        let mut reader = Cursor::new(&encoded);
+        let index = BlockIndex::load(&mut reader, 0, 0).unwrap();
+        assert_eq!(index.entries.len(), data.len().div_ceil(7000));
+        assert_eq!(index.decoded_size(), data.len() as u64);
+        for &threads in [1, 2, 4, 16].iter() {
+            reader.set_position(0);
+            let mut output = Vec::new();
+            let crc = decompress_parallel(&mut reader, &mut output, &index, threads).unwrap();
+            assert_eq!(output, data);
+            assert_eq!(crc, crate::checksum::crc32(&data));
+        }
+        // The sequential reader skips the index
+        assert_eq!(decompress(&encoded).unwrap(), data);
+        let mut reader = BlockReader::new(Cursor::new(&encoded));
+        std::io::copy(&mut reader, &mut std::io::sink()).unwrap();
+        let blocks = reader.blocks;
+        let mut inner = reader.into_inner();
+        assert_eq!(BlockIndex::read_from(&mut inner, blocks).unwrap(), index);
+
+        let empty = compress_with_index(&[], 10);
+        let index = BlockIndex::load(&mut Cursor::new(&empty), 0, 0).unwrap();
+        assert!(index.entries.is_empty());
+    }
+
+    #[test]
+    fn corrupt_index() {
+        let data = drifting_data();
+        let encoded = compress_with_index(&data, 7000);
+        let load = |data: &[u8]| BlockIndex::load(&mut Cursor::new(data), 0, 0);
+        let mut count = encoded.clone();
+        let last = count.len() - 1;
+        count[last] += 1;
+        assert!(load(&count).is_err());
+        count[last - 7] = 0xFF;
+        assert!(load(&count).is_err());
+        assert!(load(&encoded[..4]).is_err());
+
+        let mut size = encoded.clone();
+        let first = size.len() - 8 - 8 * data.len().div_ceil(7000);
+        size[first + 3] ^= 0x01;
+        assert!(load(&size).is_err());
+
+        // Sizes are consistent, but the symbols do not match the blocks
+        let mut symbols = encoded.clone();
+        symbols[first + 7] ^= 0x01;
+        let mut reader = Cursor::new(&symbols);
+        let index = BlockIndex::load(&mut reader, 0, 0).unwrap();
+        reader.set_position(0);
+        let result = decompress_parallel(&mut reader, &mut Vec::new(), &index, 4);
+        assert!(matches!(result, Err(HuffmanError::CorruptHeader(_))));
+
+        // Entries no block can have are rejected before decoding
+        let fields = [(4, [0u8; 4]), (4, [0xFF; 4]), (0, [0u8; 4]), (0, [0xFF; 4])];
+        for &(offset, value) in fields.iter() {
+            let mut entry = encoded.clone();
+            entry[first + offset..first + offset + 4].copy_from_slice(&value);
+            assert!(matches!(load(&entry), Err(HuffmanError::CorruptHeader(_))));
+            let mut reader = Cursor::new(&entry[entry.len() - 8 - 8 * index.entries.len()..]);
+            let result = BlockIndex::read_from(&mut reader, index.entries.len() as u64);
+            assert!(matches!(result, Err(HuffmanError::CorruptHeader(_))));
+        }
+    }
+
+    #[test]
+    fn corrupt_blocks() {
+        let data = drifting_data();
+        let encoded = compress(&data, 10_000);
+        assert!(matches!(
+            decompress(&encoded[..encoded.len() / 2]),
+            Err(HuffmanError::TruncatedStream)
+        ));
+        // Missing end marker
+        assert!(matches!(
+            decompress(&encoded[..encoded.len() - 8]),
+            Err(HuffmanError::TruncatedStream)
+        ));
+        let mut huge = encoded.clone();
+        huge[0] = 0xFF;
+        assert!(matches!(decompress(&huge), Err(HuffmanError::CorruptHeader(_))));
+        assert!(validate_block_size(0).is_err());
+        assert!(BlockWriter::new(Vec::new(), MAX_BLOCK_SIZE + 1).is_err());
+    }
+}
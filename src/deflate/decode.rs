@@ -0,0 +1,650 @@
+//! Reader of DEFLATE streams, zlib streams and gzip members
+use super::{
+    fixed_distance_lengths, fixed_litlen_lengths, reverse_bits, CODE_LENGTH_ORDER, DISTANCE_CODES,
+    END_OF_BLOCK, LENGTH_BASE, LENGTH_EXTRA, LITLEN_CODES, MAX_CODE_LENGTH, WINDOW,
+};
+use crate::checksum::{Adler32, Crc32};
+use crate::error::{HuffmanError, Result};
+use crate::huffman::decode::table::DecodeTable;
+use crate::huffman::decode::validate_lookup;
+use crate::huffman::Huffman;
+use crate::model::Model;
+use crate::transform::lz77;
+use std::io::{ErrorKind, Read};
+
+/// Number of bytes decoded ahead of the caller
+const CHUNK: usize = 1 << 16;
+
+/// Flags of the gzip header
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+const RESERVED: u8 = 0xE0;
+
+/// Format around the DEFLATE stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    /// The DEFLATE stream alone (RFC 1951)
+    Raw,
+    /// 2 byte header and the Adler-32 of the data (RFC 1950)
+    Zlib,
+    /// gzip members with a header, the CRC-32 and the size of the data
+    /// (RFC 1952)
+    Gzip,
+}
+
+/// Bits read starting at the least significant bit of every byte
+struct BitReader<R: Read> {
+    inner: R,
+    buffer: u64,
+    count: usize,
+}
+
+impl<R: Read> BitReader<R> {
+    fn next_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+        loop {
+            match self.inner.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Move whole bytes from the inner reader into the buffer, until it
+    /// holds `bits` bits or the inner reader is exhausted
+    fn fill(&mut self, bits: usize) -> std::io::Result<()> {
+        while self.count < bits {
+            match self.next_byte()? {
+                Some(val) => {
+                    self.buffer |= (val as u64) << self.count;
+                    self.count += 8;
+                }
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    /// Read `count` bits, up to 32
+    fn read_bits(&mut self, count: usize) -> Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
+        self.fill(count)?;
+        if count > self.count {
+            return Err(HuffmanError::TruncatedStream);
+        }
+        let value = (self.buffer & ((1 << count) - 1)) as usize;
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        Ok(self.read_bits(8)? as u8)
+    }
+
+    /// Skip the bits up to the next full byte
+    fn align(&mut self) {
+        let padding = self.count % 8;
+        self.buffer >>= padding;
+        self.count -= padding;
+    }
+
+    /// Whether the inner reader is exhausted. Must be aligned.
+    fn at_end(&mut self) -> std::io::Result<bool> {
+        self.fill(8)?;
+        Ok(self.count == 0)
+    }
+
+    /// Decode a symbol. Codewords are stored starting with their most
+    /// significant bit, therefore the next bits are reversed for the lookup.
+    fn decode_symbol(&mut self, code: &Code) -> Result<usize> {
+        self.fill(code.sentinel)?;
+        let bits = (self.buffer & ((1 << code.sentinel) - 1)) as usize;
+        let (sym, length) = code
+            .table
+            .lookup(reverse_bits(bits, code.sentinel) as u64)?;
+        let length = length as usize;
+        if length > self.count {
+            return Err(HuffmanError::TruncatedStream);
+        }
+        self.buffer >>= length;
+        self.count -= length;
+        Ok(sym as usize)
+    }
+}
+
+/// Lookup table of a canonical code, built like the tables of `Huffman`
+#[derive(Clone)]
+struct Code {
+    table: DecodeTable,
+    sentinel: usize,
+}
+
+impl Code {
+    /// Build the table for the codeword lengths, `None` if no symbol has a
+    /// codeword. Only a single codeword may leave the code incomplete.
+    fn new(lengths: &[u8]) -> Result<Option<Self>> {
+        let used = lengths.iter().filter(|&&length| length > 0).count();
+        if used == 0 {
+            return Ok(None);
+        }
+        let model = Huffman::from_lengths(lengths)?;
+        let kraft: usize = lengths
+            .iter()
+            .filter(|&&length| length > 0)
+            .map(|&length| 1 << (MAX_CODE_LENGTH - length as usize))
+            .sum();
+        if kraft != 1 << MAX_CODE_LENGTH && used > 1 {
+            return Err(HuffmanError::InvalidCode(
+                "Codeword lengths are incomplete".to_string(),
+            ));
+        }
+        let bt = model.to_btreemap();
+        let sentinel = model.sentinel();
+        validate_lookup(&bt, sentinel)?;
+        Ok(Some(Code {
+            table: DecodeTable::new(&bt, sentinel)?,
+            sentinel,
+        }))
+    }
+}
+
+/// Literal/length and distance code of a block
+#[derive(Clone)]
+struct Codes {
+    litlen: Code,
+    /// Blocks without matches may omit the distance code
+    distance: Option<Code>,
+}
+
+impl Codes {
+    fn new(litlen: &[u8], distance: &[u8]) -> Result<Self> {
+        if litlen[END_OF_BLOCK] == 0 {
+            return Err(HuffmanError::InvalidCode(
+                "Missing end of block code".to_string(),
+            ));
+        }
+        Ok(Codes {
+            litlen: Code::new(litlen)?.expect("The end of block has a codeword"),
+            distance: Code::new(distance)?,
+        })
+    }
+}
+
+/// Read the code lengths of a dynamic block and build its codes
+fn read_dynamic_codes<R: Read>(bits: &mut BitReader<R>) -> Result<Codes> {
+    let hlit = bits.read_bits(5)? + 257;
+    let hdist = bits.read_bits(5)? + 1;
+    let hclen = bits.read_bits(4)? + 4;
+    if hlit > LITLEN_CODES || hdist > DISTANCE_CODES {
+        return Err(HuffmanError::InvalidCode(format!(
+            "{} literal/length and {} distance codes",
+            hlit, hdist
+        )));
+    }
+    let mut code_length_lengths = [0u8; 19];
+    for &sym in CODE_LENGTH_ORDER[..hclen].iter() {
+        code_length_lengths[sym] = bits.read_bits(3)? as u8;
+    }
+    let code_length = Code::new(&code_length_lengths)?
+        .ok_or_else(|| HuffmanError::InvalidCode("Empty code length code".to_string()))?;
+
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let (length, repeat) = match bits.decode_symbol(&code_length)? {
+            16 => {
+                let previous = *lengths.last().ok_or_else(|| {
+                    HuffmanError::InvalidCode("Repetition without a length".to_string())
+                })?;
+                (previous, 3 + bits.read_bits(2)?)
+            }
+            17 => (0, 3 + bits.read_bits(3)?),
+            18 => (0, 11 + bits.read_bits(7)?),
+            length => (length as u8, 1),
+        };
+        if lengths.len() + repeat > hlit + hdist {
+            return Err(HuffmanError::InvalidCode(format!(
+                "More than {} codeword lengths",
+                hlit + hdist
+            )));
+        }
+        lengths.extend(std::iter::repeat_n(length, repeat));
+    }
+    Codes::new(&lengths[..hlit], &lengths[hlit..])
+}
+
+/// Decode the symbols of a block into `output` until it holds `target`
+/// bytes. Returns whether the end of the block was reached.
+fn inflate_codes<R: Read>(
+    bits: &mut BitReader<R>,
+    codes: &Codes,
+    output: &mut Vec<u8>,
+    target: usize,
+) -> Result<bool> {
+    while output.len() < target {
+        let sym = bits.decode_symbol(&codes.litlen)?;
+        if sym < END_OF_BLOCK {
+            output.push(sym as u8);
+            continue;
+        }
+        if sym == END_OF_BLOCK {
+            return Ok(true);
+        }
+        let index = sym - END_OF_BLOCK - 1;
+        if index >= LENGTH_BASE.len() {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Invalid length code {}",
+                sym
+            )));
+        }
+        let length = LENGTH_BASE[index] as usize + bits.read_bits(LENGTH_EXTRA[index] as usize)?;
+        let code = match codes.distance {
+            Some(ref distance) => bits.decode_symbol(distance)?,
+            None => {
+                return Err(HuffmanError::InvalidCode(
+                    "Match without distance code".to_string(),
+                ))
+            }
+        };
+        if code >= DISTANCE_CODES {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Invalid distance code {}",
+                code
+            )));
+        }
+        let (base, extra) = lz77::slot_distance(code as u16);
+        let distance = base + bits.read_bits(extra)?;
+        if distance > output.len() {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Distance {} reaches before the start of the data",
+                distance
+            )));
+        }
+        let from = output.len() - distance;
+        for ix in from..from + length {
+            output.push(output[ix]);
+        }
+    }
+    Ok(false)
+}
+
+enum State {
+    /// Header of the container
+    Header,
+    /// Header of the next block or the trailer after the final block
+    Block,
+    /// Remaining bytes of a stored block
+    Stored(usize),
+    /// Codes of a block with fixed or dynamic codes
+    Codes(Box<Codes>),
+    Done,
+}
+
+/// Decodes a DEFLATE stream in a `Container`. Concatenated gzip members are
+/// decoded one after another.
+pub struct InflateReader<R: Read> {
+    bits: BitReader<R>,
+    container: Container,
+    state: State,
+    final_block: bool,
+    fixed: Option<Codes>,
+    /// Up to `WINDOW` bytes of history followed by the decoded bytes,
+    /// starting at `position`. The bytes before `checked` are part of the
+    /// checksum.
+    buffer: Vec<u8>,
+    position: usize,
+    checked: usize,
+    crc: Crc32,
+    adler: Adler32,
+    member_size: u64,
+    pub writeout: u64,
+}
+
+impl<R: Read> InflateReader<R> {
+    pub fn new(reader: R, container: Container) -> Self {
+        InflateReader {
+            bits: BitReader {
+                inner: reader,
+                buffer: 0,
+                count: 0,
+            },
+            container,
+            state: State::Header,
+            final_block: false,
+            fixed: None,
+            buffer: Vec::new(),
+            position: 0,
+            checked: 0,
+            crc: Crc32::new(),
+            adler: Adler32::new(),
+            member_size: 0,
+            writeout: 0,
+        }
+    }
+    /// Returns the inner reader. Bytes which were already moved into the
+    /// buffer are lost.
+    pub fn into_inner(self) -> R {
+        self.bits.inner
+    }
+
+    /// Decode until the buffer holds `target` bytes or the stream ends
+    fn inflate(&mut self, target: usize) -> Result<()> {
+        while self.buffer.len() < target {
+            match self.state {
+                State::Header => {
+                    self.read_header()?;
+                    self.state = State::Block;
+                }
+                State::Block if self.final_block => self.read_trailer()?,
+                State::Block => self.read_block_header()?,
+                State::Stored(remaining) => {
+                    let n = remaining.min(target - self.buffer.len());
+                    for _ in 0..n {
+                        let byte = self.bits.read_byte()?;
+                        self.buffer.push(byte);
+                    }
+                    self.state = if n == remaining {
+                        State::Block
+                    } else {
+                        State::Stored(remaining - n)
+                    };
+                }
+                State::Codes(ref codes) => {
+                    if inflate_codes(&mut self.bits, codes, &mut self.buffer, target)? {
+                        self.state = State::Block;
+                    }
+                }
+                State::Done => break,
+            }
+        }
+        self.update_checksum();
+        Ok(())
+    }
+
+    fn update_checksum(&mut self) {
+        let data = &self.buffer[self.checked..];
+        match self.container {
+            Container::Raw => {}
+            Container::Zlib => self.adler.update(data),
+            Container::Gzip => self.crc.update(data),
+        }
+        self.member_size += data.len() as u64;
+        self.checked = self.buffer.len();
+    }
+
+    fn read_header(&mut self) -> Result<()> {
+        match self.container {
+            Container::Raw => {}
+            Container::Zlib => {
+                let cmf = self.bits.read_byte()?;
+                let flg = self.bits.read_byte()?;
+                if cmf & 0x0F != 8
+                    || cmf >> 4 > 7
+                    || !(cmf as u16 * 256 + flg as u16).is_multiple_of(31)
+                {
+                    return Err(HuffmanError::CorruptHeader(format!(
+                        "Invalid zlib header {:02x} {:02x}",
+                        cmf, flg
+                    )));
+                }
+                if flg & 0x20 != 0 {
+                    return Err(HuffmanError::CorruptHeader(
+                        "Preset dictionaries are not supported".to_string(),
+                    ));
+                }
+            }
+            Container::Gzip => self.read_gzip_header()?,
+        }
+        Ok(())
+    }
+
+    fn read_gzip_header(&mut self) -> Result<()> {
+        let mut crc = Crc32::new();
+        let mut header = [0u8; 10];
+        for byte in header.iter_mut() {
+            *byte = self.bits.read_byte()?;
+        }
+        crc.update(&header);
+        if header[..2] != [0x1f, 0x8b] {
+            return Err(HuffmanError::BadMagic(header[..2].to_vec()));
+        }
+        if header[2] != 8 {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Unknown compression method {}",
+                header[2]
+            )));
+        }
+        let flags = header[3];
+        if flags & RESERVED != 0 {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Reserved flags {:#04x}",
+                flags
+            )));
+        }
+        let mut next = |bits: &mut BitReader<R>| -> Result<u8> {
+            let byte = bits.read_byte()?;
+            crc.update(&[byte]);
+            Ok(byte)
+        };
+        if flags & FEXTRA != 0 {
+            let length = next(&mut self.bits)? as usize | (next(&mut self.bits)? as usize) << 8;
+            for _ in 0..length {
+                next(&mut self.bits)?;
+            }
+        }
+        // Zero terminated file name and comment
+        for &flag in [FNAME, FCOMMENT].iter() {
+            if flags & flag != 0 {
+                while next(&mut self.bits)? != 0 {}
+            }
+        }
+        if flags & FHCRC != 0 {
+            let actual = crc.finalize() & 0xFFFF;
+            let expected = self.bits.read_bits(16)? as u32;
+            if expected != actual {
+                return Err(HuffmanError::ChecksumMismatch { expected, actual });
+            }
+        }
+        Ok(())
+    }
+
+    fn read_block_header(&mut self) -> Result<()> {
+        self.final_block = self.bits.read_bits(1)? == 1;
+        self.state = match self.bits.read_bits(2)? {
+            0 => {
+                self.bits.align();
+                let length = self.bits.read_bits(16)?;
+                let complement = self.bits.read_bits(16)?;
+                if length != !complement & 0xFFFF {
+                    return Err(HuffmanError::InvalidCode(format!(
+                        "Stored block length {:04x} does not match {:04x}",
+                        length, complement
+                    )));
+                }
+                State::Stored(length)
+            }
+            1 => {
+                if self.fixed.is_none() {
+                    self.fixed = Some(Codes::new(
+                        &fixed_litlen_lengths(),
+                        &fixed_distance_lengths(),
+                    )?);
+                }
+                State::Codes(Box::new(self.fixed.clone().expect("Built above")))
+            }
+            2 => State::Codes(Box::new(read_dynamic_codes(&mut self.bits)?)),
+            _ => return Err(HuffmanError::InvalidCode("Reserved block type".to_string())),
+        };
+        Ok(())
+    }
+
+    /// Verify the trailer after the final block. Another gzip member may
+    /// follow.
+    fn read_trailer(&mut self) -> Result<()> {
+        self.update_checksum();
+        self.bits.align();
+        self.state = State::Done;
+        match self.container {
+            Container::Raw => {}
+            Container::Zlib => {
+                let mut expected = 0u32;
+                for _ in 0..4 {
+                    expected = expected << 8 | self.bits.read_byte()? as u32;
+                }
+                let actual = self.adler.finalize();
+                if expected != actual {
+                    return Err(HuffmanError::ChecksumMismatch { expected, actual });
+                }
+            }
+            Container::Gzip => {
+                let expected = self.bits.read_bits(32)? as u32;
+                let actual = self.crc.finalize();
+                if expected != actual {
+                    return Err(HuffmanError::ChecksumMismatch { expected, actual });
+                }
+                // The size modulo 2^32 serves as a second checksum
+                let expected = self.bits.read_bits(32)? as u32;
+                let actual = self.member_size as u32;
+                if expected != actual {
+                    return Err(HuffmanError::ChecksumMismatch { expected, actual });
+                }
+                if !self.bits.at_end()? {
+                    self.state = State::Header;
+                    self.final_block = false;
+                    self.crc = Crc32::new();
+                    self.member_size = 0;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for InflateReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.buffer.len() && !matches!(self.state, State::Done) {
+            let history = self.buffer.len().saturating_sub(WINDOW);
+            self.buffer.drain(..history);
+            self.position = self.buffer.len();
+            self.checked = self.buffer.len();
+            self.inflate(self.position + CHUNK)?;
+        }
+        let n = buf.len().min(self.buffer.len() - self.position);
+        buf[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        self.writeout += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::{DeflateWriter, GzipWriter};
+    use std::io::Write;
+
+    /// Pseudo-random bytes with `bits` significant bits
+    fn noise(count: usize, bits: u32) -> Vec<u8> {
+        (0..count as u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761).rotate_left(7) >> (32 - bits)) as u8)
+            .collect()
+    }
+
+    fn inflate(encoded: &[u8], container: Container) -> Result<Vec<u8>> {
+        let mut reader = InflateReader::new(encoded, container);
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded)?;
+        assert_eq!(reader.writeout, decoded.len() as u64);
+        Ok(decoded)
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut writer = GzipWriter::new(Vec::new()).unwrap();
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn roundtrips() {
+        let text = b"It was the best of times, it was the worst of times, ".repeat(5000);
+        let mut mixed = noise(200_000, 4);
+        mixed.extend_from_slice(&noise(300_000, 8));
+        mixed.extend_from_within(1000..50_000);
+        for data in [&b""[..], b"a", &text, &mixed].iter() {
+            assert_eq!(&inflate(&gzip(data), Container::Gzip).unwrap(), data);
+            let mut writer = DeflateWriter::new(Vec::new());
+            writer.write_all(data).unwrap();
+            let encoded = writer.finish().unwrap();
+            assert_eq!(&inflate(&encoded, Container::Raw).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn foreign_streams() {
+        // zlib.compress(b"hello, hello, hello!", 9)
+        let zlib = [
+            0x78, 0xda, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xc8, 0x40, 0xa2, 0x14, 0x01,
+            0x4b, 0x1e, 0x06, 0xf6,
+        ];
+        assert_eq!(
+            inflate(&zlib, Container::Zlib).unwrap(),
+            b"hello, hello, hello!"
+        );
+        let mut corrupt = zlib;
+        corrupt[17] ^= 1;
+        assert!(matches!(
+            inflate(&corrupt, Container::Zlib),
+            Err(HuffmanError::ChecksumMismatch { .. })
+        ));
+        // Raw DEFLATE of b"abc" with a fixed code
+        assert_eq!(
+            inflate(&[0x4b, 0x4c, 0x4a, 0x06, 0x00], Container::Raw).unwrap(),
+            b"abc"
+        );
+        // Stored block with the name "a" in the gzip header
+        let member = [
+            0x1f, 0x8b, 8, FNAME, 0, 0, 0, 0, 0, 3, b'a', 0, 1, 2, 0, 0xfd, 0xff, b'h', b'i', 0xac,
+            0x2a, 0x93, 0xd8, 2, 0, 0, 0,
+        ];
+        assert_eq!(inflate(&member, Container::Gzip).unwrap(), b"hi");
+    }
+
+    #[test]
+    fn concatenated_members() {
+        let mut encoded = gzip(b"first ");
+        encoded.extend_from_slice(&gzip(b"second"));
+        assert_eq!(inflate(&encoded, Container::Gzip).unwrap(), b"first second");
+        encoded.push(0x1f);
+        assert!(inflate(&encoded, Container::Gzip).is_err());
+    }
+
+    #[test]
+    fn corrupt_streams() {
+        let data = b"abracadabra, abracadabra!".repeat(40);
+        let encoded = gzip(&data);
+        for ix in 0..encoded.len() {
+            for &flip in [0x01u8, 0x80].iter() {
+                let mut corrupt = encoded.clone();
+                corrupt[ix] ^= flip;
+                // Must not panic, the output may still be wrong
+                let _ = inflate(&corrupt, Container::Gzip);
+            }
+        }
+        let mut corrupt = encoded.clone();
+        corrupt[encoded.len() - 8] ^= 1;
+        assert!(inflate(&corrupt, Container::Gzip).is_err());
+        let mut corrupt = encoded.clone();
+        corrupt[encoded.len() - 1] ^= 1;
+        assert!(inflate(&corrupt, Container::Gzip).is_err());
+        assert!(inflate(&encoded[..encoded.len() - 1], Container::Gzip).is_err());
+        assert!(inflate(&encoded, Container::Zlib).is_err());
+        // Reserved block type
+        assert!(inflate(&[0x07], Container::Raw).is_err());
+    }
+}
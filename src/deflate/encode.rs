@@ -0,0 +1,566 @@
+//! Writers of DEFLATE streams and gzip members
+use super::{
+    distance_code, fixed_distance_lengths, fixed_litlen_lengths, length_code, reverse_bits,
+    CODE_LENGTH_ORDER, DISTANCE_CODES, END_OF_BLOCK, LENGTH_EXTRA, LITLEN_CODES, MAX_CODE_LENGTH,
+    MAX_CODE_LENGTH_CODE_LENGTH, MAX_STORED, WINDOW,
+};
+use crate::checksum::Crc32;
+use crate::error::Result;
+use crate::huffman::header::model_lengths;
+use crate::huffman::Huffman;
+use crate::model::Model;
+use crate::transform::lz77::{self, MatchFinder, Token};
+use log::debug;
+use std::io::Write;
+
+/// Number of bytes per block
+pub const BLOCK_SIZE: usize = 1 << 17;
+
+/// Header of a gzip member: magic, compression method 8 (DEFLATE), no
+/// flags, no modification time, no extra flags and an unknown OS
+const GZIP_HEADER: [u8; 10] = [0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+
+/// Block types of the 2 bit `BTYPE` field
+const STORED: usize = 0;
+const FIXED: usize = 1;
+const DYNAMIC: usize = 2;
+
+/// Bits packed starting at the least significant bit of every byte
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: usize,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        BitWriter {
+            bytes: Vec::new(),
+            buffer: 0,
+            count: 0,
+        }
+    }
+
+    /// Append the lowest `count` bits of `value`, `count` must not exceed 32
+    fn write_bits(&mut self, value: usize, count: usize) {
+        debug_assert!(count <= 32 && value >> count == 0);
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Append a Huffman code, most significant bit first
+    fn write_symbol(&mut self, model: &Huffman, sym: usize) {
+        let (code, length) = model.encode(sym);
+        self.write_bits(reverse_bits(code, length), length);
+    }
+
+    /// Pad with zeros up to the next full byte
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write_bits(0, 8 - self.count);
+        }
+    }
+}
+
+/// Give at least two symbols a codeword. Decoders like `gzip` reject codes
+/// with a single codeword.
+fn ensure_two_codes(histogram: &mut [usize]) {
+    for ix in 0..2 {
+        if histogram.iter().filter(|&&count| count > 0).count() < 2 && histogram[ix] == 0 {
+            histogram[ix] = 1;
+        }
+    }
+}
+
+/// Number of extra bits of the distance code `code`
+fn distance_extra(code: usize) -> usize {
+    lz77::slot_distance(code as u16).1
+}
+
+/// Bits needed for the tokens of a block, including the end of block, coded
+/// with the codeword lengths `litlen` and `distance`
+fn data_cost(
+    litlen_histogram: &[usize],
+    distance_histogram: &[usize],
+    litlen: &[u8],
+    distance: &[u8],
+) -> usize {
+    let codes: usize = litlen_histogram
+        .iter()
+        .zip(litlen.iter())
+        .chain(distance_histogram.iter().zip(distance.iter()))
+        .map(|(&count, &length)| count * length as usize)
+        .sum();
+    let length_extra: usize = litlen_histogram[END_OF_BLOCK + 1..]
+        .iter()
+        .zip(LENGTH_EXTRA.iter())
+        .map(|(&count, &extra)| count * extra as usize)
+        .sum();
+    let distance_extra: usize = distance_histogram
+        .iter()
+        .enumerate()
+        .map(|(code, &count)| count * distance_extra(code))
+        .sum();
+    codes + length_extra + distance_extra
+}
+
+/// Codes and header of a block with dynamic Huffman codes
+struct DynamicCodes {
+    litlen: Huffman,
+    distance: Huffman,
+    /// Number of literal/length and distance codeword lengths in the header
+    hlit: usize,
+    hdist: usize,
+    /// Code for the code lengths and the number of its codeword lengths in
+    /// the header
+    code_length: Huffman,
+    hclen: usize,
+    /// Run-length coded codeword lengths: symbol and value of its extra bits
+    runs: Vec<(usize, usize)>,
+}
+
+impl DynamicCodes {
+    fn new(litlen_histogram: &[usize], distance_histogram: &[usize]) -> Result<Self> {
+        let mut histogram = litlen_histogram.to_vec();
+        ensure_two_codes(&mut histogram);
+        let litlen = Huffman::from_histogram(&histogram, MAX_CODE_LENGTH)?;
+        let mut histogram = distance_histogram.to_vec();
+        ensure_two_codes(&mut histogram);
+        let distance = Huffman::from_histogram(&histogram, MAX_CODE_LENGTH)?;
+
+        let litlen_lengths = model_lengths(&litlen);
+        let distance_lengths = model_lengths(&distance);
+        let used = |lengths: &[u8], min: usize| {
+            lengths
+                .iter()
+                .rposition(|&l| l > 0)
+                .map_or(0, |ix| ix + 1)
+                .max(min)
+        };
+        let hlit = used(&litlen_lengths, END_OF_BLOCK + 1);
+        let hdist = used(&distance_lengths, 1);
+        let mut lengths = litlen_lengths[..hlit].to_vec();
+        lengths.extend_from_slice(&distance_lengths[..hdist]);
+        let runs = encode_runs(&lengths);
+
+        let mut histogram = [0usize; 19];
+        for &(sym, _) in runs.iter() {
+            histogram[sym] += 1;
+        }
+        ensure_two_codes(&mut histogram);
+        let code_length = Huffman::from_histogram(&histogram, MAX_CODE_LENGTH_CODE_LENGTH)?;
+        let code_length_lengths = model_lengths(&code_length);
+        let hclen = used(
+            &CODE_LENGTH_ORDER
+                .iter()
+                .map(|&sym| code_length_lengths[sym])
+                .collect::<Vec<u8>>(),
+            4,
+        );
+        Ok(DynamicCodes {
+            litlen,
+            distance,
+            hlit,
+            hdist,
+            code_length,
+            hclen,
+            runs,
+        })
+    }
+
+    /// Bits needed for the header after `BTYPE`
+    fn header_cost(&self) -> usize {
+        let runs: usize = self
+            .runs
+            .iter()
+            .map(|&(sym, _)| self.code_length.encode(sym).1 + run_extra(sym))
+            .sum();
+        5 + 5 + 4 + 3 * self.hclen + runs
+    }
+
+    fn write_header(&self, bits: &mut BitWriter) {
+        bits.write_bits(self.hlit - 257, 5);
+        bits.write_bits(self.hdist - 1, 5);
+        bits.write_bits(self.hclen - 4, 4);
+        for &sym in CODE_LENGTH_ORDER[..self.hclen].iter() {
+            bits.write_bits(self.code_length.encode(sym).1, 3);
+        }
+        for &(sym, value) in self.runs.iter() {
+            bits.write_symbol(&self.code_length, sym);
+            bits.write_bits(value, run_extra(sym));
+        }
+    }
+}
+
+/// Number of extra bits of the code length symbol `sym`
+fn run_extra(sym: usize) -> usize {
+    match sym {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// Run-length code the codeword lengths with the symbols `0..=15` for a
+/// single length, `16` for 3 to 6 repetitions of the previous length and
+/// `17`/`18` for 3 to 10/11 to 138 zeros
+fn encode_runs(lengths: &[u8]) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut ix = 0;
+    while ix < lengths.len() {
+        let length = lengths[ix];
+        let run = lengths[ix..].iter().take_while(|&&l| l == length).count();
+        let mut remaining = run;
+        if length == 0 {
+            while remaining >= 11 {
+                let count = remaining.min(138);
+                runs.push((18, count - 11));
+                remaining -= count;
+            }
+            if remaining >= 3 {
+                runs.push((17, remaining - 3));
+                remaining = 0;
+            }
+        } else {
+            runs.push((length as usize, 0));
+            remaining -= 1;
+            while remaining >= 3 {
+                let count = remaining.min(6);
+                runs.push((16, count - 3));
+                remaining -= count;
+            }
+        }
+        runs.extend(std::iter::repeat_n((length as usize, 0), remaining));
+        ix += run;
+    }
+    runs
+}
+
+/// Compresses everything written into a raw DEFLATE stream in blocks of
+/// `BLOCK_SIZE` bytes. `finish` must be called to write the final block.
+pub struct DeflateWriter<W: Write> {
+    inner: W,
+    bits: BitWriter,
+    /// Up to `WINDOW` bytes of history followed by the bytes of the next
+    /// block, starting at `start`
+    buffer: Vec<u8>,
+    start: usize,
+    finder: MatchFinder,
+    fixed_litlen: Huffman,
+    fixed_distance: Huffman,
+    pub readbytes: u64,
+}
+
+impl<W: Write> DeflateWriter<W> {
+    pub fn new(writer: W) -> Self {
+        DeflateWriter {
+            inner: writer,
+            bits: BitWriter::new(),
+            buffer: Vec::new(),
+            start: 0,
+            finder: MatchFinder::new(WINDOW),
+            fixed_litlen: Huffman::from_lengths(&fixed_litlen_lengths())
+                .expect("The fixed code is a prefix code"),
+            fixed_distance: Huffman::from_lengths(&fixed_distance_lengths())
+                .expect("The fixed code is a prefix code"),
+            readbytes: 0,
+        }
+    }
+
+    /// Encode the pending bytes as the cheapest of a stored, fixed or dynamic
+    /// block and pass all complete bytes to the inner writer
+    fn write_block(&mut self, last: bool) -> Result<()> {
+        let data = &self.buffer[self.start..];
+        let tokens = self.finder.tokenize(&self.buffer, self.start);
+        let mut litlen_histogram = [0usize; LITLEN_CODES];
+        let mut distance_histogram = [0usize; DISTANCE_CODES];
+        for token in tokens.iter() {
+            match *token {
+                Token::Literal(byte) => litlen_histogram[byte as usize] += 1,
+                Token::Match { length, distance } => {
+                    litlen_histogram[length_code(length).0] += 1;
+                    distance_histogram[distance_code(distance).0] += 1;
+                }
+            }
+        }
+        litlen_histogram[END_OF_BLOCK] += 1;
+
+        let dynamic = DynamicCodes::new(&litlen_histogram, &distance_histogram)?;
+        let dynamic_cost = dynamic.header_cost()
+            + data_cost(
+                &litlen_histogram,
+                &distance_histogram,
+                &model_lengths(&dynamic.litlen),
+                &model_lengths(&dynamic.distance),
+            );
+        let fixed_cost = data_cost(
+            &litlen_histogram,
+            &distance_histogram,
+            &fixed_litlen_lengths(),
+            &fixed_distance_lengths(),
+        );
+        // Every chunk has a 3 bit header, is aligned and has a 4 byte length
+        let chunks = data.len().div_ceil(MAX_STORED).max(1);
+        let first_padding = (8 - (self.bits.count + 3) % 8) % 8;
+        let stored_cost = chunks * (3 + 32) + first_padding + (chunks - 1) * 5 + 8 * data.len();
+
+        let kind = if stored_cost < fixed_cost.min(dynamic_cost) {
+            STORED
+        } else if fixed_cost <= dynamic_cost {
+            FIXED
+        } else {
+            DYNAMIC
+        };
+        debug!(
+            "DEFLATE block of {} bytes, {} tokens: stored {} bits, fixed {} bits, dynamic {} bits",
+            data.len(),
+            tokens.len(),
+            stored_cost,
+            fixed_cost,
+            dynamic_cost
+        );
+
+        let bits = &mut self.bits;
+        match kind {
+            STORED => {
+                // An empty block still needs a single empty chunk
+                let chunks: Vec<&[u8]> = if data.is_empty() {
+                    vec![data]
+                } else {
+                    data.chunks(MAX_STORED).collect()
+                };
+                for (ix, chunk) in chunks.iter().enumerate() {
+                    let final_chunk = last && ix + 1 == chunks.len();
+                    bits.write_bits(final_chunk as usize, 1);
+                    bits.write_bits(STORED, 2);
+                    bits.align();
+                    bits.write_bits(chunk.len(), 16);
+                    bits.write_bits(!chunk.len() & 0xFFFF, 16);
+                    bits.bytes.extend_from_slice(chunk);
+                }
+            }
+            _ => {
+                bits.write_bits(last as usize, 1);
+                bits.write_bits(kind, 2);
+                let (litlen, distance) = if kind == FIXED {
+                    (&self.fixed_litlen, &self.fixed_distance)
+                } else {
+                    dynamic.write_header(bits);
+                    (&dynamic.litlen, &dynamic.distance)
+                };
+                for token in tokens.iter() {
+                    match *token {
+                        Token::Literal(byte) => bits.write_symbol(litlen, byte as usize),
+                        Token::Match {
+                            length,
+                            distance: d,
+                        } => {
+                            let (code, extra, value) = length_code(length);
+                            bits.write_symbol(litlen, code);
+                            bits.write_bits(value, extra as usize);
+                            let (code, extra, value) = distance_code(d);
+                            bits.write_symbol(distance, code);
+                            bits.write_bits(value, extra as usize);
+                        }
+                    }
+                }
+                bits.write_symbol(litlen, END_OF_BLOCK);
+            }
+        }
+        if last {
+            bits.align();
+        }
+        self.inner.write_all(&bits.bytes)?;
+        bits.bytes.clear();
+
+        let history = self.buffer.len().saturating_sub(WINDOW);
+        self.buffer.drain(..history);
+        self.finder.slide(history);
+        self.start = self.buffer.len();
+        Ok(())
+    }
+
+    /// Write the final block. Returns the flushed writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.write_block(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for DeflateWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // The block is only written once more data arrives, since the final
+        // block has to be marked
+        if self.buffer.len() == self.start + BLOCK_SIZE && !buf.is_empty() {
+            self.write_block(false)?;
+        }
+        let n = buf.len().min(self.start + BLOCK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        self.readbytes += n as u64;
+        Ok(n)
+    }
+
+    /// Only flushes the inner writer, since every flush would end a block
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compresses everything written into a single gzip member. `finish` must be
+/// called to write the final block and the trailer.
+pub struct GzipWriter<W: Write> {
+    inner: DeflateWriter<W>,
+    checksum: Crc32,
+    pub readbytes: u64,
+}
+
+impl<W: Write> GzipWriter<W> {
+    /// Write the gzip header to `writer`
+    pub fn new(mut writer: W) -> Result<Self> {
+        writer.write_all(&GZIP_HEADER)?;
+        Ok(GzipWriter {
+            inner: DeflateWriter::new(writer),
+            checksum: Crc32::new(),
+            readbytes: 0,
+        })
+    }
+
+    /// Write the final block, the CRC-32 and the size modulo `2^32` of the
+    /// data (little endian). Returns the flushed writer.
+    pub fn finish(self) -> Result<W> {
+        let size = self.readbytes as u32;
+        let mut writer = self.inner.finish()?;
+        writer.write_all(&self.checksum.finalize().to_le_bytes())?;
+        writer.write_all(&size.to_le_bytes())?;
+        writer.flush()?;
+        Ok(writer)
+    }
+}
+
+impl<W: Write> Write for GzipWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.checksum.update(&buf[..n]);
+        self.readbytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::process::{Command, Stdio};
+
+    /// Pseudo-random bytes with `bits` significant bits
+    fn noise(count: usize, bits: u32) -> Vec<u8> {
+        (0..count as u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761).rotate_left(7) >> (32 - bits)) as u8)
+            .collect()
+    }
+
+    fn compress(data: &[u8]) -> Vec<u8> {
+        let mut writer = GzipWriter::new(Vec::new()).unwrap();
+        writer.write_all(data).unwrap();
+        assert_eq!(writer.readbytes, data.len() as u64);
+        writer.finish().unwrap()
+    }
+
+    /// Decompress with the system `gzip`, `None` if it is not installed
+    fn gunzip(encoded: &[u8]) -> Option<Vec<u8>> {
+        let mut child = Command::new("gzip")
+            .arg("-dc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let input = encoded.to_vec();
+        let feeder = std::thread::spawn(move || stdin.write_all(&input));
+        let mut decoded = Vec::new();
+        child
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_end(&mut decoded)
+            .unwrap();
+        feeder.join().unwrap().unwrap();
+        assert!(child.wait().unwrap().success(), "gzip rejected the stream");
+        Some(decoded)
+    }
+
+    #[test]
+    fn code_length_runs() {
+        let mut lengths = vec![8u8; 10];
+        lengths.extend_from_slice(&[0; 150]);
+        lengths.extend_from_slice(&[5, 5, 0, 0, 0, 7]);
+        assert_eq!(
+            encode_runs(&lengths),
+            [
+                (8, 0),
+                (16, 3),
+                (16, 0),
+                (18, 127),
+                (18, 1),
+                (5, 0),
+                (5, 0),
+                (17, 0),
+                (7, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn block_types() {
+        // Only the final empty block
+        let encoded = compress(b"");
+        assert_eq!(encoded.len(), 10 + 2 + 8);
+        // Random data is stored with a few bytes per chunk
+        let data = noise(3 * MAX_STORED, 8);
+        let encoded = compress(&data);
+        assert!(
+            encoded.len() <= data.len() + 20 + 4 * 5,
+            "{}",
+            encoded.len()
+        );
+        // A short text is cheaper with the fixed code
+        let mut writer = DeflateWriter::new(Vec::new());
+        writer.write_all(b"abcabcabc").unwrap();
+        assert_eq!(writer.finish().unwrap()[0] & 0b111, 0b011);
+        // Longer text with a skewed distribution gets a dynamic code
+        let mut writer = DeflateWriter::new(Vec::new());
+        writer.write_all(&noise(10_000, 3)).unwrap();
+        assert_eq!(writer.finish().unwrap()[0] & 0b111, 0b101);
+    }
+
+    #[test]
+    fn gzip_roundtrips() {
+        let text = b"It was the best of times, it was the worst of times, ".repeat(5000);
+        let mut mixed = noise(BLOCK_SIZE + 1000, 4);
+        mixed.extend_from_slice(&noise(2 * BLOCK_SIZE, 8));
+        mixed.extend_from_within(1000..50_000);
+        for data in [&b""[..], b"a", b"aaaaaaaaaaaaaaaa", &text, &mixed].iter() {
+            let encoded = compress(data);
+            assert_eq!(&encoded[..3], &[0x1f, 0x8b, 8]);
+            assert_eq!(
+                encoded[encoded.len() - 4..],
+                (data.len() as u32).to_le_bytes()
+            );
+            match gunzip(&encoded) {
+                Some(decoded) => assert_eq!(&decoded, data),
+                None => return,
+            }
+        }
+    }
+}
@@ -0,0 +1,143 @@
+//! DEFLATE (RFC 1951), zlib (RFC 1950) and gzip (RFC 1952) streams
+//!
+//! The `DeflateWriter` splits the data into blocks, finds matches with the
+//! LZ77 match finder of `transform::lz77` and emits every block in the
+//! cheapest of the three block types:
+//! - stored: the raw bytes, in chunks of up to 65535 bytes
+//! - fixed: the literals, lengths and distances coded with the fixed code
+//!   of the RFC
+//! - dynamic: canonical Huffman codes of at most `MAX_CODE_LENGTH` bits for
+//!   the block, built via `Huffman::from_histogram` with package-merge. The
+//!   code lengths are run-length coded and coded with a third canonical code.
+//!
+//! The `GzipWriter` wraps the DEFLATE stream into the gzip member format,
+//! i.e. a header, the CRC-32 and the size of the original data.
+//!
+//! The `InflateReader` decodes raw DEFLATE streams, zlib streams and gzip
+//! members. Its literal/length and distance tables are built from the
+//! codeword lengths like the tables of `Huffman`, see
+//! `huffman::decode::table`.
+//!
+//! Unlike the `Encoder` of this crate, DEFLATE packs the bits starting at the
+//! least significant bit of every byte. Huffman codes are packed starting
+//! with their most significant bit, i.e. reversed.
+pub mod decode;
+pub mod encode;
+
+pub use decode::{Container, InflateReader};
+pub use encode::{DeflateWriter, GzipWriter};
+
+use crate::transform::lz77;
+
+/// Longest codeword of the literal/length and distance codes
+pub const MAX_CODE_LENGTH: usize = 15;
+
+/// Longest codeword of the code for the code lengths
+pub const MAX_CODE_LENGTH_CODE_LENGTH: usize = 7;
+
+/// Largest distance of a match
+pub const WINDOW: usize = 32 * 1024;
+
+/// Symbol ending every compressed block
+pub const END_OF_BLOCK: usize = 256;
+
+/// Literals, end of block and the length codes `257..=285`
+pub const LITLEN_CODES: usize = 286;
+
+/// Distance codes `0..=29`
+pub const DISTANCE_CODES: usize = 30;
+
+/// Largest number of bytes in a stored block
+pub const MAX_STORED: usize = u16::MAX as usize;
+
+/// Smallest length of the length codes `257..=285`
+pub const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+/// Number of extra bits of the length codes `257..=285`
+pub const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Order of the code lengths of the code length code in the block header
+pub const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// Length code of `length`, its number of extra bits and their value
+pub fn length_code(length: usize) -> (usize, u8, usize) {
+    let index = if length == 258 {
+        28
+    } else {
+        LENGTH_BASE[..28].partition_point(|&base| base as usize <= length) - 1
+    };
+    (
+        257 + index,
+        LENGTH_EXTRA[index],
+        length - LENGTH_BASE[index] as usize,
+    )
+}
+
+/// Distance code of `distance`, its number of extra bits and their value.
+/// The distance codes of DEFLATE are the distance slots of `lz77`.
+pub fn distance_code(distance: usize) -> (usize, u8, usize) {
+    let (slot, extra, value) = lz77::distance_slot(distance);
+    (slot as usize, extra as u8, value)
+}
+
+/// Code lengths of the fixed literal/length code, including the unused
+/// codes 286 and 287
+pub fn fixed_litlen_lengths() -> [u8; 288] {
+    let mut lengths = [8u8; 288];
+    lengths[144..256].iter_mut().for_each(|l| *l = 9);
+    lengths[256..280].iter_mut().for_each(|l| *l = 7);
+    lengths
+}
+
+/// Code lengths of the fixed distance code, including the unused codes 30
+/// and 31
+pub fn fixed_distance_lengths() -> [u8; 32] {
+    [5u8; 32]
+}
+
+/// Reverse the lowest `length` bits of `code`
+pub fn reverse_bits(code: usize, length: usize) -> usize {
+    if length == 0 {
+        return 0;
+    }
+    code.reverse_bits() >> (usize::BITS as usize - length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman::Huffman;
+    use crate::model::Model;
+
+    #[test]
+    fn length_and_distance_codes() {
+        assert_eq!(length_code(3), (257, 0, 0));
+        assert_eq!(length_code(10), (264, 0, 0));
+        assert_eq!(length_code(12), (265, 1, 1));
+        assert_eq!(length_code(257), (284, 5, 30));
+        assert_eq!(length_code(258), (285, 0, 0));
+        assert_eq!(distance_code(1), (0, 0, 0));
+        assert_eq!(distance_code(6), (4, 1, 1));
+        assert_eq!(distance_code(24_577), (29, 13, 0));
+        assert_eq!(distance_code(WINDOW), (29, 13, 8191));
+    }
+
+    #[test]
+    fn fixed_codes() {
+        // RFC 1951, section 3.2.6
+        let litlen = Huffman::from_lengths(&fixed_litlen_lengths()).unwrap();
+        assert_eq!(litlen.encode(0), (0b0011_0000, 8));
+        assert_eq!(litlen.encode(144), (0b1_1001_0000, 9));
+        assert_eq!(litlen.encode(256), (0, 7));
+        assert_eq!(litlen.encode(280), (0b1100_0000, 8));
+        assert_eq!(reverse_bits(0b110, 3), 0b011);
+        assert_eq!(reverse_bits(1, 1), 1);
+    }
+}
@@ -0,0 +1,111 @@
+//! Error type for all fallible operations of this crate.
+//!
+//! The library is meant to be embedded into long-running processes. Therefore
+//! no input file, corrupted or not, may panic. Every public function dealing
+//! with external data returns a `Result` with a `HuffmanError` instead.
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum HuffmanError {
+    /// Reading from the source or writing to the destination failed
+    Io(io::Error),
+    /// The header could not be parsed
+    CorruptHeader(String),
+    /// The input does not start with the expected magic bytes
+    BadMagic(Vec<u8>),
+    /// The input ended before all announced symbols could be decoded
+    TruncatedStream,
+    /// The code table does not describe a valid prefix code
+    InvalidCode(String),
+    /// The file was written with an unknown version of the format
+    UnsupportedVersion(u8),
+    /// The file uses optional features unknown to this version
+    UnsupportedFlags(u8),
+    /// The stored checksum does not match the data
+    ChecksumMismatch { expected: u32, actual: u32 },
+}
+
+pub type Result<T> = std::result::Result<T, HuffmanError>;
+
+impl fmt::Display for HuffmanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HuffmanError::Io(err) => write!(f, "I/O error: {}", err),
+            HuffmanError::CorruptHeader(msg) => write!(f, "Corrupt header: {}", msg),
+            HuffmanError::BadMagic(magic) => write!(f, "Bad magic bytes: {:?}", magic),
+            HuffmanError::TruncatedStream => write!(f, "Truncated stream"),
+            HuffmanError::InvalidCode(msg) => write!(f, "Invalid code: {}", msg),
+            HuffmanError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported format version {}", version)
+            }
+            HuffmanError::UnsupportedFlags(flags) => write!(f, "Unsupported flags {:#010b}", flags),
+            HuffmanError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Checksum mismatch: expected {:08x}, got {:08x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for HuffmanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HuffmanError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for HuffmanError {
+    /// Unwraps errors which were wrapped into an `io::Error` by one of the
+    /// `Read` or `Write` implementations of this crate.
+    fn from(err: io::Error) -> Self {
+        if !matches!(err.get_ref(), Some(inner) if inner.is::<HuffmanError>()) {
+            return HuffmanError::Io(err);
+        }
+        let kind = err.kind();
+        match err.into_inner().map(|inner| inner.downcast::<HuffmanError>()) {
+            Some(Ok(inner)) => *inner,
+            Some(Err(inner)) => HuffmanError::Io(io::Error::new(kind, inner)),
+            None => HuffmanError::Io(kind.into()),
+        }
+    }
+}
+
+impl From<HuffmanError> for io::Error {
+    /// Needed for the `Read` implementations of the decoders.
+    fn from(err: HuffmanError) -> Self {
+        match err {
+            HuffmanError::Io(err) => err,
+            HuffmanError::TruncatedStream => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            _ => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_error_roundtrip() {
+        let err: io::Error = HuffmanError::InvalidCode("test".to_string()).into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        match HuffmanError::from(err) {
+            HuffmanError::InvalidCode(msg) => assert_eq!(msg, "test"),
+            other => panic!("Unexpected error {:?}", other),
+        }
+
+        let err: io::Error = HuffmanError::TruncatedStream.into();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(matches!(
+            HuffmanError::from(err),
+            HuffmanError::TruncatedStream
+        ));
+
+        let err = io::Error::new(io::ErrorKind::NotFound, "missing");
+        assert!(matches!(HuffmanError::from(err), HuffmanError::Io(_)));
+    }
+}
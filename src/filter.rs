@@ -0,0 +1,363 @@
+//! Reversible filters applied to the data before compression
+//!
+//! A filter transforms the data such that the histogram becomes more skewed.
+//! The `FilterReader` applies a filter to the input of the encoder, the
+//! `UnfilterWriter` reverts it on the output of the decoder. The filter is
+//! recorded in the header, see `huffman::header`.
+//!
+//! # Delta filter
+//! The data is split into elements of `width` bytes, each replaced by its
+//! difference to the previous element (wrapping). The first element is
+//! stored unchanged. Trailing bytes which do not form a whole element are
+//! not filtered. Sampled signals and sorted or slowly changing numbers are
+//! close to their predecessor, therefore the differences are small.
+//!
+//! # Binary layout
+//! - 1 byte filter ID, `1` for the delta filter
+//! - Delta filter: 1 byte element width, the MSB is set for big endian
+//!
+//! Data without a filter has no filter descriptor at all.
+use crate::error::{HuffmanError, Result};
+use crate::BUF;
+use serde::Serialize;
+use std::fmt;
+use std::io::{ErrorKind, Read, Write};
+
+/// Filter ID of the delta filter
+pub(crate) const DELTA: u8 = 1;
+
+/// Marks big endian elements in the descriptor of the delta filter
+const BIG_ENDIAN: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum Filter {
+    #[default]
+    None,
+    /// Differences of neighboring elements of `width` bytes
+    Delta { width: u8, big_endian: bool },
+}
+
+impl Filter {
+    /// Delta filter for elements of 1, 2, 4 or 8 bytes
+    pub fn delta(width: u8, big_endian: bool) -> Result<Self> {
+        if ![1, 2, 4, 8].contains(&width) {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Unsupported element width {}",
+                width
+            )));
+        }
+        Ok(Filter::Delta { width, big_endian })
+    }
+
+    /// Number of bytes of an element
+    pub fn width(&self) -> usize {
+        match self {
+            Filter::None => 1,
+            Filter::Delta { width, .. } => *width as usize,
+        }
+    }
+
+    /// Append the descriptor of the filter, nothing for `Filter::None`
+    pub fn to_binary(&self, result: &mut Vec<u8>) {
+        if let Filter::Delta { width, big_endian } = *self {
+            result.push(DELTA);
+            result.push(if big_endian {
+                width | BIG_ENDIAN
+            } else {
+                width
+            });
+        }
+    }
+
+    /// Read the descriptor of a filter
+    pub fn from_binary<I: Iterator<Item = u8>>(bytes: &mut I) -> Result<Self> {
+        let missing = || HuffmanError::CorruptHeader("Missing filter".to_string());
+        match bytes.next().ok_or_else(missing)? {
+            DELTA => {
+                let field = bytes.next().ok_or_else(missing)?;
+                Filter::delta(field & !BIG_ENDIAN, field & BIG_ENDIAN != 0)
+                    .map_err(|err| HuffmanError::CorruptHeader(err.to_string()))
+            }
+            id => Err(HuffmanError::CorruptHeader(format!(
+                "Unknown filter {}",
+                id
+            ))),
+        }
+    }
+
+    fn load(&self, bytes: &[u8]) -> u64 {
+        let mut value = [0u8; 8];
+        match self {
+            Filter::Delta {
+                big_endian: true, ..
+            } => {
+                value[8 - bytes.len()..].copy_from_slice(bytes);
+                u64::from_be_bytes(value)
+            }
+            _ => {
+                value[..bytes.len()].copy_from_slice(bytes);
+                u64::from_le_bytes(value)
+            }
+        }
+    }
+
+    fn store(&self, value: u64, bytes: &mut [u8]) {
+        let width = bytes.len();
+        match self {
+            Filter::Delta {
+                big_endian: true, ..
+            } => bytes.copy_from_slice(&value.to_be_bytes()[8 - width..]),
+            _ => bytes.copy_from_slice(&value.to_le_bytes()[..width]),
+        }
+    }
+
+    /// Filter whole elements in place. `previous` is the last element of the
+    /// preceding data, `0` at the start.
+    pub fn apply(&self, data: &mut [u8], previous: &mut u64) {
+        if *self == Filter::None {
+            return;
+        }
+        for element in data.chunks_exact_mut(self.width()) {
+            let value = self.load(element);
+            self.store(value.wrapping_sub(*previous), element);
+            *previous = value;
+        }
+    }
+
+    /// Revert `apply` in place
+    pub fn revert(&self, data: &mut [u8], previous: &mut u64) {
+        if *self == Filter::None {
+            return;
+        }
+        for element in data.chunks_exact_mut(self.width()) {
+            let value = self.load(element).wrapping_add(*previous);
+            self.store(value, element);
+            *previous = self.load(element);
+        }
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Filter::None => write!(f, "none"),
+            Filter::Delta { width, big_endian } => write!(
+                f,
+                "delta of {} byte {} endian elements",
+                width,
+                if *big_endian { "big" } else { "little" }
+            ),
+        }
+    }
+}
+
+/// Applies a filter to everything read from the inner reader
+pub struct FilterReader<R: Read> {
+    inner: R,
+    filter: Filter,
+    previous: u64,
+    /// Filtered bytes not returned yet, starting at `position`
+    ready: Vec<u8>,
+    position: usize,
+    /// Bytes of an incomplete element
+    carry: Vec<u8>,
+    finished: bool,
+}
+
+impl<R: Read> FilterReader<R> {
+    pub fn new(reader: R, filter: Filter) -> Self {
+        FilterReader {
+            inner: reader,
+            filter,
+            previous: 0,
+            ready: Vec::with_capacity(BUF),
+            position: 0,
+            carry: Vec::new(),
+            finished: false,
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Read and filter the next chunk. Trailing bytes of an incomplete
+    /// element are passed unfiltered once the inner reader is exhausted.
+    fn refill(&mut self) -> std::io::Result<()> {
+        let start = self.carry.len();
+        self.ready.clear();
+        self.ready.append(&mut self.carry);
+        self.ready.resize(start + BUF, 0);
+        let n = loop {
+            match self.inner.read(&mut self.ready[start..]) {
+                Ok(n) => break n,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        };
+        self.ready.truncate(start + n);
+        self.position = 0;
+        if n == 0 {
+            self.finished = true;
+            return Ok(());
+        }
+        let aligned = self.ready.len() / self.filter.width() * self.filter.width();
+        self.carry.extend_from_slice(&self.ready[aligned..]);
+        self.ready.truncate(aligned);
+        self.filter.apply(&mut self.ready, &mut self.previous);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for FilterReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.position == self.ready.len() && !self.finished {
+            self.refill()?;
+        }
+        let n = (self.ready.len() - self.position).min(buf.len());
+        buf[..n].copy_from_slice(&self.ready[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+/// Reverts a filter on everything written to the inner writer. `finish` must
+/// be called to write the trailing bytes of an incomplete element.
+pub struct UnfilterWriter<W: Write> {
+    inner: W,
+    filter: Filter,
+    previous: u64,
+    /// Bytes of an incomplete element
+    carry: Vec<u8>,
+}
+
+impl<W: Write> UnfilterWriter<W> {
+    pub fn new(writer: W, filter: Filter) -> Self {
+        UnfilterWriter {
+            inner: writer,
+            filter,
+            previous: 0,
+            carry: Vec::new(),
+        }
+    }
+
+    /// Write the trailing bytes. Returns the flushed writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.inner.write_all(&self.carry)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for UnfilterWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.filter == Filter::None {
+            return self.inner.write(buf);
+        }
+        self.carry.extend_from_slice(buf);
+        let aligned = self.carry.len() / self.filter.width() * self.filter.width();
+        self.filter
+            .revert(&mut self.carry[..aligned], &mut self.previous);
+        self.inner.write_all(&self.carry[..aligned])?;
+        self.carry.drain(..aligned);
+        Ok(buf.len())
+    }
+
+    /// Flushes the inner writer, the trailing bytes are written by `finish`
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads at most `step` bytes at once
+    struct Chunked<'a>(&'a [u8], usize);
+
+    impl<'a> Read for Chunked<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.1.min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    fn roundtrip(data: &[u8], filter: Filter, step: usize) -> Vec<u8> {
+        let mut filtered = Vec::new();
+        FilterReader::new(Chunked(data, step), filter)
+            .read_to_end(&mut filtered)
+            .unwrap();
+        assert_eq!(filtered.len(), data.len());
+        let mut writer = UnfilterWriter::new(Vec::new(), filter);
+        for chunk in filtered.chunks(step) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), data);
+        filtered
+    }
+
+    #[test]
+    fn delta() {
+        let data = [10u8, 12, 15, 15, 14, 250, 3];
+        let filtered = roundtrip(&data, Filter::delta(1, false).unwrap(), 3);
+        assert_eq!(filtered, [10, 2, 3, 0, 255, 236, 9]);
+
+        let data = [0x01u8, 0x00, 0x02, 0x05, 0xFF, 0x01, 0x07];
+        let little = roundtrip(&data, Filter::delta(2, false).unwrap(), 3);
+        assert_eq!(little, [0x01, 0x00, 0x01, 0x05, 0xFD, 0xFC, 0x07]);
+        let big = roundtrip(&data, Filter::delta(2, true).unwrap(), 3);
+        assert_eq!(big, [0x01, 0x00, 0x01, 0x05, 0xFC, 0xFC, 0x07]);
+        assert_eq!(roundtrip(&data, Filter::None, 2), data);
+    }
+
+    #[test]
+    fn delta_makes_samples_compressible() {
+        // Slowly rising 32 bit samples
+        let data: Vec<u8> = (0..10_000u32)
+            .flat_map(|i| (1_000_000 + 3 * i + i % 7).to_be_bytes())
+            .collect();
+        for &(step, size) in [(1, 1001), (5, 10_001), (4096, data.len() - 3)].iter() {
+            for &width in [1, 2, 4, 8].iter() {
+                for &big_endian in [false, true].iter() {
+                    let filter = Filter::delta(width, big_endian).unwrap();
+                    roundtrip(&data[..size], filter, step);
+                }
+            }
+        }
+        let filtered = roundtrip(&data, Filter::delta(4, true).unwrap(), 4096);
+        let distinct = |data: &[u8]| {
+            let mut seen = [false; 256];
+            data.iter().for_each(|&b| seen[b as usize] = true);
+            seen.iter().filter(|&&s| s).count()
+        };
+        assert!(distinct(&filtered) < 12);
+        assert!(distinct(&data) > 200);
+    }
+
+    #[test]
+    fn descriptors() {
+        for filter in [
+            Filter::delta(1, false).unwrap(),
+            Filter::delta(8, true).unwrap(),
+        ]
+        .iter()
+        {
+            let mut binary = Vec::new();
+            filter.to_binary(&mut binary);
+            assert_eq!(
+                Filter::from_binary(&mut binary.into_iter()).unwrap(),
+                *filter
+            );
+        }
+        let mut binary = Vec::new();
+        Filter::None.to_binary(&mut binary);
+        assert!(binary.is_empty());
+        assert!(Filter::delta(3, false).is_err());
+        assert!(Filter::from_binary(&mut vec![1u8, 3].into_iter()).is_err());
+        assert!(Filter::from_binary(&mut vec![9u8, 1].into_iter()).is_err());
+        assert!(Filter::from_binary(&mut vec![1u8].into_iter()).is_err());
+    }
+}
@@ -0,0 +1,316 @@
+//! Container format of compressed files
+//!
+//! # Layout
+//! - 4 byte magic `pzhf`
+//! - 1 byte format version
+//! - 1 byte flags for optional features
+//! - 8 byte header length (big endian)
+//! - x byte header, see `huffman::header`
+//! - 4 byte CRC-32 over version, flags, header length and header (big endian)
+//! - x byte data
+//! - 4 byte CRC-32 of the original data (big endian), if
+//!   `FLAG_CONTENT_CHECKSUM` is set. If the header records a filter, the
+//!   checksum covers the filtered data.
+//!
+//! If `FLAG_BLOCKS` is set, the data consists of blocks with their own tables
+//! and the table of the header is empty. If `FLAG_INDEX` is set as well, an
+//! index of the blocks follows the blocks.
+//!
+//! If `FLAG_SYNC_POINTS` is set, a table of sync points for random access
+//! follows at the very end of the file.
+//!
+//! If `FLAG_ADAPTIVE` is set, the data is a single adaptive Huffman stream
+//! terminated by an end of stream symbol and the table of the header is empty.
+//!
+//! If `FLAG_CONTEXT` is set, the data starts with the table set of the
+//! context model, followed by the encoded data. The table of the header is
+//! empty.
+//!
+//! `FLAG_BLOCKS`, `FLAG_ADAPTIVE` and `FLAG_CONTEXT` select the coding of the
+//! data, at most one of them may be set.
+//!
+//! If the header records a transform, see `transform`, the data is coded as
+//! described by the transform and none of the flags above except
+//! `FLAG_CONTENT_CHECKSUM` and `FLAG_UNKNOWN_SIZE` may be set.
+//!
+//! Files whose header records a filter or a transform have the version
+//! `VERSION_DESCRIPTORS`, all others the version `VERSION`. Decoders without
+//! filters and transforms thus reject these files by their version.
+//!
+//! The decoder rejects unknown versions and flags instead of misdecoding
+//! the data.
+use crate::checksum::Crc32;
+use crate::error::{HuffmanError, Result};
+use crate::filter::Filter;
+use crate::huffman::header::Header;
+use crate::transform::Transform;
+use crate::{bytes_to_u64, u64_to_bytes};
+use std::io::{ErrorKind, Read, Write};
+
+/// Magic bytes at the start of every compressed file
+pub const MAGIC: [u8; 4] = *b"pzhf";
+
+/// Version of files without a filter and a transform
+pub const VERSION: u8 = 1;
+
+/// Version of files whose header records a filter or a transform
+pub const VERSION_DESCRIPTORS: u8 = 2;
+
+/// The data is followed by a CRC-32 of the original data
+pub const FLAG_CONTENT_CHECKSUM: u8 = 0x01;
+
+/// The data is split into blocks with a separate table each, see `block`
+pub const FLAG_BLOCKS: u8 = 0x02;
+
+/// The blocks are followed by an index, see `block::BlockIndex`
+pub const FLAG_INDEX: u8 = 0x04;
+
+/// The file ends with a table of sync points, see `huffman::sync`
+pub const FLAG_SYNC_POINTS: u8 = 0x08;
+
+/// The size of the data was not known during compression, e.g. for pipes.
+/// The header stores a size of `0`. Only valid together with `FLAG_BLOCKS`,
+/// `FLAG_ADAPTIVE` or a transform recorded in the header.
+pub const FLAG_UNKNOWN_SIZE: u8 = 0x10;
+
+/// The data is encoded with an adaptive code, see `huffman::adaptive`, and
+/// the table of the header is empty
+pub const FLAG_ADAPTIVE: u8 = 0x20;
+
+/// The data is encoded with an order-1 context model, see
+/// `huffman::context`. The table set precedes the data and the table of the
+/// header is empty.
+pub const FLAG_CONTEXT: u8 = 0x40;
+
+/// Flags of the coding modes, which exclude each other
+const MODE_FLAGS: u8 = FLAG_BLOCKS | FLAG_ADAPTIVE | FLAG_CONTEXT;
+
+/// All flags known to this version of the format
+pub const SUPPORTED_FLAGS: u8 = FLAG_CONTENT_CHECKSUM
+    | FLAG_BLOCKS
+    | FLAG_INDEX
+    | FLAG_SYNC_POINTS
+    | FLAG_UNKNOWN_SIZE
+    | FLAG_ADAPTIVE
+    | FLAG_CONTEXT;
+
+/// Preamble of a compressed file, i.e. everything in front of the data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preamble {
+    pub version: u8,
+    pub flags: u8,
+    /// Serialised `huffman::header::Header`
+    pub header: Vec<u8>,
+}
+
+impl Preamble {
+    pub fn new(flags: u8, header: Vec<u8>) -> Self {
+        Preamble {
+            version: VERSION,
+            flags,
+            header,
+        }
+    }
+
+    /// Preamble of a file with the header `header`. The version depends on
+    /// the descriptors recorded in the header.
+    pub fn for_header(flags: u8, header: &Header) -> Result<Self> {
+        let mut preamble = Preamble::new(flags, header.to_binary()?);
+        if has_descriptors(header) {
+            preamble.version = VERSION_DESCRIPTORS;
+        }
+        Ok(preamble)
+    }
+
+    /// Parse the header and check it against the version
+    pub fn parse_header(&self) -> Result<Header> {
+        let header = Header::from_binary(&self.header)?;
+        if has_descriptors(&header) != (self.version == VERSION_DESCRIPTORS) {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Filter {:?} and transform {:?} do not match the version {}",
+                header.filter, header.transform, self.version
+            )));
+        }
+        Ok(header)
+    }
+
+    fn checksum(&self) -> u32 {
+        let mut crc = Crc32::new();
+        crc.update(&[self.version, self.flags]);
+        crc.update(&u64_to_bytes(self.header.len() as u64));
+        crc.update(&self.header);
+        crc.finalize()
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[self.version, self.flags])?;
+        writer.write_all(&u64_to_bytes(self.header.len() as u64))?;
+        writer.write_all(&self.header)?;
+        writer.write_all(&self.checksum().to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Read and validate the preamble. Afterwards `reader` is positioned at
+    /// the start of the data.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        read_exact_or_truncated(reader, &mut magic)?;
+        if magic != MAGIC {
+            return Err(HuffmanError::BadMagic(magic.to_vec()));
+        }
+        let mut fields = [0u8; 2];
+        read_exact_or_truncated(reader, &mut fields)?;
+        let [version, flags] = fields;
+        if version != VERSION && version != VERSION_DESCRIPTORS {
+            return Err(HuffmanError::UnsupportedVersion(version));
+        }
+        if flags & !SUPPORTED_FLAGS != 0 {
+            return Err(HuffmanError::UnsupportedFlags(flags));
+        }
+        if (flags & MODE_FLAGS).count_ones() > 1 {
+            return Err(HuffmanError::UnsupportedFlags(flags));
+        }
+        let mut header_length = [0u8; 8];
+        read_exact_or_truncated(reader, &mut header_length)?;
+        let header_length = bytes_to_u64(&header_length);
+        let mut header = Vec::new();
+        reader.by_ref().take(header_length).read_to_end(&mut header)?;
+        if (header.len() as u64) < header_length {
+            return Err(HuffmanError::TruncatedStream);
+        }
+        let mut checksum = [0u8; 4];
+        read_exact_or_truncated(reader, &mut checksum)?;
+        let preamble = Preamble {
+            version,
+            flags,
+            header,
+        };
+        let expected = u32::from_be_bytes(checksum);
+        let actual = preamble.checksum();
+        if expected != actual {
+            return Err(HuffmanError::ChecksumMismatch { expected, actual });
+        }
+        Ok(preamble)
+    }
+}
+
+/// Whether the header records a filter or a transform
+fn has_descriptors(header: &Header) -> bool {
+    header.filter != Filter::None || header.transform != Transform::None
+}
+
+/// Read the CRC-32 footer and compare it with the checksum of the decoded data
+pub fn verify_footer<R: Read>(reader: &mut R, actual: u32) -> Result<()> {
+    let mut footer = [0u8; 4];
+    read_exact_or_truncated(reader, &mut footer)?;
+    let expected = u32::from_be_bytes(footer);
+    if expected != actual {
+        return Err(HuffmanError::ChecksumMismatch { expected, actual });
+    }
+    Ok(())
+}
+
+/// Fill `buf` completely or report a truncated stream.
+pub fn read_exact_or_truncated(reader: &mut impl Read, buf: &mut [u8]) -> Result<()> {
+    reader.read_exact(buf).map_err(|err| match err.kind() {
+        ErrorKind::UnexpectedEof => HuffmanError::TruncatedStream,
+        _ => err.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn encoded_preamble() -> Vec<u8> {
+        let mut data = Vec::new();
+        Preamble::new(0, b"header".to_vec()).write_to(&mut data).unwrap();
+        data.extend_from_slice(b"data");
+        data
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = encoded_preamble();
+        let mut reader = Cursor::new(&data);
+        let preamble = Preamble::read_from(&mut reader).unwrap();
+        assert_eq!(preamble, Preamble::new(0, b"header".to_vec()));
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"data");
+    }
+
+    #[test]
+    fn reject_invalid_preambles() {
+        let read = |data: &[u8]| Preamble::read_from(&mut Cursor::new(data));
+        let data = encoded_preamble();
+
+        let mut version = data.clone();
+        version[4] = VERSION_DESCRIPTORS + 1;
+        assert!(matches!(read(&version), Err(HuffmanError::UnsupportedVersion(3))));
+
+        let mut flags = Vec::new();
+        Preamble::new(!SUPPORTED_FLAGS, b"header".to_vec())
+            .write_to(&mut flags)
+            .unwrap();
+        assert!(matches!(read(&flags), Err(HuffmanError::UnsupportedFlags(0x80))));
+
+        let mut header = data.clone();
+        header[14] ^= 1;
+        assert!(matches!(read(&header), Err(HuffmanError::ChecksumMismatch { .. })));
+
+        let mut checksum = data.clone();
+        checksum[20] ^= 1;
+        assert!(matches!(read(&checksum), Err(HuffmanError::ChecksumMismatch { .. })));
+
+        assert!(matches!(read(&data[..5]), Err(HuffmanError::TruncatedStream)));
+        assert!(matches!(read(&data[..22]), Err(HuffmanError::TruncatedStream)));
+    }
+
+    #[test]
+    fn reject_several_modes() {
+        for &flags in [FLAG_BLOCKS, FLAG_ADAPTIVE, FLAG_CONTEXT].iter() {
+            for &other in [FLAG_BLOCKS, FLAG_ADAPTIVE, FLAG_CONTEXT].iter() {
+                let mut data = Vec::new();
+                Preamble::new(flags | other, b"header".to_vec())
+                    .write_to(&mut data)
+                    .unwrap();
+                let preamble = Preamble::read_from(&mut Cursor::new(&data));
+                if flags == other {
+                    assert!(preamble.is_ok());
+                } else {
+                    assert!(matches!(
+                        preamble,
+                        Err(HuffmanError::UnsupportedFlags(f)) if f == flags | other
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn descriptor_version() {
+        let filter = Filter::delta(2, true).unwrap();
+        let transform = Transform::lz77(4096).unwrap();
+        let headers = [
+            (Header::empty(10, Filter::None, Transform::None), VERSION),
+            (Header::empty(10, filter, Transform::None), VERSION_DESCRIPTORS),
+            (Header::empty(0, Filter::None, transform), VERSION_DESCRIPTORS),
+        ];
+        for (header, version) in headers.iter() {
+            let preamble = Preamble::for_header(FLAG_CONTENT_CHECKSUM, header).unwrap();
+            assert_eq!(preamble.version, *version);
+            assert_eq!(&preamble.parse_header().unwrap(), header);
+
+            // Descriptors in a file of version 1 or none in a file of version 2
+            let mut mismatch = preamble;
+            mismatch.version = VERSION + VERSION_DESCRIPTORS - version;
+            assert!(matches!(
+                mismatch.parse_header(),
+                Err(HuffmanError::CorruptHeader(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,558 @@
+//! Adaptive (one-pass) Huffman coding
+//!
+//! The `AdaptiveHuffman` model implements the FGK algorithm (Faller, Gallager,
+//! Knuth). Encoder and decoder start with the same empty tree and update it
+//! after every symbol, therefore neither a first pass over the data nor a
+//! table in the header is needed.
+//!
+//! # Symbols
+//! The alphabet consists of the 256 bytes and `END_OF_STREAM`. All symbols
+//! which have not occurred yet share the leaf NYT (not yet transmitted). Its
+//! codeword is followed by the symbol in `ESCAPE_BITS` bits. Every stream
+//! ends with `END_OF_STREAM`, padded to a full byte, so the size of the data
+//! does not need to be known in advance.
+//!
+//! # Tree
+//! Every node has a number. Numbers increase with the weight and the two
+//! children of a node have consecutive numbers (sibling property). Once the
+//! weight of the root reaches `MAX_WEIGHT`, all weights are halved and the
+//! tree is rebuilt. This bounds the length of the codewords and lets the code
+//! follow drifting statistics.
+use crate::checksum::Crc32;
+use crate::model::Model;
+use crate::BUF;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::io::{Error, ErrorKind, Read, Write};
+
+/// Number of symbols, i.e. all bytes and `END_OF_STREAM`
+const ALPHABET: usize = 257;
+
+/// Symbol marking the end of the stream
+pub const END_OF_STREAM: usize = 256;
+
+/// Size of a symbol following the codeword of NYT
+pub const ESCAPE_BITS: usize = 9;
+
+/// Weight of the root at which all weights are halved
+pub const MAX_WEIGHT: u32 = 1 << 16;
+
+/// Number of nodes of a tree containing all symbols and NYT
+const MAX_NODES: usize = 2 * ALPHABET + 1;
+
+/// Marks a missing parent, child or leaf
+const NONE: usize = usize::MAX;
+
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    weight: u32,
+    number: usize,
+    parent: usize,
+    /// Children of internal nodes, `NONE` for leaves
+    children: [usize; 2],
+    /// Symbol of a leaf, `NONE` for internal nodes and NYT
+    symbol: usize,
+}
+
+impl Node {
+    fn leaf(symbol: usize, weight: u32) -> Self {
+        Node {
+            weight,
+            number: 0,
+            parent: NONE,
+            children: [NONE; 2],
+            symbol,
+        }
+    }
+    fn is_leaf(&self) -> bool {
+        self.children[0] == NONE
+    }
+}
+
+pub struct AdaptiveHuffman {
+    nodes: Vec<Node>,
+    /// Node for every number, `NONE` for numbers not in use yet
+    order: [usize; MAX_NODES],
+    /// Leaf of every symbol, `NONE` if the symbol did not occur yet
+    leaves: [usize; ALPHABET],
+    nyt: usize,
+    root: usize,
+    max_weight: u32,
+}
+
+impl Default for AdaptiveHuffman {
+    fn default() -> Self {
+        AdaptiveHuffman::new()
+    }
+}
+
+impl AdaptiveHuffman {
+    /// Empty tree, only consisting of NYT
+    pub fn new() -> Self {
+        AdaptiveHuffman::with_max_weight(MAX_WEIGHT)
+    }
+
+    fn with_max_weight(max_weight: u32) -> Self {
+        let mut model = AdaptiveHuffman {
+            nodes: Vec::with_capacity(MAX_NODES),
+            order: [NONE; MAX_NODES],
+            leaves: [NONE; ALPHABET],
+            nyt: 0,
+            root: 0,
+            max_weight,
+        };
+        model.nyt = model.push(Node::leaf(NONE, 0), MAX_NODES - 1);
+        model.root = model.nyt;
+        model
+    }
+
+    fn push(&mut self, mut node: Node, number: usize) -> usize {
+        let id = self.nodes.len();
+        node.number = number;
+        self.nodes.push(node);
+        self.order[number] = id;
+        id
+    }
+
+    /// Codeword and its length for `symbol` in the current tree. Symbols
+    /// which did not occur yet are escaped via NYT.
+    pub fn code(&self, symbol: usize) -> (usize, usize) {
+        let leaf = self.leaves[symbol];
+        let start = if leaf == NONE { self.nyt } else { leaf };
+        let mut code = 0usize;
+        let mut length = 0usize;
+        let mut node = start;
+        while node != self.root {
+            let parent = self.nodes[node].parent;
+            if self.nodes[parent].children[1] == node {
+                code |= 1 << length;
+            }
+            length += 1;
+            node = parent;
+        }
+        if leaf == NONE {
+            code = code << ESCAPE_BITS | symbol;
+            length += ESCAPE_BITS;
+        }
+        (code, length)
+    }
+
+    /// Increment the weight of `symbol` and restore the sibling property
+    pub fn update(&mut self, symbol: usize) {
+        let mut node = match self.leaves[symbol] {
+            NONE => self.split_nyt(symbol),
+            leaf => leaf,
+        };
+        while node != NONE {
+            // A node whose sibling is NYT may need a second swap, once it is
+            // no longer a child of its former parent of the same weight
+            loop {
+                let leader = self.leader(node);
+                if leader == node {
+                    break;
+                }
+                self.swap(node, leader);
+            }
+            self.nodes[node].weight += 1;
+            node = self.nodes[node].parent;
+        }
+        if self.nodes[self.root].weight >= self.max_weight {
+            self.rescale();
+        }
+    }
+
+    /// Replace NYT by an internal node with a new NYT and a leaf for `symbol`
+    fn split_nyt(&mut self, symbol: usize) -> usize {
+        let old = self.nyt;
+        let number = self.nodes[old].number;
+        let mut nyt = Node::leaf(NONE, 0);
+        nyt.parent = old;
+        let mut leaf = Node::leaf(symbol, 0);
+        leaf.parent = old;
+        self.nyt = self.push(nyt, number - 2);
+        let leaf = self.push(leaf, number - 1);
+        self.nodes[old].children = [self.nyt, leaf];
+        self.leaves[symbol] = leaf;
+        leaf
+    }
+
+    /// Highest numbered node with the same weight as `node`, except its
+    /// parent. The parent has the same weight, if the sibling is NYT.
+    fn leader(&self, node: usize) -> usize {
+        let weight = self.nodes[node].weight;
+        let mut number = self.nodes[node].number;
+        while number + 1 < MAX_NODES && self.nodes[self.order[number + 1]].weight == weight {
+            number += 1;
+        }
+        if self.order[number] == self.nodes[node].parent {
+            number -= 1;
+        }
+        self.order[number]
+    }
+
+    /// Swap the subtrees of `a` and `b`, which are not ancestors of each other
+    fn swap(&mut self, a: usize, b: usize) {
+        let (pa, pb) = (self.nodes[a].parent, self.nodes[b].parent);
+        let ia = (self.nodes[pa].children[1] == a) as usize;
+        let ib = (self.nodes[pb].children[1] == b) as usize;
+        self.nodes[pa].children[ia] = b;
+        self.nodes[pb].children[ib] = a;
+        self.nodes[a].parent = pb;
+        self.nodes[b].parent = pa;
+        let (na, nb) = (self.nodes[a].number, self.nodes[b].number);
+        self.nodes[a].number = nb;
+        self.nodes[b].number = na;
+        self.order[na] = b;
+        self.order[nb] = a;
+    }
+
+    /// Halve all weights and rebuild the tree. The nodes are numbered in the
+    /// order in which the Huffman algorithm merges them, which satisfies the
+    /// sibling property.
+    fn rescale(&mut self) {
+        let mut leaves = vec![Node::leaf(NONE, 0)];
+        for node in self
+            .nodes
+            .iter()
+            .filter(|n| n.is_leaf() && n.symbol != NONE)
+        {
+            leaves.push(Node::leaf(node.symbol, node.weight.div_ceil(2)));
+        }
+        leaves[1..].sort_by_key(|node| node.symbol);
+        self.nodes.clear();
+        self.order = [NONE; MAX_NODES];
+        self.leaves = [NONE; ALPHABET];
+
+        let mut heap = BinaryHeap::new();
+        for node in leaves {
+            let id = self.nodes.len();
+            if node.symbol != NONE {
+                self.leaves[node.symbol] = id;
+            }
+            heap.push(Reverse((node.weight, id)));
+            self.nodes.push(node);
+        }
+        let mut merged = Vec::with_capacity(2 * self.nodes.len());
+        while let Some(Reverse((weight, a))) = heap.pop() {
+            merged.push(a);
+            let Some(Reverse((other, b))) = heap.pop() else {
+                break;
+            };
+            merged.push(b);
+            let parent = self.nodes.len();
+            let mut node = Node::leaf(NONE, weight + other);
+            node.children = [a, b];
+            self.nodes.push(node);
+            self.nodes[a].parent = parent;
+            self.nodes[b].parent = parent;
+            heap.push(Reverse((weight + other, parent)));
+        }
+        let first = MAX_NODES - merged.len();
+        for (ix, &id) in merged.iter().enumerate() {
+            self.nodes[id].number = first + ix;
+            self.order[first + ix] = id;
+        }
+        self.nyt = 0;
+        self.root = *merged.last().expect("Tree contains NYT");
+    }
+
+    /// Decode the next symbol, reading single bits from `next_bit`
+    fn decode<F: FnMut() -> std::io::Result<usize>>(
+        &self,
+        mut next_bit: F,
+    ) -> std::io::Result<usize> {
+        let mut node = self.root;
+        while !self.nodes[node].is_leaf() {
+            node = self.nodes[node].children[next_bit()?];
+        }
+        if node != self.nyt {
+            return Ok(self.nodes[node].symbol);
+        }
+        let mut symbol = 0;
+        for _ in 0..ESCAPE_BITS {
+            symbol = symbol << 1 | next_bit()?;
+        }
+        if symbol >= ALPHABET || self.leaves[symbol] != NONE {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid escaped symbol {}", symbol),
+            ));
+        }
+        Ok(symbol)
+    }
+}
+
+/// The codewords of the current state of the tree
+impl Model for AdaptiveHuffman {
+    fn encode(&self, sym: usize) -> (usize, usize) {
+        self.code(sym)
+    }
+    fn sentinel(&self) -> usize {
+        (0..256).map(|sym| self.code(sym).1).max().unwrap_or(0)
+    }
+    fn to_btreemap(&self) -> BTreeMap<usize, (u16, u8)> {
+        let sentinel = self.sentinel();
+        (0..256)
+            .map(|sym| {
+                let (code, length) = self.code(sym);
+                (code << (sentinel - length), (sym as u16, length as u8))
+            })
+            .collect()
+    }
+}
+
+/// Encodes all written data with an adaptive Huffman code. `finish` must be
+/// called to terminate the stream.
+pub struct AdaptiveEncoder<W: Write> {
+    inner: W,
+    model: AdaptiveHuffman,
+    buffer: u64,
+    bits: usize,
+    output: Vec<u8>,
+    checksum: Option<Crc32>,
+    pub readbytes: u64,
+}
+
+impl<W: Write> AdaptiveEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        AdaptiveEncoder {
+            inner: writer,
+            model: AdaptiveHuffman::new(),
+            buffer: 0,
+            bits: 0,
+            output: Vec::with_capacity(BUF),
+            checksum: None,
+            readbytes: 0,
+        }
+    }
+    /// Append a CRC-32 of all written data after the end of the stream
+    pub fn use_checksum(&mut self, enabled: bool) {
+        self.checksum = if enabled { Some(Crc32::new()) } else { None };
+    }
+    fn put(&mut self, symbol: usize) -> std::io::Result<()> {
+        let (code, length) = self.model.code(symbol);
+        debug_assert!(length + self.bits <= 64);
+        self.buffer = self.buffer << length | code as u64;
+        self.bits += length;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.output.push((self.buffer >> self.bits) as u8);
+        }
+        self.model.update(symbol);
+        if self.output.len() >= BUF {
+            self.inner.write_all(&self.output)?;
+            self.output.clear();
+        }
+        Ok(())
+    }
+    /// Write the end of the stream, the padding and the checksum. Returns
+    /// the flushed writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        self.put(END_OF_STREAM)?;
+        if self.bits > 0 {
+            self.output.push((self.buffer << (8 - self.bits)) as u8);
+        }
+        if let Some(crc) = self.checksum {
+            self.output.extend_from_slice(&crc.finalize().to_be_bytes());
+        }
+        self.inner.write_all(&self.output)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for AdaptiveEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(crc) = self.checksum.as_mut() {
+            crc.update(buf);
+        }
+        for &sym in buf.iter() {
+            self.put(sym as usize)?;
+        }
+        self.readbytes += buf.len() as u64;
+        Ok(buf.len())
+    }
+    /// Flushes the completed bytes only, the stream is terminated by `finish`
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.write_all(&self.output)?;
+        self.output.clear();
+        self.inner.flush()
+    }
+}
+
+/// Decodes a stream written by `AdaptiveEncoder` up to `END_OF_STREAM`
+pub struct AdaptiveDecoder<R: Read> {
+    inner: R,
+    model: AdaptiveHuffman,
+    byte: u8,
+    bits_left: u8,
+    finished: bool,
+    pub writeout: u64,
+}
+
+impl<R: Read> AdaptiveDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        AdaptiveDecoder {
+            inner: reader,
+            model: AdaptiveHuffman::new(),
+            byte: 0,
+            bits_left: 0,
+            finished: false,
+            writeout: 0,
+        }
+    }
+    /// Get the reader back. After the end of the stream it is positioned
+    /// directly behind the encoded data.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for AdaptiveDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut consumed = 0;
+        while consumed < buf.len() && !self.finished {
+            let AdaptiveDecoder {
+                inner,
+                model,
+                byte,
+                bits_left,
+                ..
+            } = self;
+            let symbol = model.decode(|| {
+                if *bits_left == 0 {
+                    let mut next = [0u8; 1];
+                    inner.read_exact(&mut next)?;
+                    *byte = next[0];
+                    *bits_left = 8;
+                }
+                *bits_left -= 1;
+                Ok((*byte >> *bits_left) as usize & 1)
+            })?;
+            if symbol == END_OF_STREAM {
+                self.finished = true;
+            } else {
+                buf[consumed] = symbol as u8;
+                consumed += 1;
+            }
+            self.model.update(symbol);
+        }
+        self.writeout += consumed as u64;
+        Ok(consumed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check the sibling property and the weights of the whole tree
+    fn check_tree(model: &AdaptiveHuffman) {
+        let mut numbers: Vec<usize> = model.nodes.iter().map(|n| n.number).collect();
+        numbers.sort_unstable();
+        let first = MAX_NODES - model.nodes.len();
+        assert_eq!(numbers, (first..MAX_NODES).collect::<Vec<_>>());
+        for number in first..MAX_NODES - 1 {
+            let (a, b) = (model.order[number], model.order[number + 1]);
+            assert!(model.nodes[a].weight <= model.nodes[b].weight);
+        }
+        for node in model.nodes.iter().filter(|n| !n.is_leaf()) {
+            let [left, right] = node.children;
+            assert_eq!(model.nodes[left].number + 1, model.nodes[right].number);
+            assert_eq!(
+                node.weight,
+                model.nodes[left].weight + model.nodes[right].weight
+            );
+        }
+        assert_eq!(model.nodes[model.root].number, MAX_NODES - 1);
+        assert_eq!(model.nodes[model.nyt].weight, 0);
+    }
+
+    fn roundtrip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = AdaptiveEncoder::new(Vec::new());
+        encoder.write_all(data).unwrap();
+        let encoded = encoder.finish().unwrap();
+        let mut decoded = Vec::new();
+        let mut decoder = AdaptiveDecoder::new(encoded.as_slice());
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        assert_eq!(decoder.writeout, data.len() as u64);
+        assert!(decoder.into_inner().is_empty());
+        encoded
+    }
+
+    #[test]
+    fn sibling_property() {
+        let text = b"This is a lovely text in a big world. ";
+        let mut model = AdaptiveHuffman::with_max_weight(500);
+        for (ix, &sym) in text.iter().cycle().take(5000).enumerate() {
+            model.update(sym as usize ^ (ix / 700));
+            check_tree(&model);
+        }
+        // Rescaling keeps the weights small
+        assert!(model.nodes[model.root].weight < 500);
+    }
+
+    #[test]
+    fn codes_follow_the_statistics() {
+        let mut model = AdaptiveHuffman::new();
+        assert_eq!(model.code(b'a' as usize), (b'a' as usize, ESCAPE_BITS));
+        for _ in 0..10 {
+            model.update(b'a' as usize);
+        }
+        model.update(b'b' as usize);
+        assert_eq!(model.code(b'a' as usize).1, 1);
+        assert_eq!(model.code(b'b' as usize).1, 2);
+        assert_eq!(model.code(b'c' as usize).1, 2 + ESCAPE_BITS);
+        // The current state is a complete prefix code for all bytes
+        let table = model.to_btreemap();
+        assert_eq!(table.len(), 256);
+        let sentinel = model.sentinel();
+        let kraft: usize = table
+            .values()
+            .map(|&(_, l)| 1 << (sentinel - l as usize))
+            .sum();
+        assert!(kraft <= 1 << sentinel);
+    }
+
+    #[test]
+    fn roundtrips() {
+        assert_eq!(roundtrip(&[]).len(), 2);
+        roundtrip(&[42]);
+        roundtrip(&[0u8; 1000]);
+        let all: Vec<u8> = (0..=255u8).cycle().take(5000).collect();
+        roundtrip(&all);
+        let text = "This is a lovely text in a big world. ".repeat(100);
+        let encoded = roundtrip(text.as_bytes());
+        assert!(encoded.len() < text.len() * 6 / 10);
+    }
+
+    #[test]
+    fn rescaling_roundtrip() {
+        // Long enough to rescale several times, with drifting statistics
+        let data: Vec<u8> = (0..300_000usize)
+            .map(|i| ((i.wrapping_mul(2_654_435_761) >> 28) & 15) as u8 + (i / 50_000) as u8 * 16)
+            .collect();
+        let encoded = roundtrip(&data);
+        assert!(encoded.len() < data.len() * 3 / 4);
+    }
+
+    #[test]
+    fn checksum_and_truncation() {
+        let data = b"This is a lovely text in a big world. ";
+        let mut encoder = AdaptiveEncoder::new(Vec::new());
+        encoder.use_checksum(true);
+        encoder.write_all(data).unwrap();
+        let encoded = encoder.finish().unwrap();
+        let (stream, footer) = encoded.split_at(encoded.len() - 4);
+        assert_eq!(footer, crate::checksum::crc32(data).to_be_bytes());
+
+        let mut decoded = Vec::new();
+        let mut decoder = AdaptiveDecoder::new(stream);
+        decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(&decoded, data);
+
+        let mut decoder = AdaptiveDecoder::new(&stream[..stream.len() - 2]);
+        assert!(decoder.read_to_end(&mut Vec::new()).is_err());
+    }
+}
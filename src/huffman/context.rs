@@ -0,0 +1,536 @@
+//! Order-1 context modelling
+//!
+//! The previous byte selects the Huffman table of the next byte. Every
+//! context with enough data gets a table of its own, all other contexts share
+//! a single table built from their combined histogram. A context gets its own
+//! table only if the bits saved outweigh the size of the table in the header.
+//! The first byte of the data is coded in the context `INITIAL_CONTEXT`.
+//!
+//! # Binary layout of the table set
+//! - 4 byte size of the following fields (big endian)
+//! - 32 byte bitmap of the contexts with a table of their own, bit `c % 8`
+//!   of byte `c / 8` for context `c`
+//! - Run-length encoded codeword lengths of the shared table, see
+//!   `huffman::header::encode_lengths`
+//! - Run-length encoded codeword lengths of every table of its own, in
+//!   increasing order of the contexts
+use super::decode::table::DecodeTable;
+use super::decode::validate_lookup;
+use super::header::{decode_lengths, encode_lengths, model_lengths};
+use super::{Huffman, MAX_CODE_LENGTH};
+use crate::checksum::Crc32;
+use crate::error::{HuffmanError, Result};
+use crate::format::read_exact_or_truncated;
+use crate::model::Model;
+use crate::BUF;
+use log::info;
+use std::io::{Error, ErrorKind, Read, Write};
+
+/// Context of the first byte
+pub const INITIAL_CONTEXT: u8 = 0;
+
+/// Number of contexts, i.e. previous bytes
+const CONTEXTS: usize = 256;
+
+/// Index width of the primary lookup tables. Smaller than the default, since
+/// the decoder holds up to 257 tables.
+const CONTEXT_LOOKUP_BITS: usize = 9;
+
+/// Histogram of the bytes following each byte
+pub type ContextHistograms = Vec<[usize; 256]>;
+
+/// Count every byte of `reader` in the context of its previous byte
+pub fn context_histograms<R: Read>(reader: &mut R) -> Result<ContextHistograms> {
+    let mut histograms = vec![[0usize; 256]; CONTEXTS];
+    let mut buffer = vec![0u8; BUF];
+    let mut previous = INITIAL_CONTEXT;
+    loop {
+        let n = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        for &byte in buffer[..n].iter() {
+            histograms[previous as usize][byte as usize] += 1;
+            previous = byte;
+        }
+    }
+    Ok(histograms)
+}
+
+/// Number of bits needed to code `histogram` with `model`
+fn coded_bits(histogram: &[usize; 256], model: &Huffman) -> usize {
+    histogram
+        .iter()
+        .enumerate()
+        .map(|(sym, &count)| count * model.encode(sym).1)
+        .sum()
+}
+
+/// Size in bits of the codeword lengths of `model` in the table set
+fn table_bits(model: &Huffman) -> Result<usize> {
+    let mut serialised = Vec::new();
+    encode_lengths(&model_lengths(model), &mut serialised)?;
+    Ok(8 * serialised.len())
+}
+
+pub struct ContextModel {
+    /// Index into `tables` for every context
+    contexts: [usize; CONTEXTS],
+    /// The shared table followed by the tables of their own
+    tables: Vec<Huffman>,
+}
+
+impl ContextModel {
+    /// Choose the tables for the histograms of all 256 contexts
+    pub fn from_histograms(histograms: &[[usize; 256]]) -> Result<Self> {
+        if histograms.len() != CONTEXTS {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Expected {} contexts, got {}",
+                CONTEXTS,
+                histograms.len()
+            )));
+        }
+        let mut total = [0usize; 256];
+        for histogram in histograms.iter() {
+            for (sum, &count) in total.iter_mut().zip(histogram.iter()) {
+                *sum += count;
+            }
+        }
+        let all = Huffman::from_histogram(&total, MAX_CODE_LENGTH)?;
+
+        let mut contexts = [0usize; CONTEXTS];
+        let mut tables = Vec::new();
+        let mut shared = [0usize; 256];
+        for (context, histogram) in histograms.iter().enumerate() {
+            if histogram.iter().all(|&count| count == 0) {
+                continue;
+            }
+            let own = Huffman::from_histogram(histogram, MAX_CODE_LENGTH)?;
+            if coded_bits(histogram, &own) + table_bits(&own)? < coded_bits(histogram, &all) {
+                tables.push(own);
+                contexts[context] = tables.len();
+            } else {
+                for (sum, &count) in shared.iter_mut().zip(histogram.iter()) {
+                    *sum += count;
+                }
+            }
+        }
+        tables.insert(0, Huffman::from_histogram(&shared, MAX_CODE_LENGTH)?);
+        info!("Context model with {} tables", tables.len());
+        Ok(ContextModel { contexts, tables })
+    }
+
+    pub fn from_slice(data: &[u8]) -> Self {
+        let histograms = context_histograms(&mut &data[..]).expect("Reading from a slice");
+        ContextModel::from_histograms(&histograms).expect("All bytes fit into MAX_CODE_LENGTH bits")
+    }
+
+    /// The table of the byte following `previous`
+    pub fn table(&self, previous: u8) -> &Huffman {
+        &self.tables[self.contexts[previous as usize]]
+    }
+
+    /// Number of contexts with a table of their own
+    pub fn own_tables(&self) -> usize {
+        self.tables.len() - 1
+    }
+
+    pub fn to_binary(&self) -> Result<Vec<u8>> {
+        let mut result = vec![0u8; 4 + CONTEXTS / 8];
+        for (context, &table) in self.contexts.iter().enumerate() {
+            if table > 0 {
+                result[4 + context / 8] |= 1 << (context % 8);
+            }
+        }
+        for table in self.tables.iter() {
+            encode_lengths(&model_lengths(table), &mut result)?;
+        }
+        let size = (result.len() - 4) as u32;
+        result[..4].copy_from_slice(&size.to_be_bytes());
+        info!("Context table set size: {} bytes", result.len());
+        Ok(result)
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&self.to_binary()?)?;
+        Ok(())
+    }
+
+    /// Read the table set. Afterwards `reader` is positioned at the start of
+    /// the encoded data.
+    pub fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut size = [0u8; 4];
+        read_exact_or_truncated(reader, &mut size)?;
+        let size = u32::from_be_bytes(size) as u64;
+        let mut binary = Vec::new();
+        reader.by_ref().take(size).read_to_end(&mut binary)?;
+        if (binary.len() as u64) < size {
+            return Err(HuffmanError::TruncatedStream);
+        }
+        if binary.len() < CONTEXTS / 8 {
+            return Err(HuffmanError::CorruptHeader(
+                "Missing context bitmap".to_string(),
+            ));
+        }
+        let (bitmap, lengths) = binary.split_at(CONTEXTS / 8);
+        let mut contexts = [0usize; CONTEXTS];
+        let mut own = 0;
+        for (context, table) in contexts.iter_mut().enumerate() {
+            if bitmap[context / 8] & (1 << (context % 8)) != 0 {
+                own += 1;
+                *table = own;
+            }
+        }
+        let mut bytes = lengths.iter().cloned();
+        let tables = (0..=own)
+            .map(|_| Huffman::from_lengths(&decode_lengths(&mut bytes)?))
+            .collect::<Result<Vec<Huffman>>>()?;
+        if bytes.next().is_some() {
+            return Err(HuffmanError::CorruptHeader(
+                "Trailing bytes after the context tables".to_string(),
+            ));
+        }
+        Ok(ContextModel { contexts, tables })
+    }
+}
+
+/// Encodes all written data with the table of the previous byte. `finish`
+/// must be called to write the last bits.
+pub struct ContextEncoder<'a, W: Write> {
+    inner: W,
+    model: &'a ContextModel,
+    previous: u8,
+    buffer: u64,
+    bits: usize,
+    output: Vec<u8>,
+    checksum: Option<Crc32>,
+    pub readbytes: u64,
+}
+
+impl<'a, W: Write> ContextEncoder<'a, W> {
+    pub fn new(writer: W, model: &'a ContextModel) -> Self {
+        ContextEncoder {
+            inner: writer,
+            model,
+            previous: INITIAL_CONTEXT,
+            buffer: 0,
+            bits: 0,
+            output: Vec::with_capacity(BUF),
+            checksum: None,
+            readbytes: 0,
+        }
+    }
+    /// Append a CRC-32 of all written data after the encoded data
+    pub fn use_checksum(&mut self, enabled: bool) {
+        self.checksum = if enabled { Some(Crc32::new()) } else { None };
+    }
+    /// Write the padding of the last byte and the checksum. Returns the
+    /// flushed writer.
+    pub fn finish(mut self) -> std::io::Result<W> {
+        if self.bits > 0 {
+            self.output.push((self.buffer << (8 - self.bits)) as u8);
+        }
+        if let Some(crc) = self.checksum {
+            self.output.extend_from_slice(&crc.finalize().to_be_bytes());
+        }
+        self.inner.write_all(&self.output)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<'a, W: Write> Write for ContextEncoder<'a, W> {
+    /// Encodes the bytes up to the first byte without a codeword. The
+    /// checksum only covers the encoded bytes.
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut encoded = 0;
+        for &sym in buf.iter() {
+            let (code, length) = self.model.table(self.previous).encode(sym as usize);
+            if length == 0 {
+                break;
+            }
+            self.buffer = self.buffer << length | code as u64;
+            self.bits += length;
+            while self.bits >= 8 {
+                self.bits -= 8;
+                self.output.push((self.buffer >> self.bits) as u8);
+            }
+            self.previous = sym;
+            encoded += 1;
+        }
+        if encoded == 0 && !buf.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Byte {} has no codeword in context {}", buf[0], self.previous),
+            ));
+        }
+        if let Some(crc) = self.checksum.as_mut() {
+            crc.update(&buf[..encoded]);
+        }
+        if self.output.len() >= BUF {
+            self.inner.write_all(&self.output)?;
+            self.output.clear();
+        }
+        self.readbytes += encoded as u64;
+        Ok(encoded)
+    }
+    /// Flushes the completed bytes only, the last bits are written by `finish`
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.write_all(&self.output)?;
+        self.output.clear();
+        self.inner.flush()
+    }
+}
+
+/// Lookup table and sentinel of a table of the `ContextModel`
+struct ContextTable {
+    table: DecodeTable,
+    sentinel: usize,
+}
+
+/// Decodes `size` bytes written by `ContextEncoder`
+pub struct ContextDecoder<R: Read> {
+    inner: R,
+    contexts: [usize; CONTEXTS],
+    /// `None` for tables without any codeword
+    tables: Vec<Option<ContextTable>>,
+    previous: u8,
+    /// Left-aligned bits not decoded yet
+    buffer: u64,
+    bits: usize,
+    checksum: Option<Crc32>,
+    size: u64,
+    pub writeout: u64,
+}
+
+impl<R: Read> ContextDecoder<R> {
+    pub fn new(reader: R, model: &ContextModel, size: u64) -> Result<Self> {
+        let mut tables = Vec::with_capacity(model.tables.len());
+        for huffman in model.tables.iter() {
+            let bt = huffman.to_btreemap();
+            if bt.is_empty() {
+                tables.push(None);
+                continue;
+            }
+            let sentinel = huffman.sentinel();
+            validate_lookup(&bt, sentinel)?;
+            tables.push(Some(ContextTable {
+                table: DecodeTable::with_bits(&bt, sentinel, CONTEXT_LOOKUP_BITS)?,
+                sentinel,
+            }));
+        }
+        Ok(ContextDecoder {
+            inner: reader,
+            contexts: model.contexts,
+            tables,
+            previous: INITIAL_CONTEXT,
+            buffer: 0,
+            bits: 0,
+            checksum: None,
+            size,
+            writeout: 0,
+        })
+    }
+    /// Verify the decoded data against the CRC-32 footer following the
+    /// encoded data. A mismatch is reported by `read` as an error.
+    pub fn use_checksum(&mut self, enabled: bool) {
+        self.checksum = if enabled { Some(Crc32::new()) } else { None };
+    }
+    /// Get the reader back. Bytes which were already moved into the buffer
+    /// are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+    /// Fill the buffer with whole bytes. Returns `false` if the inner reader
+    /// is exhausted.
+    fn fill(&mut self) -> std::io::Result<bool> {
+        while self.bits <= 56 {
+            let mut byte = [0u8; 1];
+            match self.inner.read(&mut byte) {
+                Ok(0) => return Ok(false),
+                Ok(_) => {
+                    self.buffer |= (byte[0] as u64) << (56 - self.bits);
+                    self.bits += 8;
+                }
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+    fn decode(&mut self) -> Result<u8> {
+        self.fill()?;
+        let context = self.contexts[self.previous as usize];
+        let table = self.tables[context].as_ref().ok_or_else(|| {
+            HuffmanError::InvalidCode(format!("No table for context {}", self.previous))
+        })?;
+        let (sym, length) = table.table.lookup(self.buffer >> (64 - table.sentinel))?;
+        let length = length as usize;
+        if length > self.bits {
+            return Err(HuffmanError::TruncatedStream);
+        }
+        self.buffer = self.buffer.checked_shl(length as u32).unwrap_or(0);
+        self.bits -= length;
+        self.previous = sym as u8;
+        Ok(sym as u8)
+    }
+    /// Compare the checksum with the footer following the padded data, which
+    /// might already be in the buffer
+    fn verify_checksum(&mut self) -> Result<()> {
+        let crc = match self.checksum.take() {
+            Some(crc) => crc,
+            None => return Ok(()),
+        };
+        let padding = self.bits % 8;
+        self.buffer = self.buffer.checked_shl(padding as u32).unwrap_or(0);
+        self.bits -= padding;
+        let mut footer = [0u8; 4];
+        let buffered = (self.bits / 8).min(4);
+        for byte in footer.iter_mut().take(buffered) {
+            *byte = (self.buffer >> 56) as u8;
+            self.buffer <<= 8;
+            self.bits -= 8;
+        }
+        read_exact_or_truncated(&mut self.inner, &mut footer[buffered..])?;
+        let expected = u32::from_be_bytes(footer);
+        let actual = crc.finalize();
+        if expected != actual {
+            return Err(HuffmanError::ChecksumMismatch { expected, actual });
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ContextDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let nbytes = ((self.size - self.writeout) as usize).min(buf.len());
+        for byte in buf.iter_mut().take(nbytes) {
+            *byte = self.decode()?;
+        }
+        if let Some(crc) = self.checksum.as_mut() {
+            crc.update(&buf[..nbytes]);
+        }
+        self.writeout += nbytes as u64;
+        if self.writeout == self.size {
+            self.verify_checksum()?;
+        }
+        Ok(nbytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Lines of a CSV file with a timestamp, a sensor name and a value
+    fn csv() -> Vec<u8> {
+        let mut data = b"timestamp,sensor,value\n".to_vec();
+        for i in 0..3000u64 {
+            let line = format!(
+                "2024-03-{:02}T{:02}:{:02}:00,sensor-{},{}.{}\n",
+                1 + i / 1440 % 28,
+                i / 60 % 24,
+                i % 60,
+                ["alpha", "beta", "gamma"][(i % 3) as usize],
+                i.wrapping_mul(2_654_435_761) % 1000,
+                i % 10
+            );
+            data.extend_from_slice(line.as_bytes());
+        }
+        data
+    }
+
+    fn encode(data: &[u8], model: &ContextModel) -> Vec<u8> {
+        let mut encoder = ContextEncoder::new(Vec::new(), model);
+        encoder.use_checksum(true);
+        encoder.write_all(&data[..data.len() / 2]).unwrap();
+        encoder.write_all(&data[data.len() / 2..]).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn decode(encoded: &[u8], model: &ContextModel, size: usize) -> Result<Vec<u8>> {
+        let mut decoder = ContextDecoder::new(encoded, model, size as u64)?;
+        decoder.use_checksum(true);
+        let mut decoded = Vec::new();
+        decoder.read_to_end(&mut decoded)?;
+        Ok(decoded)
+    }
+
+    #[test]
+    fn roundtrip() {
+        let data = csv();
+        let model = ContextModel::from_slice(&data);
+        assert!(model.own_tables() > 10);
+        let encoded = encode(&data, &model);
+        assert_eq!(decode(&encoded, &model, data.len()).unwrap(), data);
+
+        // Order-0 needs considerably more space
+        let h = Huffman::from_slice(&data);
+        let mut histogram = [0usize; 256];
+        for &byte in data.iter() {
+            histogram[byte as usize] += 1;
+        }
+        let order0 = coded_bits(&histogram, &h) / 8;
+        let order1 = encoded.len() + model.to_binary().unwrap().len();
+        assert!(order1 * 10 < order0 * 8, "{} vs {}", order1, order0);
+
+        for data in [&b""[..], b"a", b"abababababababab", &[7u8; 1000]].iter() {
+            let model = ContextModel::from_slice(data);
+            let encoded = encode(data, &model);
+            assert_eq!(&decode(&encoded, &model, data.len()).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn table_set() {
+        let data = csv();
+        let model = ContextModel::from_slice(&data);
+        let mut binary = Vec::new();
+        model.write_to(&mut binary).unwrap();
+        binary.extend_from_slice(b"data");
+        let mut reader = binary.as_slice();
+        let read = ContextModel::read_from(&mut reader).unwrap();
+        assert_eq!(reader, b"data");
+        assert_eq!(read.contexts, model.contexts);
+        for (a, b) in read.tables.iter().zip(model.tables.iter()) {
+            assert_eq!(a.to_btreemap(), b.to_btreemap());
+        }
+        // Contexts without a table of their own use the shared table
+        assert!(model.contexts.contains(&0));
+
+        assert!(ContextModel::read_from(&mut &binary[..20]).is_err());
+        let mut corrupt = binary.clone();
+        corrupt[3] += 1;
+        assert!(ContextModel::read_from(&mut corrupt.as_slice()).is_err());
+    }
+
+    #[test]
+    fn corrupt_data() {
+        let data = csv();
+        let model = ContextModel::from_slice(&data);
+        let mut encoded = encode(&data, &model);
+        let err = decode(&encoded[..encoded.len() / 2], &model, data.len()).unwrap_err();
+        assert!(matches!(err, HuffmanError::TruncatedStream));
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+        let err = decode(&encoded, &model, data.len()).unwrap_err();
+        assert!(matches!(err, HuffmanError::ChecksumMismatch { .. }));
+
+        // Bytes without a codeword in their context
+        let mut encoder = ContextEncoder::new(Vec::new(), &model);
+        assert!(encoder.write(b"\x00\x01").is_err());
+
+        // The bytes in front of such a byte are encoded and checksummed
+        let mut encoder = ContextEncoder::new(Vec::new(), &model);
+        encoder.use_checksum(true);
+        let mut input = data[..10].to_vec();
+        input.push(0);
+        assert_eq!(encoder.write(&input).unwrap(), 10);
+        assert!(encoder.write_all(&input[10..]).is_err());
+        assert_eq!(encoder.readbytes, 10);
+        let encoded = encoder.finish().unwrap();
+        assert_eq!(decode(&encoded, &model, 10).unwrap(), &data[..10]);
+    }
+}
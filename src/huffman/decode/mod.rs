@@ -1,147 +1,284 @@
 use super::encode::Encoder;
 use super::header::Header;
+use crate::checksum::Crc32;
+use crate::error::{HuffmanError, Result};
 use crate::model::Model;
 use log::debug;
 use std::collections::BTreeMap;
-use std::io::Error;
+use std::io::ErrorKind;
 use std::io::{Read, Write};
 use succinct::bit_vec::BitVecMut;
 use succinct::rank::BitRankSupport;
 use succinct::rsdict::RsDict;
 use succinct::BitVector;
 
+pub mod seek;
+pub mod table;
 pub mod vault;
 
+use table::{DecodeTable, MultiDecodeTable};
+
+/// Largest sentinel accepted by the decoders. The lookup tables need up to
+/// `1 << sentinel` entries, therefore larger values are rejected.
+pub const MAX_SENTINEL: usize = 24;
+
 pub struct Decoder<R: Read> {
     inner: R,
     buffer: u64,
     bits_left_in_buffer: u8,
-    // bt: BTreeMap<usize, (u8, u8)>,
-    table: Vec<(u8, u8)>,
-    rbv: RsDict,
-    sentinel: usize,
+    // bt: BTreeMap<usize, (u16, u8)>,
+    table: DecodeTable,
+    multi: Option<MultiDecodeTable>,
+    checksum: Option<Crc32>,
     writeout: usize,
     goalsbyte: usize,
     shift: u8,
+    alphabet: usize,
 }
 
 impl<R: Read> Decoder<R> {
-    pub fn new<W: Write, M: Model>(reader: R, encoder: &Encoder<W, M>) -> Self {
-        Decoder {
+    pub fn new<W: Write, M: Model>(reader: R, encoder: &Encoder<W, M>) -> Result<Self> {
+        Decoder::with_model(reader, encoder.model, encoder.readbytes)
+    }
+    pub fn from_header(header: Header, reader: R) -> Result<Self> {
+        let model = header.to_model()?;
+        Decoder::with_model(reader, &model, header.readbytes)
+    }
+    /// Decode `goalsbyte` symbols encoded with `model` from `reader`
+    pub fn with_model<M: Model>(reader: R, model: &M, goalsbyte: usize) -> Result<Self> {
+        let bt = model.to_btreemap();
+        let sentinel = model.sentinel();
+        validate_lookup(&bt, sentinel)?;
+        let table = DecodeTable::new(&bt, sentinel)?;
+        Ok(Decoder {
             inner: reader,
             buffer: 0,
             bits_left_in_buffer: 64,
-            table: {
-                let (t, _) = prepare_lookup(&encoder.model.to_btreemap());
-                t
-            },
-            rbv: {
-                let (_, v) = prepare_lookup(&encoder.model.to_btreemap());
-                v
-            },
-            sentinel: encoder.model.sentinel(),
+            table,
+            multi: None,
+            checksum: None,
             writeout: 0,
-            goalsbyte: encoder.readbytes,
-            shift: 64 - encoder.model.sentinel() as u8,
+            goalsbyte,
+            shift: 64 - sentinel as u8,
+            alphabet: model.alphabet_size(),
+        })
+    }
+    /// Decode several symbols with a single lookup, if their codewords fit
+    /// into the lookup width. Pays off for highly skewed data with short
+    /// codewords. Only available for byte alphabets.
+    pub fn use_multi_symbol_table(&mut self, enabled: bool) {
+        self.multi = if enabled && self.alphabet <= 256 {
+            Some(MultiDecodeTable::new(&self.table))
+        } else {
+            None
+        };
+    }
+    /// Verify the decoded data against the CRC-32 footer following the
+    /// encoded data. A mismatch is reported by `read` as an error. Symbols
+    /// decoded via `read_symbols` are checked as 2 byte big endian values.
+    pub fn use_checksum(&mut self, enabled: bool) {
+        self.checksum = if enabled { Some(Crc32::new()) } else { None };
+    }
+    /// Skip the first `bits` bits of the inner reader, e.g. to start at a
+    /// sync point which is not byte-aligned. Must be called before `read`.
+    pub fn skip_bits(&mut self, bits: u8) -> Result<()> {
+        while self.bits_left_in_buffer >= 8 {
+            match self.next_byte()? {
+                Some(val) => {
+                    self.buffer += (val as u64) << (self.bits_left_in_buffer - 8);
+                    self.bits_left_in_buffer -= 8;
+                }
+                None => break,
+            }
+        }
+        if bits > 64 - self.bits_left_in_buffer {
+            return Err(HuffmanError::TruncatedStream);
         }
+        self.buffer = self.buffer.checked_shl(bits as u32).unwrap_or(0);
+        self.bits_left_in_buffer += bits;
+        Ok(())
     }
-    pub fn from_header(header: Header, reader: R) -> Self {
-        Decoder {
-            inner: reader,
-            buffer: 0,
-            bits_left_in_buffer: 64,
-            table: {
-                let (t, _) = prepare_lookup(&header.btree);
-                t
-            },
-            rbv: {
-                let (_, v) = prepare_lookup(&header.btree);
-                v
-            },
-            sentinel: header.sentinel,
-            writeout: 0,
-            goalsbyte: header.readbytes,
-            shift: 64 - header.sentinel as u8,
+    /// Decode up to `buf.len()` symbols of an alphabet with up to
+    /// `MAX_ALPHABET_SIZE` symbols. Returns the number of decoded symbols,
+    /// which is `0` once all symbols are decoded.
+    pub fn read_symbols(&mut self, buf: &mut [u16]) -> Result<usize> {
+        let nsymbols = (self.goalsbyte - self.writeout).min(buf.len());
+        for sym in buf.iter_mut().take(nsymbols) {
+            self.fill()?;
+            *sym = self.decode_symbol()?;
         }
+        if let Some(crc) = self.checksum.as_mut() {
+            for sym in buf.iter().take(nsymbols) {
+                crc.update(&sym.to_be_bytes());
+            }
+        }
+        if self.writeout == self.goalsbyte {
+            self.verify_checksum()?;
+        }
+        Ok(nsymbols)
+    }
+    /// Read `count` raw bits written by `Encoder::write_bits`, up to 32
+    pub fn read_bits(&mut self, count: usize) -> Result<usize> {
+        if count > 32 {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Can not read {} bits at once",
+                count
+            )));
+        }
+        if count == 0 {
+            return Ok(0);
+        }
+        self.fill()?;
+        if count > 64 - self.bits_left_in_buffer as usize {
+            return Err(HuffmanError::TruncatedStream);
+        }
+        let value = (self.buffer >> (64 - count)) as usize;
+        self.buffer <<= count;
+        self.bits_left_in_buffer += count as u8;
+        Ok(value)
+    }
+    /// Returns the inner reader. Bytes which were already moved into the
+    /// buffer are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+    fn next_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let mut byte = [0u8; 1];
+        loop {
+            match self.inner.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    /// Move whole bytes from the inner reader into the buffer, until it is
+    /// full or the inner reader is exhausted
+    #[inline]
+    fn fill(&mut self) -> std::io::Result<()> {
+        while self.bits_left_in_buffer >= 8 {
+            match self.next_byte()? {
+                Some(val) => {
+                    self.buffer += (val as u64) << (self.bits_left_in_buffer - 8);
+                    self.bits_left_in_buffer -= 8;
+                }
+                None => break,
+            }
+        }
+        debug!(
+            "Add: {:064b} BLE {:2}",
+            self.buffer, self.bits_left_in_buffer
+        );
+        Ok(())
+    }
+    /// Decode a single symbol from the buffer. The buffer holds more than
+    /// `sentinel` bits, unless the inner reader is exhausted.
+    #[inline]
+    fn decode_symbol(&mut self) -> Result<u16> {
+        let searchvalue = self.buffer >> self.shift;
+        let (sym, length) = self.table.lookup(searchvalue)?;
+        if length > 64 - self.bits_left_in_buffer {
+            return Err(HuffmanError::TruncatedStream);
+        }
+        self.writeout += 1;
+        self.buffer <<= length;
+        debug!(
+            "Rem: {:064b} SYM {:b} LEN {} SVA {}",
+            self.buffer, sym, length, searchvalue
+        );
+        self.bits_left_in_buffer += length;
+        Ok(sym)
+    }
+    /// Compare the checksum of the decoded data with the footer. The footer
+    /// starts at the byte following the last (padded) byte of encoded data,
+    /// which might already be in the buffer.
+    fn verify_checksum(&mut self) -> Result<()> {
+        let crc = match self.checksum.take() {
+            Some(crc) => crc,
+            None => return Ok(()),
+        };
+        let padding = (64 - self.bits_left_in_buffer) % 8;
+        self.buffer <<= padding;
+        self.bits_left_in_buffer += padding;
+        let mut footer = [0u8; 4];
+        for byte in footer.iter_mut() {
+            if self.bits_left_in_buffer < 64 {
+                *byte = (self.buffer >> 56) as u8;
+                self.buffer <<= 8;
+                self.bits_left_in_buffer += 8;
+            } else {
+                *byte = self.next_byte()?.ok_or(HuffmanError::TruncatedStream)?;
+            }
+        }
+        let expected = u32::from_be_bytes(footer);
+        let actual = crc.finalize();
+        if expected != actual {
+            return Err(HuffmanError::ChecksumMismatch { expected, actual });
+        }
+        Ok(())
+    }
+    /// Decode the next symbols via the multi-symbol table into `buf`.
+    /// Returns the number of decoded symbols. Zero if no multi-symbol table
+    /// is used or the entry does not fit, i.e. a single lookup is needed.
+    #[inline]
+    fn decode_multi(&mut self, buf: &mut [u8]) -> usize {
+        let entry = match &self.multi {
+            Some(multi) => multi.lookup(self.buffer),
+            None => return 0,
+        };
+        let count = entry.count as usize;
+        if count == 0 || count > buf.len() || entry.length > 64 - self.bits_left_in_buffer {
+            return 0;
+        }
+        buf[..count].copy_from_slice(&entry.symbols[..count]);
+        self.writeout += count;
+        self.buffer <<= entry.length;
+        self.bits_left_in_buffer += entry.length;
+        count
     }
 }
 
 impl<R: Read> Read for Decoder<R> {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.alphabet > 256 {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "Symbols do not fit into bytes, use read_symbols",
+            ));
+        }
         let nbytes = (self.goalsbyte - self.writeout).min(buf.len());
         let mut consumed = 0;
-        let mut iter = self.inner.by_ref().bytes(); //.skip(self.pos);
-        while let Some(Ok(val)) = iter.next() {
-            // debug!("Reading {}", val);
-            if self.bits_left_in_buffer >= 16 {
-                // There is still room for a byte in the buffer -> fill it up
-                let v = (val as u64) << (self.bits_left_in_buffer - 8);
-                self.buffer += v;
-                self.bits_left_in_buffer -= 8;
-                debug!(
-                    "Add: {:064b} BLE {:2}",
-                    self.buffer, self.bits_left_in_buffer
-                );
+        while consumed < nbytes {
+            // There is still room for a byte in the buffer -> fill it up
+            self.fill()?;
+            // Actual decoding of the values from the buffer
+            let decoded = self.decode_multi(&mut buf[consumed..nbytes]);
+            if decoded > 0 {
+                consumed += decoded;
                 continue;
             }
-            while (64 - self.bits_left_in_buffer - 8) as usize >= self.sentinel && consumed < nbytes
-            {
-                // Actual decoding of the values from the buffer. As long as the consumed is less than nbytes
-                // or the buffer needs to be filled up again
-                let searchvalue = self.buffer >> self.shift;
-                let pos = self.rbv.rank1(searchvalue + 1) as usize - 1;
-                let (sym, length) = self.table[pos];
-                // debug!("Decoded {} {} {}", sym, length, consumed);
-                buf[consumed] = sym;
-                consumed += 1;
-                self.writeout += 1;
-                self.buffer <<= length;
-                debug!(
-                    "Rem: {:064b} SYM {:b} LEN {} SVA {} CNS {}",
-                    self.buffer, sym, length, searchvalue, consumed
-                );
-                self.bits_left_in_buffer += length;
-            }
-            debug!(
-                "Out: {:064b} BLE {} >",
-                self.buffer, self.bits_left_in_buffer
-            );
-            // Do not forget to add the current value `val` into the buffer
-            let v = (val as u64) << (self.bits_left_in_buffer - 8);
-            self.buffer += v;
-            self.bits_left_in_buffer -= 8;
-            if consumed >= nbytes {
-                // If consumed was the reason for the break above, return written bytes
-                // otherwise continue
-                debug!(
-                    "Out: {:064b} BLE {} >",
-                    self.buffer, self.bits_left_in_buffer
-                );
-                return Ok(consumed);
-            }
-        }
-        // debug!("{} {} {} {}", consumed, self.writeout, self.goalsbyte, nbytes);
-        // assert!(self.goalsbyte - self.writeout == nbytes);
-        while consumed < nbytes {
-            let searchvalue = self.buffer >> self.shift;
-            let pos = self.rbv.rank1(searchvalue + 1) as usize - 1;
-            let (sym, length) = self.table[pos];
-            // debug!("{} {:?} {}", consumed, buf, sym);
-            buf[consumed] = sym;
+            buf[consumed] = self.decode_symbol()? as u8;
             consumed += 1;
-            self.writeout += 1;
-            self.buffer <<= length;
-            self.bits_left_in_buffer += length;
+        }
+        if let Some(crc) = self.checksum.as_mut() {
+            crc.update(&buf[..consumed]);
+        }
+        if self.writeout == self.goalsbyte {
+            self.verify_checksum()?;
         }
         Ok(consumed)
     }
 }
 
-pub fn prepare_lookup(bt: &BTreeMap<usize, (u8, u8)>) -> (Vec<(u8, u8)>, RsDict) {
+pub fn prepare_lookup(bt: &BTreeMap<usize, (u16, u8)>) -> Result<(Vec<(u16, u8)>, RsDict)> {
     debug!("Btree from encoder: {:?}", bt);
-    let table: Vec<(u8, u8)> = bt.values().cloned().collect();
+    let table: Vec<(u16, u8)> = bt.values().cloned().collect();
     let keys: Vec<usize> = bt.keys().cloned().collect();
-    let m: usize = keys[keys.len() - 1];
+    let m: usize = match keys.last() {
+        Some(&m) => m,
+        None => return Err(HuffmanError::InvalidCode("Empty code table".to_string())),
+    };
     let mut bv: BitVector<u64> = BitVector::with_fill(m as u64 + 1, false);
     for k in keys {
         bv.set_bit(k as u64, true);
@@ -151,26 +288,80 @@ pub fn prepare_lookup(bt: &BTreeMap<usize, (u8, u8)>) -> (Vec<(u8, u8)>, RsDict)
         jbv.push(bit);
     }
 
-    (table, jbv)
+    Ok((table, jbv))
+}
+
+/// Check that the left-aligned codewords in `bt` can be looked up with
+/// `sentinel` bits.
+pub fn validate_lookup(bt: &BTreeMap<usize, (u16, u8)>, sentinel: usize) -> Result<()> {
+    if sentinel == 0 || sentinel > MAX_SENTINEL {
+        return Err(HuffmanError::InvalidCode(format!(
+            "Unsupported sentinel {}",
+            sentinel
+        )));
+    }
+    if bt.is_empty() {
+        return Err(HuffmanError::InvalidCode("Empty code table".to_string()));
+    }
+    for (&codeword, &(sym, length)) in bt.iter() {
+        if length == 0 || length as usize > sentinel {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Symbol {} has codeword length {}",
+                sym, length
+            )));
+        }
+        if codeword >> sentinel > 0 {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Codeword {:b} of symbol {} exceeds the sentinel",
+                codeword, sym
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Find the symbol and codeword length for the next `sentinel` bits of the
+/// stream via a rank query. Superseded by `DecodeTable::lookup()`.
+#[inline]
+pub fn rank_lookup(rbv: &RsDict, table: &[(u16, u8)], searchvalue: u64) -> Result<(u16, u8)> {
+    match (rbv.rank1(searchvalue + 1) as usize).checked_sub(1) {
+        Some(pos) => Ok(table[pos]),
+        None => Err(HuffmanError::InvalidCode(format!(
+            "No codeword found for {:b}",
+            searchvalue
+        ))),
+    }
 }
 
-pub fn search_key_or_next_small_key(tree: &BTreeMap<usize, (u8, u8)>, key: usize) -> (u8, u8) {
+pub fn search_key_or_next_small_key(
+    tree: &BTreeMap<usize, (u16, u8)>,
+    key: usize,
+) -> Result<(u16, u8)> {
     let mut iter = tree.range(..key + 1);
 
     if let Some((_, v)) = iter.next_back() {
-        *v
+        Ok(*v)
     } else {
-        panic!("Panic!!!!")
+        Err(HuffmanError::InvalidCode(format!(
+            "No codeword found for {:b}",
+            key
+        )))
     }
 }
 
-pub fn read(data: &[u8], model: &impl Model, goalsbyte: usize) -> Vec<u8> {
+pub fn read(data: &[u8], model: &impl Model, goalsbyte: usize) -> Result<Vec<u8>> {
+    if model.alphabet_size() > 256 {
+        return Err(HuffmanError::InvalidCode(
+            "Symbols do not fit into bytes".to_string(),
+        ));
+    }
     let mut buffer: u64 = 0;
     let mut bits_left_in_buffer = 64u8;
     let bt = model.to_btreemap();
     debug!("{:?}", &bt);
-    let (table, rbv) = prepare_lookup(&model.to_btreemap());
     let s = model.sentinel();
+    validate_lookup(&bt, s)?;
+    let table = DecodeTable::new(&bt, s)?;
     let shift = 64 - s;
     let mut result: Vec<u8> = Vec::with_capacity(data.len());
     let mut writeout = 0;
@@ -186,9 +377,8 @@ pub fn read(data: &[u8], model: &impl Model, goalsbyte: usize) -> Vec<u8> {
         // buffer filled
         while (64 - bits_left_in_buffer) as usize >= s {
             let searchvalue = buffer >> shift;
-            let pos = rbv.rank1(searchvalue + 1) as usize - 1;
-            let (sym, length) = table[pos];
-            result.push(sym);
+            let (sym, length) = table.lookup(searchvalue)?;
+            result.push(sym as u8);
             // let s = result[writeout];
             // let exp = origin[writeout];
             // if s != exp {
@@ -218,23 +408,26 @@ pub fn read(data: &[u8], model: &impl Model, goalsbyte: usize) -> Vec<u8> {
     // consume bits in buffer
     while goalsbyte > writeout {
         let searchvalue = buffer >> shift;
-        let pos = rbv.rank1(searchvalue + 1) as usize - 1;
-        let (sym, length) = table[pos];
-        result.push(sym);
+        let (sym, length) = table.lookup(searchvalue)?;
+        if length > 64 - bits_left_in_buffer {
+            return Err(HuffmanError::TruncatedStream);
+        }
+        result.push(sym as u8);
         writeout += 1;
         // let (sym,length) = search_key_or_next_small_key(&bt, searchvalue as usize);
         // result.push(sym);
         buffer <<= length;
         bits_left_in_buffer += length;
     }
-    result
+    Ok(result)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::huffman::encode::{calculate_length, Encoder};
-    use crate::huffman::Huffman;
+    use crate::huffman::{Huffman, MAX_CODE_LENGTH};
+    use crate::model::MAX_ALPHABET_SIZE;
     use std::io::{Cursor, Write};
 
     #[test]
@@ -247,13 +440,13 @@ mod tests {
             codewords[*word as usize] = *word as usize;
             length[*word as usize] = calculate_length(*word as usize);
         }
-        let h = Huffman::new(codewords, length);
+        let h = Huffman::new(codewords.to_vec(), length.to_vec());
         let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
 
         // Encode `words`
         enc.write(&words).expect("");
         enc.flush().expect("");
-        let decoded_words = read(enc.inner.get_ref(), &h, enc.readbytes);
+        let decoded_words = read(enc.inner.get_ref(), &h, enc.readbytes).unwrap();
         assert_eq!(words.as_slice(), decoded_words.as_slice());
     }
 
@@ -264,7 +457,7 @@ mod tests {
         for i in 0..words.len() {
             histogram[i] = words[i] as usize;
         }
-        let h = Huffman::from_histogram(&histogram);
+        let h = Huffman::from_histogram(&histogram, MAX_CODE_LENGTH).unwrap();
         let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
 
         // Encode `words`
@@ -273,7 +466,243 @@ mod tests {
         ];
         enc.write(&origin).expect("");
         enc.flush().expect("");
-        let decoded_words = read(enc.inner.get_ref(), &h, enc.readbytes);
+        let decoded_words = read(enc.inner.get_ref(), &h, enc.readbytes).unwrap();
         assert_eq!(origin.as_slice(), decoded_words.as_slice());
     }
+
+    #[test]
+    fn decode_length_limited_codes() {
+        let mut histogram = [1usize; 256];
+        let (mut a, mut b) = (1usize, 1usize);
+        for count in histogram.iter_mut().take(60) {
+            *count = a;
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+        let origin: Vec<u8> = (0..=255u8).chain((0..=59u8).rev()).collect();
+        for &max_length in [11, 12, 15].iter() {
+            let h = Huffman::from_histogram(&histogram, max_length).unwrap();
+            assert_eq!(h.sentinel(), max_length);
+            let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
+            enc.write(&origin).expect("");
+            enc.flush().expect("");
+            let decoded_words = read(enc.inner.get_ref(), &h, enc.readbytes).unwrap();
+            assert_eq!(origin, decoded_words);
+        }
+    }
+
+    #[test]
+    fn decode_with_multi_symbol_table() {
+        let words: Vec<u8> = vec![200, 40, 10, 3, 2, 2, 2, 1, 1, 1];
+        let mut histogram = [0usize; 256];
+        for i in 0..words.len() {
+            histogram[i] = words[i] as usize;
+        }
+        let h = Huffman::from_histogram(&histogram, MAX_CODE_LENGTH).unwrap();
+        let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
+        let origin: Vec<u8> = (0..5000usize)
+            .map(|i| match i % 23 {
+                0 => 9,
+                1 | 2 => 1,
+                3 => 4,
+                _ => 0,
+            })
+            .collect();
+        enc.write(&origin).expect("");
+        enc.flush().expect("");
+
+        for &blocksize in [1, 3, 4, 7, 4096].iter() {
+            let mut decoder = Decoder::new(Cursor::new(enc.inner.get_ref()), &enc).unwrap();
+            decoder.use_multi_symbol_table(true);
+            let mut buf = vec![0u8; blocksize];
+            let mut decoded = Vec::new();
+            loop {
+                let nbytes = decoder.read(&mut buf).unwrap();
+                if nbytes == 0 {
+                    break;
+                }
+                decoded.extend_from_slice(&buf[..nbytes]);
+            }
+            assert_eq!(origin, decoded);
+        }
+    }
+
+    #[test]
+    fn decode_with_checksum() {
+        let origin = "This is a lovely text in a big world. ".repeat(20).into_bytes();
+        let h = Huffman::from_slice(&origin);
+        let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
+        enc.use_checksum(true);
+        enc.write(&origin).expect("");
+        enc.flush().expect("");
+        let crc = enc.checksum().unwrap();
+        assert_eq!(crc, crate::checksum::crc32(&origin));
+        let mut encoded = enc.inner.get_ref().clone();
+        encoded.extend_from_slice(&crc.to_be_bytes());
+
+        let decode = |data: &[u8], blocksize: usize| {
+            let mut decoder = Decoder::new(Cursor::new(data), &enc).unwrap();
+            decoder.use_checksum(true);
+            let mut buf = vec![0u8; blocksize];
+            let mut decoded = Vec::new();
+            loop {
+                let nbytes = decoder.read(&mut buf)?;
+                if nbytes == 0 {
+                    return Ok(decoded);
+                }
+                decoded.extend_from_slice(&buf[..nbytes]);
+            }
+        };
+        for &blocksize in [1, 7, 4096].iter() {
+            assert_eq!(decode(&encoded, blocksize).unwrap(), origin);
+        }
+        let mut corrupt = encoded.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0x01;
+        let err: std::io::Error = decode(&corrupt, 4096).unwrap_err();
+        assert!(matches!(
+            HuffmanError::from(err),
+            HuffmanError::ChecksumMismatch { .. }
+        ));
+        let err = decode(&encoded[..encoded.len() - 1], 4096).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    fn roundtrip_symbols(symbols: &[u16], alphabet_size: usize) {
+        let h = Huffman::from_symbols(symbols, alphabet_size).unwrap();
+        assert_eq!(h.alphabet_size(), alphabet_size);
+        let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
+        enc.use_checksum(true);
+        enc.write_symbols(symbols).unwrap();
+        enc.flush().unwrap();
+        let mut encoded = enc.inner.get_ref().clone();
+        encoded.extend_from_slice(&enc.checksum().unwrap().to_be_bytes());
+
+        let mut decoder = Decoder::new(Cursor::new(&encoded), &enc).unwrap();
+        decoder.use_checksum(true);
+        let mut buf = vec![0u16; 1000];
+        let mut decoded = Vec::new();
+        loop {
+            let n = decoder.read_symbols(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(decoded, symbols);
+
+        // Bytes can not hold the symbols
+        let mut decoder = Decoder::new(Cursor::new(&encoded), &enc).unwrap();
+        assert_eq!(
+            decoder.read(&mut [0u8; 4]).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn decode_large_alphabets() {
+        // Literal/length alphabet
+        let symbols: Vec<u16> = (0..20_000usize)
+            .map(|i| {
+                if i % 3 == 0 {
+                    256 + (i % 30) as u16
+                } else {
+                    (i * 7 % 256) as u16
+                }
+            })
+            .collect();
+        roundtrip_symbols(&symbols, 300);
+
+        // Quantized sensor values
+        let symbols: Vec<u16> = (0..50_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 16) as u16 / (1 + (i % 5) as u16))
+            .collect();
+        roundtrip_symbols(&symbols, MAX_ALPHABET_SIZE);
+    }
+
+    #[test]
+    fn encode_symbol_outside_alphabet() {
+        let h = Huffman::from_symbols(&[0, 1, 2], 3).unwrap();
+        let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
+        assert!(enc.write_symbols(&[3]).is_err());
+        assert!(enc.write(&[200]).is_err());
+    }
+
+    #[test]
+    fn symbols_with_raw_bits() {
+        let symbols: Vec<u16> = (0..2000u16).map(|i| i % 7 + i % 3).collect();
+        let h = Huffman::from_symbols(&symbols, 300).unwrap();
+        let mut enc = Encoder::new(Vec::new(), &h);
+        for (i, &sym) in symbols.iter().enumerate() {
+            enc.write_symbols(&[sym]).unwrap();
+            enc.write_bits(i & ((1 << (i % 33)) - 1), i % 33).unwrap();
+        }
+        assert!(enc.write_bits(4, 2).is_err());
+        enc.flush().unwrap();
+
+        let mut decoder = Decoder::with_model(enc.inner.as_slice(), &h, symbols.len()).unwrap();
+        let mut sym = [0u16; 1];
+        for (i, &expected) in symbols.iter().enumerate() {
+            assert_eq!(decoder.read_symbols(&mut sym).unwrap(), 1);
+            assert_eq!(sym[0], expected);
+            assert_eq!(decoder.read_bits(i % 33).unwrap(), i & ((1 << (i % 33)) - 1));
+        }
+        assert!(matches!(
+            decoder.read_bits(32),
+            Err(HuffmanError::TruncatedStream)
+        ));
+    }
+
+    #[test]
+    fn decode_truncated_stream() {
+        let words: Vec<u8> = vec![20, 17, 6, 3, 2, 2, 2, 1, 1, 1];
+        let mut histogram = [0usize; 256];
+        for i in 0..words.len() {
+            histogram[i] = words[i] as usize;
+        }
+        let h = Huffman::from_histogram(&histogram, MAX_CODE_LENGTH).unwrap();
+        let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
+        let origin: Vec<u8> = vec![9; 100];
+        enc.write(&origin).expect("");
+        enc.flush().expect("");
+        let encoded = enc.inner.get_ref();
+
+        let result = read(&encoded[..encoded.len() / 2], &h, enc.readbytes);
+        assert!(matches!(result, Err(HuffmanError::TruncatedStream)));
+
+        let mut decoder = Decoder::new(Cursor::new(&encoded[..encoded.len() / 2]), &enc).unwrap();
+        let mut decoded = Vec::new();
+        let err = decoder.read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn validate_invalid_tables() {
+        let mut bt = BTreeMap::new();
+        assert!(validate_lookup(&bt, 2).is_err());
+        bt.insert(0, (1, 1));
+        bt.insert(2, (2, 2));
+        assert!(validate_lookup(&bt, 2).is_ok());
+        assert!(validate_lookup(&bt, 0).is_err());
+        assert!(validate_lookup(&bt, MAX_SENTINEL + 1).is_err());
+        // Codeword outside of the sentinel
+        bt.insert(4, (3, 2));
+        assert!(validate_lookup(&bt, 2).is_err());
+        // Codeword longer than the sentinel
+        bt.clear();
+        bt.insert(0, (2, 3));
+        assert!(validate_lookup(&bt, 2).is_err());
+    }
+
+    #[test]
+    fn decode_without_matching_codeword() {
+        let mut codewords = [0usize; 256];
+        let mut length = [0usize; 256];
+        codewords[1] = 1;
+        length[1] = 1;
+        let h = Huffman::new(codewords.to_vec(), length.to_vec());
+        let result = read(&[0, 0], &h, 4);
+        assert!(matches!(result, Err(HuffmanError::InvalidCode(_))));
+    }
 }
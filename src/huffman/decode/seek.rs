@@ -0,0 +1,295 @@
+//! Random access into compressed files via sync points
+//!
+//! The `SeekableDecoder` implements `Seek` for files written with sync
+//! points, see `huffman::sync`. A seek only moves the position. The next
+//! `read` starts decoding at the nearest sync point before the position and
+//! discards the symbols up to the position. Reading forward within the same
+//! sync interval continues with the current decoder.
+//!
+//! Files without sync points can be read as well, but every seek backwards
+//! decodes from the start of the data.
+use super::Decoder;
+use crate::error::{HuffmanError, Result};
+use crate::filter::Filter;
+use crate::format::{Preamble, FLAG_ADAPTIVE, FLAG_BLOCKS, FLAG_CONTEXT, FLAG_SYNC_POINTS};
+use crate::huffman::sync::SyncTable;
+use crate::huffman::Huffman;
+use crate::transform::Transform;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+
+pub struct SeekableDecoder<R: Read + Seek> {
+    reader: Option<R>,
+    decoder: Option<Decoder<R>>,
+    model: Huffman,
+    table: SyncTable,
+    /// Start of the encoded data in the file
+    start: u64,
+    /// Size of the decoded data
+    size: u64,
+    /// Position of the next byte returned by `read`
+    position: u64,
+    /// Position of the next byte returned by `decoder`
+    decoded: u64,
+}
+
+impl<R: Read + Seek> SeekableDecoder<R> {
+    /// Read the preamble and the sync points of a compressed file
+    pub fn new(mut reader: R) -> Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let preamble = Preamble::read_from(&mut reader)?;
+        if preamble.flags & FLAG_BLOCKS != 0 {
+            return Err(HuffmanError::InvalidCode(
+                "Random access into block files is not supported".to_string(),
+            ));
+        }
+        if preamble.flags & FLAG_ADAPTIVE != 0 {
+            return Err(HuffmanError::InvalidCode(
+                "Random access into adaptive files is not supported".to_string(),
+            ));
+        }
+        if preamble.flags & FLAG_CONTEXT != 0 {
+            return Err(HuffmanError::InvalidCode(
+                "Random access into context files is not supported".to_string(),
+            ));
+        }
+        let header = preamble.parse_header()?;
+        if header.filter != Filter::None {
+            return Err(HuffmanError::InvalidCode(
+                "Random access into filtered files is not supported".to_string(),
+            ));
+        }
+        if header.transform != Transform::None {
+            return Err(HuffmanError::InvalidCode(
+                "Random access into transformed files is not supported".to_string(),
+            ));
+        }
+        let start = reader.stream_position()?;
+        let size = header.readbytes as u64;
+        let table = if preamble.flags & FLAG_SYNC_POINTS != 0 {
+            SyncTable::load(&mut reader, start, size)?
+        } else {
+            SyncTable::default()
+        };
+        let model = header.to_model()?;
+        if size > 0 {
+            // Every `restart` builds a decoder with the model
+            Decoder::with_model(std::io::empty(), &model, 0)?;
+        }
+        Ok(SeekableDecoder {
+            reader: Some(reader),
+            decoder: None,
+            model,
+            table,
+            start,
+            size,
+            position: 0,
+            decoded: 0,
+        })
+    }
+
+    /// Size of the decoded data
+    pub fn len(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Drop the current decoder and get the reader back
+    fn reset(&mut self) {
+        if let Some(decoder) = self.decoder.take() {
+            self.reader = Some(decoder.into_inner());
+        }
+    }
+
+    /// Start a new decoder at the nearest sync point before `position`. The
+    /// reader is kept on errors, e.g. for sync points past the end of a
+    /// truncated file.
+    fn restart(&mut self) -> Result<()> {
+        self.reset();
+        let point = self.table.nearest(self.position);
+        let reader = self.reader.as_mut().expect("Reader is owned by the decoder");
+        reader.seek(SeekFrom::Start(self.start + point.bit_offset / 8))?;
+        let reader = self.reader.take().expect("Reader is owned by the decoder");
+        let goalsbyte = (self.size - point.position) as usize;
+        let mut decoder = Decoder::with_model(reader, &self.model, goalsbyte)
+            .expect("Model was checked by new");
+        if let Err(err) = decoder.skip_bits((point.bit_offset % 8) as u8) {
+            self.reader = Some(decoder.into_inner());
+            return Err(err);
+        }
+        self.decoder = Some(decoder);
+        self.decoded = point.position;
+        Ok(())
+    }
+
+    /// Decode and discard the symbols up to `position`
+    fn skip_to_position(&mut self) -> Result<()> {
+        let mut scratch = [0u8; 4096];
+        let decoder = self.decoder.as_mut().expect("Decoder was started");
+        while self.decoded < self.position {
+            let n = (self.position - self.decoded).min(scratch.len() as u64) as usize;
+            let n = decoder.read(&mut scratch[..n])?;
+            if n == 0 {
+                return Err(HuffmanError::TruncatedStream);
+            }
+            self.decoded += n as u64;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for SeekableDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position >= self.size {
+            return Ok(0);
+        }
+        let behind = self.decoded > self.position;
+        let next_point = self.table.nearest(self.position).position;
+        if self.decoder.is_none() || behind || next_point > self.decoded {
+            self.restart()?;
+        }
+        self.skip_to_position()?;
+        let decoder = self.decoder.as_mut().expect("Decoder was started");
+        let n = decoder.read(buf)?;
+        self.position += n as u64;
+        self.decoded += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for SeekableDecoder<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        match position {
+            Some(position) => {
+                self.position = position;
+                Ok(position)
+            }
+            None => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::FLAG_CONTENT_CHECKSUM;
+    use crate::huffman::header::Header;
+    use crate::huffman::encode::Encoder;
+    use std::io::{Cursor, Write};
+
+    fn compress(data: &[u8], interval: usize) -> Vec<u8> {
+        let h = Huffman::from_slice(data);
+        let mut enc = Encoder::new(Vec::new(), &h);
+        enc.use_checksum(true);
+        enc.use_sync_points(interval);
+        let _output_bytes = enc.write(data).unwrap();
+        enc.flush().unwrap();
+        let mut header = Header::from(&enc);
+        header.update_readbytes(data.len() as u64);
+        let flags = FLAG_CONTENT_CHECKSUM | if interval > 0 { FLAG_SYNC_POINTS } else { 0 };
+        let mut file = Vec::new();
+        Preamble::new(flags, header.to_binary().unwrap())
+            .write_to(&mut file)
+            .unwrap();
+        file.extend_from_slice(&enc.inner);
+        file.extend_from_slice(&enc.checksum().unwrap().to_be_bytes());
+        if interval > 0 {
+            enc.sync_table.write_to(&mut file).unwrap();
+        }
+        file
+    }
+
+    fn sample() -> Vec<u8> {
+        (0..20_000usize)
+            .map(|i| b"This is a lovely text in a big world. "[i % 38] ^ (i / 1000) as u8)
+            .collect()
+    }
+
+    fn read_range(decoder: &mut SeekableDecoder<Cursor<Vec<u8>>>, pos: SeekFrom, n: usize) -> Vec<u8> {
+        decoder.seek(pos).unwrap();
+        let mut buf = vec![0u8; n];
+        let mut filled = 0;
+        while filled < n {
+            let read = decoder.read(&mut buf[filled..]).unwrap();
+            if read == 0 {
+                break;
+            }
+            filled += read;
+        }
+        buf.truncate(filled);
+        buf
+    }
+
+    #[test]
+    fn random_access() {
+        let data = sample();
+        for &interval in [0, 1, 7, 1000, 100_000].iter() {
+            let file = compress(&data, interval);
+            let mut decoder = SeekableDecoder::new(Cursor::new(file)).unwrap();
+            assert_eq!(decoder.len(), data.len() as u64);
+            for &(start, n) in [(12_345, 100), (0, 10), (19_990, 100), (999, 2), (5000, 3000)].iter() {
+                let expected = &data[start..(start + n).min(data.len())];
+                assert_eq!(read_range(&mut decoder, SeekFrom::Start(start as u64), n), expected);
+            }
+            assert_eq!(read_range(&mut decoder, SeekFrom::End(-5), 10), &data[data.len() - 5..]);
+            decoder.seek(SeekFrom::Start(100)).unwrap();
+            assert_eq!(read_range(&mut decoder, SeekFrom::Current(-50), 5), &data[50..55]);
+            assert!(decoder.seek(SeekFrom::Current(-1000)).is_err());
+
+            let mut all = Vec::new();
+            decoder.seek(SeekFrom::Start(0)).unwrap();
+            decoder.read_to_end(&mut all).unwrap();
+            assert_eq!(all, data);
+        }
+    }
+
+    #[test]
+    fn truncated_data() {
+        let data = sample();
+        let mut decoder = SeekableDecoder::new(Cursor::new(compress(&data, 1000))).unwrap();
+        let start = decoder.start as usize;
+        let points: Vec<u64> = decoder.table.points.iter().map(|p| p.position).collect();
+        let file = decoder.reader.as_mut().unwrap().get_mut();
+        file.truncate(start + 100);
+        // The sync points lie past the end of the data
+        let mut buf = [0u8; 10];
+        for &position in points.iter() {
+            decoder.seek(SeekFrom::Start(position)).unwrap();
+            assert!(decoder.read(&mut buf).is_err());
+            assert!(decoder.read(&mut buf).is_err());
+        }
+    }
+
+    #[test]
+    fn sync_points_are_recorded() {
+        let data = sample();
+        let h = Huffman::from_slice(&data);
+        let mut enc = Encoder::new(Vec::new(), &h);
+        enc.use_sync_points(1000);
+        let _output_bytes = enc.write(&data[..7000]).unwrap();
+        let _output_bytes = enc.write(&data[7000..]).unwrap();
+        enc.flush().unwrap();
+        let points = &enc.sync_table.points;
+        assert_eq!(points.len(), 19);
+        for (ix, point) in points.iter().enumerate() {
+            assert_eq!(point.position, (ix as u64 + 1) * 1000);
+            // Decoding from the sync point yields the remaining data
+            let mut decoder =
+                Decoder::with_model(&enc.inner[(point.bit_offset / 8) as usize..], &h, 10).unwrap();
+            decoder.skip_bits((point.bit_offset % 8) as u8).unwrap();
+            let mut buf = [0u8; 10];
+            decoder.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf, &data[point.position as usize..point.position as usize + 10]);
+        }
+    }
+}
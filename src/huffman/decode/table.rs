@@ -0,0 +1,294 @@
+//! Direct lookup table for decoding
+//!
+//! Instead of searching for the next smaller codeword via a rank query, the
+//! next `bits` bits of the stream are used as an index into the `primary`
+//! table. Each entry holds the decoded symbol and the length of its codeword.
+//! Codewords longer than `bits` share their primary entry with other long
+//! codewords. This entry links into a second-level table, which is indexed by
+//! the following bits of the stream.
+//!
+//! The lookup returns the same result as the rank based lookup, i.e. the
+//! symbol of the largest left-aligned codeword which is less than or equal to
+//! the search value.
+//!
+//! For skewed distributions a single lookup can decode several symbols. The
+//! `MultiDecodeTable` stores for each `bits` wide index all symbols whose
+//! codewords fit completely into the index. It is restricted to byte
+//! alphabets.
+use super::MAX_SENTINEL;
+use crate::error::{HuffmanError, Result};
+use std::collections::BTreeMap;
+
+/// Default index width of the primary table
+pub const LOOKUP_BITS: usize = 11;
+
+/// Maximum number of symbols decoded by a single lookup in a `MultiDecodeTable`
+pub const MAX_SYMBOLS: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Entry {
+    /// No codeword is less than or equal to the search value
+    Invalid,
+    /// Decoded symbol and length of its codeword
+    Symbol(u16, u8),
+    /// Offset and index width of a second-level table
+    Link(u32, u8),
+}
+
+#[derive(Debug, Clone)]
+pub struct DecodeTable {
+    sentinel: usize,
+    bits: usize,
+    primary: Vec<Entry>,
+    secondary: Vec<Entry>,
+}
+
+impl DecodeTable {
+    /// Build the lookup table for left-aligned codewords of `sentinel` bits
+    /// with a primary table of `LOOKUP_BITS` bits.
+    pub fn new(bt: &BTreeMap<usize, (u16, u8)>, sentinel: usize) -> Result<Self> {
+        DecodeTable::with_bits(bt, sentinel, LOOKUP_BITS)
+    }
+
+    /// Build the lookup table with a primary table of `bits` bits. If the
+    /// sentinel is smaller, the primary table is indexed by `sentinel` bits
+    /// and no second-level tables are needed.
+    pub fn with_bits(bt: &BTreeMap<usize, (u16, u8)>, sentinel: usize, bits: usize) -> Result<Self> {
+        if bt.is_empty() {
+            return Err(HuffmanError::InvalidCode("Empty code table".to_string()));
+        }
+        if sentinel == 0 || sentinel > MAX_SENTINEL || bits == 0 {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Unsupported sentinel {} for {} lookup bits",
+                sentinel, bits
+            )));
+        }
+        let bits = bits.min(sentinel);
+        // Number of search values covered by one primary entry
+        let slot = 1u64 << (sentinel - bits);
+        let entry_for = |value: u64| match bt.range(..=value as usize).next_back() {
+            Some((_, &(sym, length))) => Entry::Symbol(sym, length),
+            None => Entry::Invalid,
+        };
+
+        let mut primary = Vec::with_capacity(1 << bits);
+        let mut secondary = Vec::new();
+        for index in 0..1u64 << bits {
+            let start = index * slot;
+            let end = start + slot;
+            // Codewords starting within the slot need a second-level table.
+            // Its width is given by the finest alignment of these codewords.
+            let finest = bt
+                .range(start as usize + 1..end as usize)
+                .map(|(&codeword, _)| (codeword as u64 - start).trailing_zeros() as usize)
+                .min();
+            match finest {
+                None => primary.push(entry_for(start)),
+                Some(zeros) => {
+                    let width = sentinel - bits - zeros;
+                    primary.push(Entry::Link(secondary.len() as u32, width as u8));
+                    for sub in 0..1u64 << width {
+                        secondary.push(entry_for(start + (sub << zeros)));
+                    }
+                }
+            }
+        }
+        Ok(DecodeTable {
+            sentinel,
+            bits,
+            primary,
+            secondary,
+        })
+    }
+
+    /// Find the symbol and codeword length for the next `sentinel` bits of
+    /// the stream.
+    #[inline]
+    pub fn lookup(&self, searchvalue: u64) -> Result<(u16, u8)> {
+        let rest = self.sentinel - self.bits;
+        match self.primary[(searchvalue >> rest) as usize] {
+            Entry::Symbol(sym, length) => Ok((sym, length)),
+            Entry::Link(offset, width) => {
+                let index = (searchvalue >> (rest - width as usize)) & ((1 << width) - 1);
+                match self.secondary[offset as usize + index as usize] {
+                    Entry::Symbol(sym, length) => Ok((sym, length)),
+                    _ => Err(invalid_codeword(searchvalue)),
+                }
+            }
+            Entry::Invalid => Err(invalid_codeword(searchvalue)),
+        }
+    }
+
+    /// Number of entries in the primary and second-level tables
+    pub fn len(&self) -> usize {
+        self.primary.len() + self.secondary.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Entry of the `MultiDecodeTable`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MultiEntry {
+    /// Decoded symbols, only the first `count` are valid
+    pub symbols: [u8; MAX_SYMBOLS],
+    /// Number of decoded symbols. Zero if the first codeword is longer than
+    /// the index width.
+    pub count: u8,
+    /// Sum of the codeword lengths of all decoded symbols
+    pub length: u8,
+}
+
+#[derive(Debug, Clone)]
+pub struct MultiDecodeTable {
+    bits: usize,
+    entries: Vec<MultiEntry>,
+}
+
+impl MultiDecodeTable {
+    /// Build a table indexed by `LOOKUP_BITS` bits from a single-symbol table
+    pub fn new(table: &DecodeTable) -> Self {
+        MultiDecodeTable::with_bits(table, LOOKUP_BITS)
+    }
+
+    /// Build a table indexed by `bits` bits from a single-symbol table.
+    /// This assumes that the table describes a prefix code of bytes.
+    pub fn with_bits(table: &DecodeTable, bits: usize) -> Self {
+        let bits = bits.clamp(1, 32);
+        let mut entries = Vec::with_capacity(1 << bits);
+        for index in 0..1u64 << bits {
+            let mut entry = MultiEntry::default();
+            while (entry.count as usize) < MAX_SYMBOLS {
+                let used = entry.length as usize;
+                // Remaining bits of the index, left-aligned to the sentinel
+                let rest = (index << used) & ((1 << bits) - 1);
+                let searchvalue = if table.sentinel >= bits {
+                    rest << (table.sentinel - bits)
+                } else {
+                    rest >> (bits - table.sentinel)
+                };
+                match table.lookup(searchvalue) {
+                    Ok((sym, length)) if used + length as usize <= bits => {
+                        entry.symbols[entry.count as usize] = sym as u8;
+                        entry.count += 1;
+                        entry.length += length;
+                    }
+                    _ => break,
+                }
+            }
+            entries.push(entry);
+        }
+        MultiDecodeTable { bits, entries }
+    }
+
+    /// Find the symbols for the MSB of `window`
+    #[inline]
+    pub fn lookup(&self, window: u64) -> MultiEntry {
+        self.entries[(window >> (64 - self.bits)) as usize]
+    }
+
+    /// Index width of the table
+    pub fn bits(&self) -> usize {
+        self.bits
+    }
+}
+
+fn invalid_codeword(searchvalue: u64) -> HuffmanError {
+    HuffmanError::InvalidCode(format!("No codeword found for {:b}", searchvalue))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman::decode::{prepare_lookup, rank_lookup};
+    use crate::huffman::Huffman;
+    use crate::model::Model;
+
+    fn assert_same_as_rank(bt: &BTreeMap<usize, (u16, u8)>, sentinel: usize, bits: usize) {
+        let table = DecodeTable::with_bits(bt, sentinel, bits).unwrap();
+        let (t, rbv) = prepare_lookup(bt).unwrap();
+        for searchvalue in 0..1u64 << sentinel {
+            match rank_lookup(&rbv, &t, searchvalue) {
+                Ok(expected) => assert_eq!(table.lookup(searchvalue).unwrap(), expected),
+                Err(_) => assert!(table.lookup(searchvalue).is_err()),
+            }
+        }
+    }
+
+    #[test]
+    fn lookup_equals_rank_lookup() {
+        let words: Vec<usize> = vec![20, 17, 6, 3, 2, 2, 2, 1, 1, 1];
+        let mut histogram = [0usize; 256];
+        histogram[..words.len()].copy_from_slice(&words);
+        let h = Huffman::from_histogram(&histogram, 20).unwrap();
+        for &bits in [1, 2, 3, 4, 6, 11].iter() {
+            assert_same_as_rank(&h.to_btreemap(), h.sentinel(), bits);
+        }
+    }
+
+    #[test]
+    fn lookup_full_alphabet() {
+        let mut histogram = [1usize; 256];
+        let (mut a, mut b) = (1usize, 1usize);
+        for count in histogram.iter_mut().take(40) {
+            *count = a;
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+        let h = Huffman::from_histogram(&histogram, 16).unwrap();
+        assert_eq!(h.sentinel(), 16);
+        let table = DecodeTable::new(&h.to_btreemap(), h.sentinel()).unwrap();
+        assert!(table.len() > 1 << LOOKUP_BITS);
+        assert_same_as_rank(&h.to_btreemap(), h.sentinel(), LOOKUP_BITS);
+    }
+
+    #[test]
+    fn lookup_without_zero_codeword() {
+        let mut bt = BTreeMap::new();
+        bt.insert(5, (3, 2));
+        bt.insert(13, (4, 3));
+        assert_same_as_rank(&bt, 4, 2);
+        let table = DecodeTable::with_bits(&bt, 4, 2).unwrap();
+        assert!(table.lookup(0).is_err());
+        assert_eq!(table.lookup(15).unwrap(), (4, 3));
+    }
+
+    #[test]
+    fn multi_symbol_entries() {
+        // a = 0, b = 10, c = 11
+        let mut bt = BTreeMap::new();
+        bt.insert(0b00, (b'a' as u16, 1));
+        bt.insert(0b10, (b'b' as u16, 2));
+        bt.insert(0b11, (b'c' as u16, 2));
+        let table = DecodeTable::new(&bt, 2).unwrap();
+        let multi = MultiDecodeTable::with_bits(&table, 5);
+        let entry = multi.lookup(0);
+        assert_eq!(entry.count as usize, MAX_SYMBOLS);
+        assert_eq!(entry.symbols, [b'a'; 4]);
+        assert_eq!(entry.length, 4);
+        let entry = multi.lookup(0b10110 << 59);
+        assert_eq!(&entry.symbols[..entry.count as usize], b"bca");
+        assert_eq!(entry.length, 5);
+        // Last codeword does not fit into the index
+        let entry = multi.lookup(0b01101 << 59);
+        assert_eq!(&entry.symbols[..entry.count as usize], b"aca");
+        assert_eq!(entry.length, 4);
+    }
+
+    #[test]
+    fn multi_symbol_long_codewords() {
+        let mut bt = BTreeMap::new();
+        bt.insert(0, (1, 3));
+        bt.insert(1 << 5, (2, 3));
+        bt.insert(1 << 6, (3, 2));
+        bt.insert(1 << 7, (4, 1));
+        let table = DecodeTable::new(&bt, 8).unwrap();
+        let multi = MultiDecodeTable::with_bits(&table, 2);
+        assert_eq!(multi.lookup(0).count, 0);
+        let entry = multi.lookup(0b11 << 62);
+        assert_eq!(&entry.symbols[..entry.count as usize], &[4, 4]);
+    }
+}
@@ -1,4 +1,4 @@
-//! New decoding method for Huffman encoded data
+//! Decoding method for Huffman encoded data based on a vault
 //!
 //! # Inner workings of the `Decoder`
 //! The main elements of the `Decoder` are the `buffer`, `vault`, and
@@ -7,21 +7,20 @@
 //! This decoding process returns the number of bits evaluated (`cut`)
 //! and the decoded symbol. Afterwards, the `cut` MSB from the buffer will be
 //! removed. Next, the `cut` LSB from the buffer will be filled via the `cut`
-//! MSB from the `vault`. The current symbol will be written at the end of
-//! the vault. This may cause the vault to overfill. Should the vault be
-//! close to overfilling, values from the buffer are decoded into the
-//! `_reserve`. This causes the `vault` to be emptied, since the buffer gets
-//! refilled with the `vault`.
+//! MSB from the `vault`. Should the vault run empty, it is refilled with whole
+//! bytes from the inner reader and the remaining LSB of the buffer are filled
+//! from the refilled vault.
+//!
+//! The buffer is therefore always full, unless the inner reader is exhausted.
+//! A codeword can be as long as the buffer itself, even if its bits were
+//! spread over the buffer and the vault before the previous symbol was cut.
 
 use log::debug;
-use std::collections::LinkedList;
-use succinct::rsdict::RsDict;
+use crate::error::{HuffmanError, Result};
 use crate::model::Model;
-use succinct::rank::BitRankSupport;
-use crate::huffman::decode::prepare_lookup;
-
-const MAX_VAULT: u64 = 52;
-const MIN_VAULT: u64 = 16;
+use crate::huffman::decode::table::DecodeTable;
+use crate::huffman::decode::validate_lookup;
+use std::io::{ErrorKind, Read};
 
 /// The Decoder<I> struct decodes iterable data structures
 #[derive(Debug)]
@@ -31,185 +30,117 @@ pub struct Decoder<R> {
     vault: u64,
     sentinel: u64,
     remaining_outputbytes: u64,
-    rbv: RsDict,
-    table: Vec<(u8,u8)>,
-    _reserve: LinkedList<u8>,
+    table: DecodeTable,
     _vaultstatus: u64,
     _bufferstatus: u64,
 }
 
-fn initiate_buffer<R: Read>(reader: &mut R) -> (u64, u64) {
-    let mut result = 0u64;
-    let mut buf: [u8;8] = [0;8];
-    let nbytes = reader.read(&mut buf).expect("Cannot read");
-
-    for i in 0..nbytes {
-        result += (buf[i] as u64) << (56 - i * 8)
+/// The `n` MSB of `value` as LSB
+fn msb(value: u64, n: u64) -> u64 {
+    if n == 0 {
+        0
+    } else {
+        value >> (64 - n)
     }
-    (result, nbytes as u64)
-}
-
-fn initiate_sentinel(sentinel: u64) -> u64 {
-    // TODO Remove constraint
-    assert!(sentinel <= 8);
-    sentinel
 }
 
-fn initiate_reserve() -> LinkedList<u8> {
-    LinkedList::<u8>::new()
+/// Shift `value` by `n` bits to the left, dropping all bits for `n >= 64`
+fn shl(value: u64, n: u64) -> u64 {
+    if n >= 64 {
+        0
+    } else {
+        value << n
+    }
 }
 
-use std::io::Read;
-
 impl<R: Read> Decoder<R> {
-    pub fn new<M: Model>(mut reader: R, model: &M, output: u64) -> Self {
-        let (buffer, bufferstatus) = initiate_buffer(&mut reader);
-        Decoder {
-            buffer: buffer,
+    pub fn new<M: Model>(reader: R, model: &M, output: u64) -> Result<Self> {
+        if model.alphabet_size() > 256 {
+            return Err(HuffmanError::InvalidCode(
+                "Symbols do not fit into bytes".to_string(),
+            ));
+        }
+        let sentinel = model.sentinel() as u64;
+        let bt = model.to_btreemap();
+        validate_lookup(&bt, sentinel as usize)?;
+        let table = DecodeTable::new(&bt, sentinel as usize)?;
+        let mut decoder = Decoder {
             inner: reader,
-            _vaultstatus: 0,
-            _bufferstatus: bufferstatus,
+            buffer: 0,
             vault: 0,
-            sentinel: initiate_sentinel(model.sentinel() as u64),
-            _reserve: initiate_reserve(),
+            sentinel,
             remaining_outputbytes: output,
-            // TODO Move rbv and table into own struct and trait for better overview
-            rbv: {
-                let (_, v) = prepare_lookup(&model.to_btreemap());
-                v
-            },
-            table: {
-                let (t, _) = prepare_lookup(&model.to_btreemap());
-                t
-            },
+            table,
+            _vaultstatus: 0,
+            _bufferstatus: 0,
+        };
+        decoder.fill_buffer()?;
+        Ok(decoder)
+    }
+    /// Refill the empty vault with whole bytes from the inner reader.
+    /// Returns the number of bytes read, which is `0` for an exhausted reader.
+    fn fill_vault(&mut self) -> Result<usize> {
+        let mut bytes = [0u8; 8];
+        let free = ((64 - self._vaultstatus) / 8) as usize;
+        let nbytes = loop {
+            match self.inner.read(&mut bytes[..free]) {
+                Ok(n) => break n,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        };
+        for byte in bytes.iter().take(nbytes) {
+            self.vault |= (*byte as u64) << (56 - self._vaultstatus);
+            self._vaultstatus += 8;
         }
+        Ok(nbytes)
     }
-    fn consume_buffer(&mut self) -> Option<u8> {
+    /// Move bits from the vault into the LSB of the buffer until the buffer
+    /// is full or the inner reader is exhausted.
+    fn fill_buffer(&mut self) -> Result<()> {
+        while self._bufferstatus < 64 {
+            if self._vaultstatus == 0 && self.fill_vault()? == 0 {
+                break;
+            }
+            let missing = 64 - self._bufferstatus;
+            let take = missing.min(self._vaultstatus);
+            self.buffer |= msb(self.vault, take) << (missing - take);
+            self.vault = shl(self.vault, take);
+            self._vaultstatus -= take;
+            self._bufferstatus += take;
+        }
         debug!(
-            "Consuming b{:064b} v{:064b} {} {}",
+            "Filled b{:064b} v{:064b} {} {}",
             self.buffer, self.vault, self._vaultstatus, self._bufferstatus
         );
-        let lookup_value = self.buffer >> (64 - self.sentinel);
-        let (cut, sym) = self.get_cut_and_symbol(lookup_value);
-        if cut as u64 > self._bufferstatus {
-            return None;
-        }
-        if cut <= self._vaultstatus as usize {
-            // normal process
-            self.buffer <<= cut;
-            self.buffer += self.vault >> (64 - cut);
-            self.vault <<= cut;
-            self._vaultstatus -= cut as u64;
-            return Some(sym);
-        } else if self._vaultstatus > 0 {
-            // TODO Same as above might be just to a min(cut,vault)
-            self.buffer <<= cut;
-            self.buffer += self.vault >> (64 - self._vaultstatus);
-            self._bufferstatus -= cut as u64 - self._vaultstatus;
-            self.vault <<= self._vaultstatus;
-            self._vaultstatus -= self._vaultstatus;
-            return Some(sym);
-        } else {
-            self.buffer <<= cut;
-            self._bufferstatus -= cut as u64;
-            return Some(sym);
-        }
+        Ok(())
     }
-    fn empty_vault(&mut self) {
-        while self._vaultstatus > MIN_VAULT {
-            let lookup_value = self.buffer >> (64 - self.sentinel);
-            let (cut, sym) = self.get_cut_and_symbol(lookup_value);
-            assert!(cut as u64 <= self._vaultstatus);
-            self.buffer <<= cut;
-            self.buffer += self.vault >> (64 - cut);
-            self.vault <<= cut;
-            self._vaultstatus -= cut as u64;
-            self._reserve.push_back(sym);
+    fn decode(&mut self) -> Result<u8> {
+        let lookup_value = self.buffer >> (64 - self.sentinel);
+        let (cut, sym) = self.get_cut_and_symbol(lookup_value)?;
+        let cut = cut as u64;
+        if cut > self._bufferstatus {
+            return Err(HuffmanError::TruncatedStream);
         }
+        self.buffer = shl(self.buffer, cut);
+        self._bufferstatus -= cut;
+        self.fill_buffer()?;
+        Ok(sym)
     }
-    fn get_cut_and_symbol(&mut self, val: u64) -> (usize, u8) {
-        let pos = self.rbv.rank1(val + 1) as usize - 1;
-        let (sym, length) = self.table[pos];
+    fn get_cut_and_symbol(&mut self, val: u64) -> Result<(usize, u8)> {
+        let (sym, length) = self.table.lookup(val)?;
         debug!("Cut {} Symbol {:b}", length, sym);
-        (length as usize, sym)
-    }
-}
-
-impl<R: Read> Decoder<R> {
-    fn decode(&mut self, symbol: Option<u8>) -> Option<u8> {
-        if self.remaining_outputbytes == 0 {
-            debug!("Finished decoding");
-            debug!(
-                "Buffer {:064b} Vault {:064b}",
-                self.buffer, self.vault
-            );
-            return None;
-        }
-        if let Some(val) = symbol {
-            // Inner data source still not empty
-            debug!(
-                "Buffer {:064b} Read byte {:08b} {:?}",
-                self.buffer, val, self._reserve
-            );
-
-            // Check vault fill
-            if self._vaultstatus > MAX_VAULT {
-                self.empty_vault();
-                println!("Reserve {:?}", self._reserve)
-            };
-
-            // TODO Starting here a lot of overlap with empty_vault()
-            // move value to vault
-            println!("{:?} {:?}", self.vault, self._vaultstatus);
-            self.vault += (val as u64) << (64 - self._vaultstatus - 8);
-            self._vaultstatus += 8;
-
-            // decode word
-            let lookup_value = self.buffer >> (64 - self.sentinel);
-            let (cut, sym) = self.get_cut_and_symbol(lookup_value);
-            assert!(cut as u64 <= self._vaultstatus);
-
-            // fill buffer from vault
-            self.buffer <<= cut;
-            self.buffer += self.vault >> (64 - cut);
-
-            // update vault
-            self.vault <<= cut;
-            self._vaultstatus -= cut as u64;
-
-            // TODO Might be optimised using .or_else()
-            match self._reserve.pop_front() {
-                Some(from_reserve) => {
-                    self._reserve.push_back(sym);
-                    self.remaining_outputbytes -= 1;
-                    return Some(from_reserve);
-                }
-                None => {
-                    self.remaining_outputbytes -= 1;
-                    return Some(sym);
-                }
-            }
-        } else if let Some(reserve) = self._reserve.pop_front() {
-            // Inner data source empty. First output reserve
-            self.remaining_outputbytes -= 1;
-            return Some(reserve);
-        } else {
-            // Finish output by consuming buffer
-            self.remaining_outputbytes -= 1;
-            self.consume_buffer()
-        }
+        Ok((length as usize, sym as u8))
     }
 }
 
 impl<R: Read> Read for Decoder<R> {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let nbytes = buf.len().min(self.remaining_outputbytes as usize);
-        if nbytes != 0 {
-            self.inner.read(&mut buf[..nbytes]).unwrap();
-            for i in 0..nbytes {
-                buf[i] = self.decode(Some(buf[i])).unwrap()
-            }
+        for byte in buf.iter_mut().take(nbytes) {
+            *byte = self.decode()?;
         }
+        self.remaining_outputbytes -= nbytes as u64;
         Ok(nbytes)
     }
 }
@@ -217,25 +148,65 @@ impl<R: Read> Read for Decoder<R> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::huffman::Huffman;
+    use crate::huffman::{Huffman, MAX_CODE_LENGTH};
+    use crate::huffman::decode::MAX_SENTINEL;
     use crate::huffman::encode::Encoder;
     use std::io::{Cursor, Write};
 
+    fn encode_bytes(data: &[u8], h: &Huffman) -> Vec<u8> {
+        let mut enc = Encoder::new(Cursor::new(Vec::new()), h);
+        let _output_bytes = enc.write(data).expect("");
+        enc.flush().expect("");
+        enc.inner.into_inner()
+    }
+
     fn encode_str(sentence: &str) -> (Vec<u8>, Vec<u8>, Huffman){
         let data = sentence.as_bytes().to_vec();
         let h = Huffman::from_slice(data.as_slice());
-
-        let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
-        let _output_bytes = enc.write(&data).expect("");
-        enc.flush().expect("");
-        let encoded_data : Vec<u8> = enc.inner.get_ref().iter().map(|&x| x).collect();
+        let encoded_data = encode_bytes(&data, &h);
         (data, encoded_data, h)
     }
 
+    /// Histogram with Fibonacci distributed counts for the first 60 bytes.
+    /// All 256 bytes occur, resulting in codewords of up to `max_code_length`.
+    fn skewed_full_alphabet(max_code_length: usize) -> (Vec<u8>, Huffman) {
+        let mut histogram = [1usize; 256];
+        let (mut a, mut b) = (1usize, 1usize);
+        for count in histogram.iter_mut().take(60) {
+            *count = a;
+            let next = a + b;
+            a = b;
+            b = next;
+        }
+        let h = Huffman::from_histogram(&histogram, max_code_length).unwrap();
+        let mut data: Vec<u8> = (0..=255u8).collect();
+        for i in 0..2000usize {
+            data.push((i * 7 % 256) as u8);
+            data.push((59 - i % 60) as u8);
+            data.push(255 - (i % 13) as u8);
+        }
+        (data, h)
+    }
+
+    fn roundtrip_bytes(data: &[u8], h: &Huffman, blocksize: usize) {
+        let encoded_data = encode_bytes(data, h);
+        let mut decoder = Decoder::new(Cursor::new(encoded_data), h, data.len() as u64).unwrap();
+        let mut decoded_data = vec![0u8; blocksize];
+        let mut result = Vec::with_capacity(data.len());
+        loop {
+            let nbytes = decoder.read(&mut decoded_data).unwrap();
+            if nbytes == 0 {
+                break;
+            }
+            result.extend_from_slice(&decoded_data[..nbytes]);
+        }
+        assert_eq!(data, result.as_slice());
+    }
+
     fn roundtrip_decode_blockwise(sentence: &str, blocksize: usize) {
         let (data, encoded_data, h) = encode_str(sentence);
         println!("Encoded {:?} ({}) [{}]", encoded_data, sentence, sentence.len());
-        let mut decoder = Decoder::new(Cursor::new(encoded_data), &h, data.len() as u64);
+        let mut decoder = Decoder::new(Cursor::new(encoded_data), &h, data.len() as u64).unwrap();
         let mut decoded_data = vec![0u8; blocksize];
         let mut nbytes = 1;
         let mut iteration = 0;
@@ -252,7 +223,7 @@ mod tests {
     fn roundtrip_decode_at_once(sentence: &str) {
         let (data, encoded_data, h) = encode_str(sentence);
         println!("Encoded {:?} ({})", encoded_data, sentence);
-        let mut decoder = Decoder::new(Cursor::new(encoded_data), &h, data.len() as u64);
+        let mut decoder = Decoder::new(Cursor::new(encoded_data), &h, data.len() as u64).unwrap();
         let mut decoded_data: Vec<u8> = Vec::new();
 
         let nbytes = decoder.read_to_end(&mut decoded_data).unwrap();
@@ -275,4 +246,36 @@ mod tests {
         roundtrip_decode_at_once("aaafaaaaaaaaa");
         roundtrip_decode_at_once("aaaaaa");
     }
+
+    #[test]
+    fn roundtrip_full_alphabet_uniform() {
+        let data: Vec<u8> = (0..=255u8).cycle().take(256 * 20).collect();
+        let h = Huffman::from_slice(&data);
+        assert_eq!(h.sentinel(), 8);
+        for &blocksize in [1, 7, 4096].iter() {
+            roundtrip_bytes(&data, &h, blocksize);
+        }
+    }
+
+    #[test]
+    fn roundtrip_full_alphabet_skewed() {
+        for &max_code_length in [11, 15, MAX_CODE_LENGTH, MAX_SENTINEL].iter() {
+            let (data, h) = skewed_full_alphabet(max_code_length);
+            assert_eq!(h.sentinel(), max_code_length);
+            for &blocksize in [1, 7, 4096].iter() {
+                roundtrip_bytes(&data, &h, blocksize);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_stream() {
+        let (data, h) = skewed_full_alphabet(MAX_CODE_LENGTH);
+        let encoded_data = encode_bytes(&data, &h);
+        let truncated = &encoded_data[..encoded_data.len() / 2];
+        let mut decoder = Decoder::new(Cursor::new(truncated), &h, data.len() as u64).unwrap();
+        let mut decoded_data = Vec::new();
+        let err = decoder.read_to_end(&mut decoded_data).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
 }
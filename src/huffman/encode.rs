@@ -12,6 +12,8 @@
 //! The actual output is only written on disk as soon as it has enough bits set,
 //! that it looses no unused bits.
 
+use super::sync::{SyncPoint, SyncTable};
+use crate::checksum::Crc32;
 use crate::model::Model;
 use log::debug;
 use std::io::{Error, ErrorKind, Write};
@@ -31,6 +33,9 @@ pub struct Encoder<'a, W: Write, M: Model> {
     pub fillbits: Option<u8>,
     pub readbytes: usize,
     pub writeout: usize,
+    checksum: Option<Crc32>,
+    sync_interval: Option<usize>,
+    pub sync_table: SyncTable,
 }
 
 // TODO: Let readbytes be externally defined and a u64 since Readbytes must be set before encoding, since it is needed for header
@@ -45,11 +50,31 @@ impl<'a, W: Write, M: Model> Encoder<'a, W, M> {
             fillbits: None,
             readbytes: 0,
             writeout: 0,
+            checksum: None,
+            sync_interval: None,
+            sync_table: SyncTable::default(),
         }
     }
+    /// Calculate the CRC-32 of all data passed to `write`
+    pub fn use_checksum(&mut self, enabled: bool) {
+        self.checksum = if enabled { Some(Crc32::new()) } else { None };
+    }
+    /// Record a sync point every `interval` symbols in `sync_table`.
+    /// An interval of `0` disables the sync points.
+    pub fn use_sync_points(&mut self, interval: usize) {
+        self.sync_interval = if interval > 0 { Some(interval) } else { None };
+    }
+    /// Offset in bits of the next codeword from the start of the encoded data
+    fn bit_offset(&self) -> u64 {
+        self.writeout as u64 * 8 + (64 - self.remaining_bits) as u64
+    }
+    /// CRC-32 of the data written so far, if enabled via `use_checksum`
+    pub fn checksum(&self) -> Option<u32> {
+        self.checksum.map(|crc| crc.finalize())
+    }
     // Define magic bytes for encoder
     pub fn magic(&self) -> Vec<u8> {
-        "pzhf".as_bytes().to_vec()
+        crate::format::MAGIC.to_vec()
     }
     pub fn plain_write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
         self.inner.write_all(bytes)
@@ -81,6 +106,74 @@ impl<'a, W: Write, M: Model> Encoder<'a, W, M> {
         self.remaining_bits += 40;
         Ok(no)
     }
+    /// Encode symbols of an alphabet with up to `MAX_ALPHABET_SIZE` symbols.
+    /// The checksum covers the symbols as 2 byte big endian values.
+    pub fn write_symbols(&mut self, symbols: &[u16]) -> std::io::Result<()> {
+        for &sym in symbols.iter() {
+            if let Some(crc) = self.checksum.as_mut() {
+                crc.update(&sym.to_be_bytes());
+            }
+            self.encode_symbol(sym as usize)?;
+        }
+        Ok(())
+    }
+    /// Append the codeword of `sym` to the buffer. Returns the number of
+    /// bytes written to the inner writer.
+    fn encode_symbol(&mut self, sym: usize) -> std::io::Result<usize> {
+        if sym >= self.model.alphabet_size() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Symbol {} is not part of the alphabet", sym),
+            ));
+        }
+        if let Some(interval) = self.sync_interval {
+            if self.readbytes > 0 && self.readbytes.is_multiple_of(interval) {
+                self.sync_table.points.push(SyncPoint {
+                    bit_offset: self.bit_offset(),
+                    position: self.readbytes as u64,
+                });
+            }
+        }
+        self.readbytes += 1;
+        let (code, codelen) = self.model.encode(sym);
+        debug!(
+            "Encode: Symbol {}({0:b}) @ {1} -> {2} ({2:b})",
+            sym,
+            self.readbytes - 1,
+            code
+        );
+        if codelen > 64 {
+            return Err(Error::new(ErrorKind::InvalidData, "Codelen > 64"));
+        }
+        self.pack(code, codelen)
+    }
+    /// Append `count` raw bits of `value`, e.g. extra bits following a
+    /// symbol. The bits are neither counted in `readbytes` nor covered by the
+    /// checksum.
+    pub fn write_bits(&mut self, value: usize, count: usize) -> std::io::Result<()> {
+        if count > 32 || (value as u64) >> count != 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} does not fit into {} bits", value, count),
+            ));
+        }
+        self.pack(value, count)?;
+        Ok(())
+    }
+    /// Append `codelen` bits of `code` to the buffer. Returns the number of
+    /// bytes written to the inner writer.
+    fn pack(&mut self, code: usize, codelen: usize) -> std::io::Result<usize> {
+        let mut writeout = 0usize;
+        while codelen > self.remaining_bits {
+            writeout += self.put()?;
+        }
+        self.remaining_bits -= codelen;
+        self.update_buffer(code);
+        if self.buffer & 0x0000_0000_00FF_0000 > 0 {
+            writeout += self.cleanup()?;
+        }
+        Ok(writeout)
+    }
     fn update_buffer(&mut self, code: usize) {
         self.buffer += (code << self.remaining_bits) as u64;
         debug!("New Buffer: {:b}", self.buffer);
@@ -90,26 +183,11 @@ impl<'a, W: Write, M: Model> Encoder<'a, W, M> {
 impl<'a, W: Write, M: Model> Write for Encoder<'a, W, M> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
         let mut writeout = 0usize;
+        if let Some(crc) = self.checksum.as_mut() {
+            crc.update(buf);
+        }
         for sym in buf.iter() {
-            self.readbytes += 1;
-            let (code, codelen) = self.model.encode(*sym);
-            debug!(
-                "Encode: Byte {}({0:b}) @ {1} -> {2} ({2:b})",
-                sym,
-                self.readbytes - 1,
-                code
-            );
-            if codelen > 64 {
-                return Err(Error::new(ErrorKind::InvalidData, "Codelen > 64"));
-            }
-            while codelen > self.remaining_bits {
-                writeout += self.put()?;
-            }
-            self.remaining_bits -= codelen;
-            self.update_buffer(code);
-            if self.buffer & 0x0000_0000_00FF_0000 > 0 {
-                writeout += self.cleanup()?;
-            }
+            writeout += self.encode_symbol(*sym as usize)?;
         }
 
         Ok(writeout + 8 - (self.buffer.trailing_zeros() as usize / 8))
@@ -164,7 +242,7 @@ mod tests {
             codewords[*word as usize] = *word as usize;
             length[*word as usize] = calculate_length(*word as usize);
         }
-        let h = Huffman::new(codewords, length);
+        let h = Huffman::new(codewords.to_vec(), length.to_vec());
         let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
         let output_bytes = enc.write(&words).expect("");
         enc.flush().expect("");
@@ -195,7 +273,7 @@ mod tests {
         length[0] = calculate_length(0);
         length[1] = calculate_length(3);
         length[2] = calculate_length(342);
-        let h = Huffman::new(codewords, length);
+        let h = Huffman::new(codewords.to_vec(), length.to_vec());
         let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
         let output_bytes = enc.write(&[0, 1, 2]).expect("");
         enc.flush().expect("");
@@ -1,17 +1,41 @@
 //! Module for definition of the header file for Huffman Encoding
 //! The header file are information needed to concstruct a proper Decoder.
 //! The decoder can then be created using the `from_header` method.
-use bincode::{deserialize, serialize};
+//!
+//! Since the codewords are canonical, only the codeword length of every byte
+//! is stored. The decoder rebuilds the codewords via `Huffman::from_lengths`.
+//!
+//! # Binary layout
+//! - 1 byte length of the magic, followed by the magic bytes
+//! - `readbytes` as LEB128 varint
+//! - Run-length encoded codeword lengths of all 256 bytes. Each run is a
+//!   token byte `length << 3 | (run - 1)`. If the lower three bits are all
+//!   set, the next byte holds `run - 8`.
+//! - Optional descriptor of the filter applied to the data, see `filter`
+//! - Optional descriptor of the transform of the data, see `transform`
+use crate::error::{HuffmanError, Result};
+use crate::filter::{self, Filter};
+use crate::format::MAGIC;
+use crate::huffman::Huffman;
+use crate::transform::Transform;
 use log::info;
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
 
-#[derive(Debug, Deserialize, Serialize, PartialEq, Eq)]
+/// Runs of up to this length fit into a single token byte
+const SHORT_RUN: usize = 7;
+
+/// Longest run of a token with an extra byte
+const MAX_RUN: usize = SHORT_RUN + 1 + u8::MAX as usize;
+
+#[derive(Debug, PartialEq, Eq)]
 pub struct Header {
     pub magic: Vec<u8>,
-    pub btree: BTreeMap<usize, (u8, u8)>,
-    pub sentinel: usize,
+    /// Codeword length of every byte, `0` for bytes which do not occur
+    pub lengths: Vec<u8>,
     pub readbytes: usize,
+    /// Filter applied to the data before the encoding
+    pub filter: Filter,
+    /// Transform replacing the plain coding of the data
+    pub transform: Transform,
 }
 
 use super::encode::Encoder;
@@ -20,59 +44,255 @@ use std::convert::From;
 use std::io::Write;
 
 impl<'a, W: Write, M: Model> From<&Encoder<'a, W, M>> for Header {
+    /// The model must assign canonical codewords, see `canonical_codewords`.
     fn from(enc: &Encoder<'a, W, M>) -> Self {
         Header {
             magic: enc.magic(),
-            btree: enc.model.to_btreemap(),
-            sentinel: enc.model.sentinel(),
+            lengths: model_lengths(enc.model),
             readbytes: enc.readbytes,
+            filter: Filter::None,
+            transform: Transform::None,
         }
     }
 }
 
-/// TODO Add meta information to the file:
-/// - 4 byte magic
-/// - 8 byte header size
-/// - x byte header
-/// - x byte data
 impl Header {
-    pub fn to_binary(&self) -> Vec<u8> {
-        let result = serialize(&self).unwrap();
+    /// Header without a table, for data whose tables are stored elsewhere or
+    /// which is not coded with a static table
+    pub fn empty(readbytes: usize, filter: Filter, transform: Transform) -> Self {
+        Header {
+            magic: MAGIC.to_vec(),
+            lengths: vec![0u8; 256],
+            readbytes,
+            filter,
+            transform,
+        }
+    }
+    pub fn to_binary(&self) -> Result<Vec<u8>> {
+        if self.magic.len() > u8::MAX as usize {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Can not serialise {} magic bytes",
+                self.magic.len()
+            )));
+        }
+        let mut result = Vec::with_capacity(self.magic.len() + 16);
+        result.push(self.magic.len() as u8);
+        result.extend_from_slice(&self.magic);
+
+        let mut readbytes = self.readbytes as u64;
+        while readbytes >= 0x80 {
+            result.push((readbytes as u8 & 0x7F) | 0x80);
+            readbytes >>= 7;
+        }
+        result.push(readbytes as u8);
+
+        encode_lengths(&self.lengths, &mut result)?;
+        self.filter.to_binary(&mut result);
+        self.transform.to_binary(&mut result);
         info!("Header serialisation size: {} bytes", result.len());
-        result
+        Ok(result)
     }
-    pub fn from_binary(vec: &[u8]) -> Self {
-        deserialize(vec).unwrap()
+    pub fn from_binary(vec: &[u8]) -> Result<Self> {
+        let mut bytes = vec.iter().cloned();
+        let magic_length = next_byte(&mut bytes, "magic length")?;
+        let magic = (0..magic_length)
+            .map(|_| next_byte(&mut bytes, "magic"))
+            .collect::<Result<Vec<u8>>>()?;
+
+        let mut readbytes = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = next_byte(&mut bytes, "number of bytes")?;
+            readbytes |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if shift + 7 >= 64 {
+                return Err(HuffmanError::CorruptHeader(
+                    "Number of bytes overflows".to_string(),
+                ));
+            }
+        }
+
+        let lengths = decode_lengths(&mut bytes)?;
+        let mut bytes = bytes.peekable();
+        let filter = match bytes.peek() {
+            Some(&filter::DELTA) => Filter::from_binary(&mut bytes)?,
+            _ => Filter::None,
+        };
+        let transform = match bytes.peek() {
+            Some(_) => Transform::from_binary(&mut bytes)?,
+            None => Transform::None,
+        };
+        if bytes.next().is_some() {
+            return Err(HuffmanError::CorruptHeader(
+                "Trailing bytes after the transform".to_string(),
+            ));
+        }
+        Ok(Header {
+            magic,
+            lengths,
+            readbytes: readbytes as usize,
+            filter,
+            transform,
+        })
     }
     pub fn update_readbytes(&mut self, bytes: u64) {
         self.readbytes = bytes as usize
     }
+    /// Rebuild the canonical codewords stored in the header
+    pub fn to_model(&self) -> Result<Huffman> {
+        Huffman::from_lengths(&self.lengths)
+    }
+}
+
+/// Codeword length of every symbol of the model, `0` for symbols without
+/// codeword
+pub fn model_lengths<M: Model>(model: &M) -> Vec<u8> {
+    let mut lengths = vec![0u8; model.alphabet_size()];
+    for (sym, length) in model.to_btreemap().values() {
+        lengths[*sym as usize] = *length;
+    }
+    lengths
+}
+
+/// Append the run-length encoded codeword lengths of all 256 bytes
+pub fn encode_lengths(lengths: &[u8], result: &mut Vec<u8>) -> Result<()> {
+    if lengths.len() != 256 {
+        return Err(HuffmanError::CorruptHeader(format!(
+            "Can not serialise {} lengths",
+            lengths.len()
+        )));
+    }
+    encode_alphabet_lengths(lengths, result)
+}
+
+/// Append the run-length encoded codeword lengths of an alphabet of any
+/// size. The size itself is not stored.
+pub fn encode_alphabet_lengths(lengths: &[u8], result: &mut Vec<u8>) -> Result<()> {
+    let mut ix = 0;
+    while ix < lengths.len() {
+        let length = lengths[ix];
+        if length > 0x1F {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Codeword length {} does not fit into the header",
+                length
+            )));
+        }
+        let run = lengths[ix..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&l| l == length)
+            .count();
+        if run <= SHORT_RUN {
+            result.push(length << 3 | (run - 1) as u8);
+        } else {
+            result.push(length << 3 | SHORT_RUN as u8);
+            result.push((run - SHORT_RUN - 1) as u8);
+        }
+        ix += run;
+    }
+    Ok(())
+}
+
+/// Read the run-length encoded codeword lengths of all 256 bytes
+pub fn decode_lengths<I: Iterator<Item = u8>>(bytes: &mut I) -> Result<Vec<u8>> {
+    decode_alphabet_lengths(bytes, 256)
+}
+
+/// Read the run-length encoded codeword lengths of `alphabet_size` symbols
+pub fn decode_alphabet_lengths<I: Iterator<Item = u8>>(
+    bytes: &mut I,
+    alphabet_size: usize,
+) -> Result<Vec<u8>> {
+    let mut lengths = Vec::with_capacity(alphabet_size);
+    while lengths.len() < alphabet_size {
+        let token = next_byte(bytes, "codeword lengths")?;
+        let mut run = (token & SHORT_RUN as u8) as usize + 1;
+        if run > SHORT_RUN {
+            run += next_byte(bytes, "run length")? as usize;
+        }
+        if lengths.len() + run > alphabet_size {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "More than {} codeword lengths",
+                alphabet_size
+            )));
+        }
+        lengths.resize(lengths.len() + run, token >> 3);
+    }
+    Ok(lengths)
+}
+
+fn next_byte<I: Iterator<Item = u8>>(bytes: &mut I, what: &str) -> Result<u8> {
+    bytes
+        .next()
+        .ok_or_else(|| HuffmanError::CorruptHeader(format!("Missing {}", what)))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::huffman::encode::{calculate_length, Encoder};
+    use crate::huffman::encode::Encoder;
     use crate::huffman::Huffman;
     use std::io::Cursor;
 
     #[test]
     fn serialisation_roundtrip() {
-        // Generate Huffman Encoder
-        let words: Vec<u8> = vec![177, 112, 84, 143, 148, 195, 165, 206, 34, 10];
-        let mut codewords = [0usize; 256];
-        let mut length = [0usize; 256];
-        for word in words.iter() {
-            codewords[*word as usize] = *word as usize;
-            length[*word as usize] = calculate_length(*word as usize);
-        }
-        let h = Huffman::new(codewords, length);
-        let enc = Encoder::new(Cursor::new(Vec::new()), &h);
+        let data = b"This is a lovely text in a big world".to_vec();
+        let h = Huffman::from_slice(&data);
+        let mut enc = Encoder::new(Cursor::new(Vec::new()), &h);
+        enc.readbytes = data.len();
 
         let head = Header::from(&enc);
-        let temp = head.to_binary();
-        let new_head = Header::from_binary(&temp);
+        let temp = head.to_binary().unwrap();
+        let new_head = Header::from_binary(&temp).unwrap();
+        assert_eq!(new_head, head);
+        assert!(temp.len() < 64, "Header too large: {} bytes", temp.len());
+
+        // The canonical codewords are rebuilt from the lengths alone
+        let model = new_head.to_model().unwrap();
+        assert_eq!(model.to_btreemap(), h.to_btreemap());
+    }
 
-        assert_eq!(new_head, head)
+    #[test]
+    fn serialisation_of_long_runs_and_large_sizes() {
+        for &readbytes in [0usize, 127, 128, 1 << 40, usize::MAX].iter() {
+            let head = Header {
+                magic: b"pzhf".to_vec(),
+                lengths: (0..=255u8).map(|b| if b < 200 { 8 } else { b % 3 }).collect(),
+                readbytes,
+                filter: if readbytes % 2 == 0 {
+                    Filter::delta(4, true).unwrap()
+                } else {
+                    Filter::None
+                },
+                transform: if readbytes % 3 == 0 {
+                    Transform::block_sorting(900_000).unwrap()
+                } else {
+                    Transform::None
+                },
+            };
+            let temp = head.to_binary().unwrap();
+            assert_eq!(Header::from_binary(&temp).unwrap(), head);
+        }
+    }
+
+    #[test]
+    fn deserialisation_of_garbage() {
+        assert!(Header::from_binary(&[]).is_err());
+        assert!(Header::from_binary(&[255; 7]).is_err());
+        // Runs exceeding 256 symbols
+        assert!(Header::from_binary(&[0, 0, 0x0F, 255, 0x0F, 255]).is_err());
+        // Trailing bytes and unknown filters
+        assert!(Header::from_binary(&[0, 0, 0x07, 248, 0]).is_err());
+        assert!(Header::from_binary(&[0, 0, 0x07, 248, 1, 2, 0]).is_err());
+        assert!(Header::from_binary(&[0, 0, 0x07, 248]).is_ok());
+        let head = Header::from_binary(&[0, 0, 0x07, 248, 1, 2]).unwrap();
+        assert_eq!(head.filter, Filter::delta(2, false).unwrap());
+        let head = Header::from_binary(&[0, 0, 0x07, 248, 2, 0, 0, 1, 0]).unwrap();
+        assert_eq!(head.transform, Transform::block_sorting(256).unwrap());
+        assert!(Header::from_binary(&[0, 0, 0x07, 248, 2, 0, 0, 1, 0, 1, 2]).is_err());
+        // Oversubscribed codeword lengths
+        let head = Header::from_binary(&[0, 1, 0x0F, 248]).unwrap();
+        assert!(head.to_model().is_err());
     }
 }
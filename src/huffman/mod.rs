@@ -1,20 +1,29 @@
 //! This module packages functions needed for generating the codebase of
 //! Huffman Encoding.
-use crate::model::Model;
+use crate::model::{Model, MAX_ALPHABET_SIZE};
 use log::debug;
 
+pub mod adaptive;
+pub mod context;
 pub mod decode;
 pub mod encode;
 pub mod header;
+pub mod sync;
 
+/// Default upper bound for the length of a codeword. Must not exceed
+/// `decode::MAX_SENTINEL`, otherwise the data can not be decoded anymore.
+pub const MAX_CODE_LENGTH: usize = 20;
+
+/// Canonical Huffman code for an alphabet of up to `MAX_ALPHABET_SIZE`
+/// symbols. The alphabet size is the number of codeword lengths.
 pub struct Huffman {
-    codewords: [usize; 256],
-    length: [usize; 256],
+    codewords: Vec<usize>,
+    length: Vec<usize>,
 }
 
 impl Model for Huffman {
-    fn encode(&self, sym: u8) -> (usize, usize) {
-        (self.codewords[sym as usize], self.length[sym as usize])
+    fn encode(&self, sym: usize) -> (usize, usize) {
+        (self.codewords[sym], self.length[sym])
     }
     // The sentinel bits are set for `(1 << self.sentinel()) - 1`
     fn sentinel(&self) -> usize {
@@ -24,44 +33,110 @@ impl Model for Huffman {
             .max()
             .expect("Can not find maximum value.")
     }
-    fn to_btreemap(&self) -> BTreeMap<usize, (u8, u8)> {
-        let mut result: BTreeMap<usize, (u8, u8)> = BTreeMap::new();
+    fn to_btreemap(&self) -> BTreeMap<usize, (u16, u8)> {
+        let mut result: BTreeMap<usize, (u16, u8)> = BTreeMap::new();
+        let sentinel = self.sentinel();
         for (ix, &k) in self.length.iter().enumerate() {
             if k != 0 {
-                let codeword = self.codewords[ix] << (self.sentinel() - k);
-                let val = (ix as u8, k as u8);
+                let codeword = self.codewords[ix] << (sentinel - k);
+                let val = (ix as u16, k as u8);
                 debug!("DecodeTable: {} {}", self.codewords[ix], k);
                 result.insert(codeword, val);
             }
         }
         result
     }
+    fn alphabet_size(&self) -> usize {
+        self.length.len()
+    }
 }
 
+use crate::error::{HuffmanError, Result};
 use crate::stats::generate_histogram;
 use std::collections::BTreeMap;
 use std::io::{Read, Seek};
 impl Huffman {
-    pub fn new(codewords: [usize; 256], length: [usize; 256]) -> Self {
+    pub fn new(codewords: Vec<usize>, length: Vec<usize>) -> Self {
+        assert_eq!(codewords.len(), length.len());
+        assert!(!length.is_empty() && length.len() <= MAX_ALPHABET_SIZE);
         Huffman { codewords, length }
     }
-    pub fn from_histogram(histogram: &[usize; 256]) -> Self {
-        let (codewords, length) = generate_extended_codewords(histogram);
-        Huffman::new(codewords, length)
+    /// Generate codewords from a histogram. The alphabet size is the length
+    /// of the histogram. No codeword will be longer than `max_code_length`
+    /// bits.
+    pub fn from_histogram(histogram: &[usize], max_code_length: usize) -> Result<Self> {
+        if histogram.is_empty() || histogram.len() > MAX_ALPHABET_SIZE {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Unsupported alphabet size {}",
+                histogram.len()
+            )));
+        }
+        let (codewords, length) = generate_extended_codewords(histogram, max_code_length)?;
+        Ok(Huffman::new(codewords, length))
     }
-    pub fn from_reader<RS: Seek+Read>(reader: &mut RS) -> Self {
-        let histogram = generate_histogram(reader);
-        reader
-        .seek(std::io::SeekFrom::Start(0))
-        .expect("Can not move to start of file");
-        Huffman::from_histogram(&histogram)
+    /// Rebuild the canonical codewords from the codeword lengths of all
+    /// symbols, e.g. the 256 bytes stored in the header. Lengths which do not
+    /// describe a prefix code, or which are too long for the decoder, are
+    /// rejected.
+    pub fn from_lengths(lengths: &[u8]) -> Result<Self> {
+        if lengths.is_empty() || lengths.len() > MAX_ALPHABET_SIZE {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Unsupported alphabet size {}",
+                lengths.len()
+            )));
+        }
+        let mut length = vec![0usize; lengths.len()];
+        // Kraft sum scaled by 2^MAX_SENTINEL
+        let mut kraft = 0usize;
+        for (l, &k) in length.iter_mut().zip(lengths.iter()) {
+            if k as usize > decode::MAX_SENTINEL {
+                return Err(HuffmanError::InvalidCode(format!(
+                    "Codeword length {} exceeds {} bits",
+                    k,
+                    decode::MAX_SENTINEL
+                )));
+            }
+            if k > 0 {
+                kraft += 1 << (decode::MAX_SENTINEL - k as usize);
+            }
+            *l = k as usize;
+        }
+        if kraft > 1 << decode::MAX_SENTINEL {
+            return Err(HuffmanError::InvalidCode(
+                "Codeword lengths are oversubscribed".to_string(),
+            ));
+        }
+        Ok(Huffman::new(canonical_codewords(&length), length))
+    }
+    pub fn from_reader<RS: Seek+Read>(reader: &mut RS) -> Result<Self> {
+        let histogram = generate_histogram(reader)?;
+        reader.seek(std::io::SeekFrom::Start(0))?;
+        Huffman::from_histogram(&histogram, MAX_CODE_LENGTH)
     }
     pub fn from_slice(data: &[u8]) -> Self {
         let mut histogram = [0usize; 256];
         for value in data {
             histogram[*value as usize] += 1
         }
-        Huffman::from_histogram(&histogram)
+        Huffman::from_histogram(&histogram, MAX_CODE_LENGTH)
+            .expect("All bytes fit into MAX_CODE_LENGTH bits")
+    }
+    /// Generate codewords for the symbols `0..alphabet_size` from their
+    /// frequencies in `data`
+    pub fn from_symbols(data: &[u16], alphabet_size: usize) -> Result<Self> {
+        let mut histogram = vec![0usize; alphabet_size];
+        for &value in data {
+            match histogram.get_mut(value as usize) {
+                Some(count) => *count += 1,
+                None => {
+                    return Err(HuffmanError::InvalidCode(format!(
+                        "Symbol {} exceeds the alphabet size {}",
+                        value, alphabet_size
+                    )))
+                }
+            }
+        }
+        Huffman::from_histogram(&histogram, MAX_CODE_LENGTH)
     }
 }
 
@@ -69,6 +144,9 @@ impl Huffman {
 /// This will transform the histogram into a codeword length array for each
 /// byte.
 pub fn calculate_codeword_length_inplace(histogram: &mut [usize]) {
+    if histogram.is_empty() {
+        return;
+    }
     let mut leaf = (histogram.len() - 1) as i32;
     let mut root = histogram.len() - 1;
 
@@ -124,10 +202,83 @@ pub fn calculate_codeword_length_inplace(histogram: &mut [usize]) {
     }
 }
 
+/// Calculate the length of the codewords for each byte in place, such that no
+/// codeword is longer than `max_length` bits.
+///
+/// Like `calculate_codeword_length_inplace` this expects the histogram to be
+/// sorted in decreasing order. If the optimal codeword lengths already fit into
+/// `max_length` bits, they are used unchanged. Otherwise the optimal
+/// length-limited codeword lengths are calculated via the package-merge
+/// algorithm of Larmore and Hirschberg.
+pub fn calculate_limited_codeword_length_inplace(
+    histogram: &mut [usize],
+    max_length: usize,
+) -> Result<()> {
+    let n = histogram.len();
+    if n > 1 && (max_length == 0 || max_length > 64 || (max_length < 64 && n > 1 << max_length)) {
+        return Err(HuffmanError::InvalidCode(format!(
+            "{} symbols do not fit into codewords of at most {} bits",
+            n, max_length
+        )));
+    }
+    let weights: Vec<usize> = histogram.iter().rev().cloned().collect();
+    calculate_codeword_length_inplace(histogram);
+    if n <= 1 || histogram[n - 1] <= max_length {
+        return Ok(());
+    }
+
+    // Package-merge: Each level contains the leaves (`false`) and the packages
+    // (`true`) built from pairs of the previous level, sorted by weight.
+    let mut levels: Vec<Vec<bool>> = Vec::with_capacity(max_length);
+    let mut current = weights.clone();
+    levels.push(vec![false; n]);
+    for _ in 1..max_length {
+        let packages: Vec<usize> = current.chunks_exact(2).map(|p| p[0] + p[1]).collect();
+        let mut merged = Vec::with_capacity(n + packages.len());
+        let mut kinds = Vec::with_capacity(n + packages.len());
+        let (mut leaf, mut package) = (0, 0);
+        while leaf < n || package < packages.len() {
+            if package >= packages.len() || (leaf < n && weights[leaf] <= packages[package]) {
+                merged.push(weights[leaf]);
+                kinds.push(false);
+                leaf += 1;
+            } else {
+                merged.push(packages[package]);
+                kinds.push(true);
+                package += 1;
+            }
+        }
+        levels.push(kinds);
+        current = merged;
+    }
+
+    // Select the `2n - 2` cheapest items of the last level. Every selected
+    // package selects the two items it was built from in the level below.
+    // Each time a leaf is selected, its codeword gets one bit longer.
+    let mut lengths = vec![0usize; n];
+    let mut select = 2 * n - 2;
+    for kinds in levels.iter().rev() {
+        let mut packages = 0;
+        let mut leaf = 0;
+        for &is_package in kinds.iter().take(select) {
+            if is_package {
+                packages += 1;
+            } else {
+                lengths[leaf] += 1;
+                leaf += 1;
+            }
+        }
+        select = 2 * packages;
+    }
+    for (target, length) in histogram.iter_mut().zip(lengths.into_iter().rev()) {
+        *target = length;
+    }
+    Ok(())
+}
+
 pub fn sort_by_value(store: &[usize]) -> Vec<(usize, usize)> {
-    assert!(store.len() <= 256);
     let mut sorted_tuple: Vec<(usize, usize)> = vec![];
-    sorted_tuple.reserve_exact(256);
+    sorted_tuple.reserve_exact(store.len());
     sorted_tuple.extend(
         store
             .iter()
@@ -153,6 +304,9 @@ pub fn extract_values(store: &[(usize, usize)]) -> Vec<usize> {
 }
 
 pub fn calculate_codewords_based_on_length(lengths: &[usize]) -> (Vec<usize>, Vec<usize>) {
+    if lengths.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
     let max_wordlen = lengths[lengths.len() - 1];
     debug!("Max word length: {}", 1 << max_wordlen);
     let mut li_small_codes: Vec<usize> = vec![0usize; lengths.len()];
@@ -170,39 +324,70 @@ pub fn calculate_codewords_based_on_length(lengths: &[usize]) -> (Vec<usize>, Ve
     (li_small_codes, li_big_codes)
 }
 
-/// Generate extended codewords from a histogram.
+/// Generate extended codewords from a histogram. Returns the codewords and
+/// their lengths for every symbol of the histogram.
 ///
 /// # Steps
 /// 1. Enumerate the histogram
 /// 2. Sort the enumerated histogram by count
 /// 3. Extract the counts of the sorted histogram
-/// 4. Calculate codeword lengths inplace
-/// 5. Generate canonical codewords based on length
-pub fn generate_extended_codewords(histogram: &[usize]) -> ([usize; 256], [usize; 256]) {
+/// 4. Calculate codeword lengths inplace, limited to `max_code_length` bits
+/// 5. Assign canonical codewords in order of (length, symbol)
+pub fn generate_extended_codewords(
+    histogram: &[usize],
+    max_code_length: usize,
+) -> Result<(Vec<usize>, Vec<usize>)> {
     // let hist = enumerate(histogram);
     let sorted_tuple = sort_by_value(&histogram);
     let mut weights = extract_values(&sorted_tuple);
-    calculate_codeword_length_inplace(&mut weights);
+    calculate_limited_codeword_length_inplace(&mut weights, max_code_length)?;
     debug!("Lengths {:?}", weights);
     if weights.len() == 1 {
         weights[0] = 1;
     }
-    let (codes, _) = calculate_codewords_based_on_length(&weights);
-    debug!("  Codes {:?}", codes);
     debug!(" Stuple {:?}", sorted_tuple);
 
-    let mut extended_codes = [0usize; 256];
-    let mut length = [0usize; 256];
-    for (ix, (code, (key, _))) in codes.into_iter().zip(sorted_tuple.into_iter()).enumerate() {
-        extended_codes[key as usize] = code;
-        let l = weights[ix];
-        length[key as usize] = l;
-        debug!(
-            "Huffman code: {0:>8b} [{0:>3}] -> {1:b} [{1:>3}] {2}",
-            key, code, l
-        );
+    let mut length = vec![0usize; histogram.len()];
+    for (ix, (key, _)) in sorted_tuple.into_iter().enumerate() {
+        length[key] = weights[ix];
+    }
+    let extended_codes = canonical_codewords(&length);
+    for (key, (&code, &l)) in extended_codes.iter().zip(length.iter()).enumerate() {
+        if l > 0 {
+            debug!(
+                "Huffman code: {0:>8b} [{0:>3}] -> {1:b} [{1:>3}] {2}",
+                key, code, l
+            );
+        }
     }
-    (extended_codes, length)
+    Ok((extended_codes, length))
+}
+
+/// Assign canonical codewords to the codeword lengths. Codewords are handed
+/// out in increasing order of (length, symbol), therefore the codewords can be
+/// reconstructed from the lengths alone. Symbols of length `0` do not get a
+/// codeword.
+pub fn canonical_codewords(length: &[usize]) -> Vec<usize> {
+    let max_length = length.iter().cloned().max().unwrap_or(0);
+    // First codeword of each length
+    let mut count = vec![0usize; max_length + 1];
+    for &l in length.iter().filter(|&&l| l > 0) {
+        count[l] += 1;
+    }
+    let mut next = vec![0usize; max_length + 1];
+    let mut code = 0usize;
+    for l in 1..=max_length {
+        code = (code + count[l - 1]) << 1;
+        next[l] = code;
+    }
+    let mut codewords = vec![0usize; length.len()];
+    for (codeword, &l) in codewords.iter_mut().zip(length.iter()) {
+        if l > 0 {
+            *codeword = next[l];
+            next[l] += 1;
+        }
+    }
+    codewords
 }
 
 #[cfg(test)]
@@ -213,7 +398,7 @@ mod tests {
     #[test]
     fn test_extended_codewords_sorted_input() {
         let histogram = vec![20, 17, 6, 3, 2, 2, 2, 1, 1, 1];
-        let (ext_codes, _) = generate_extended_codewords(&histogram);
+        let (ext_codes, _) = generate_extended_codewords(&histogram, MAX_CODE_LENGTH).unwrap();
         assert_eq!(ext_codes[0], 0);
         assert_eq!(ext_codes[1], 2);
         assert_eq!(ext_codes[2], 12);
@@ -229,6 +414,64 @@ mod tests {
         assert!(ext_codes[9] >= 30 && ext_codes[9] <= 63);
     }
 
+    #[test]
+    fn test_codewords_from_lengths() {
+        let mut histogram = [0usize; 256];
+        for (ix, &count) in [20, 17, 6, 3, 2, 2, 2, 1, 1, 1].iter().enumerate() {
+            histogram[ix * 23] = count;
+        }
+        let (codes, length) = generate_extended_codewords(&histogram, MAX_CODE_LENGTH).unwrap();
+        let lengths: Vec<u8> = length.iter().map(|&l| l as u8).collect();
+        let h = Huffman::from_lengths(&lengths).unwrap();
+        assert_eq!(h.codewords, codes);
+        assert_eq!(h.length, length);
+
+        // Single symbol
+        let mut lengths = vec![0u8; 256];
+        lengths[42] = 1;
+        let h = Huffman::from_lengths(&lengths).unwrap();
+        assert_eq!(h.encode(42), (0, 1));
+
+        // Oversubscribed, too long and unsupported number of lengths
+        assert!(Huffman::from_lengths(&[1u8; 256]).is_err());
+        lengths[42] = decode::MAX_SENTINEL as u8 + 1;
+        assert!(Huffman::from_lengths(&lengths).is_err());
+        assert!(Huffman::from_lengths(&[]).is_err());
+        assert!(Huffman::from_lengths(&vec![0u8; MAX_ALPHABET_SIZE + 1]).is_err());
+        let h = Huffman::from_lengths(&[1u8; 2]).unwrap();
+        assert_eq!(h.alphabet_size(), 2);
+        assert_eq!(h.encode(1), (1, 1));
+    }
+
+    #[test]
+    fn test_codewords_large_alphabet() {
+        let histogram: Vec<usize> = (0..300).map(|sym| 300 - sym).collect();
+        let (codes, length) = generate_extended_codewords(&histogram, MAX_CODE_LENGTH).unwrap();
+        assert_eq!(codes.len(), 300);
+        let max_length = *length.iter().max().unwrap();
+        assert_eq!(kraft_sum(&length, max_length), 1 << max_length);
+        assert!(length[0] <= length[299]);
+        let lengths: Vec<u8> = length.iter().map(|&l| l as u8).collect();
+        let h = Huffman::from_lengths(&lengths).unwrap();
+        assert_eq!(h.codewords, codes);
+        assert_eq!(h.to_btreemap().len(), 300);
+
+        let symbols: Vec<u16> = vec![0, 299, 299, 65535];
+        assert!(Huffman::from_symbols(&symbols, 300).is_err());
+        let h = Huffman::from_symbols(&symbols, MAX_ALPHABET_SIZE).unwrap();
+        assert_eq!(h.alphabet_size(), MAX_ALPHABET_SIZE);
+        assert_eq!(h.encode(299).1, 1);
+        assert_eq!(h.encode(1).1, 0);
+    }
+
+    #[test]
+    fn test_extended_codewords_empty_input() {
+        let (ext_codes, length) =
+            generate_extended_codewords(&[0usize; 256], MAX_CODE_LENGTH).unwrap();
+        assert_eq!(ext_codes.iter().sum::<usize>(), 0);
+        assert_eq!(length.iter().sum::<usize>(), 0);
+    }
+
     #[test]
     fn test_codeword_lengths_inplace() {
         let mut elements: HashMap<Vec<usize>, Vec<usize>> = HashMap::new();
@@ -245,4 +488,101 @@ mod tests {
             assert_eq!(hist, expected);
         }
     }
+
+    fn fibonacci(n: usize) -> Vec<usize> {
+        let mut fib = vec![1usize, 1];
+        while fib.len() < n {
+            fib.push(fib[fib.len() - 1] + fib[fib.len() - 2]);
+        }
+        fib.reverse();
+        fib
+    }
+
+    fn kraft_sum(lengths: &[usize], max_length: usize) -> usize {
+        lengths.iter().map(|l| 1 << (max_length - l)).sum()
+    }
+
+    fn cost(weights: &[usize], lengths: &[usize]) -> usize {
+        weights.iter().zip(lengths.iter()).map(|(w, l)| w * l).sum()
+    }
+
+    // Cheapest non-decreasing codeword lengths satisfying the Kraft inequality
+    fn brute_force_cost(weights: &[usize], max_length: usize) -> usize {
+        fn search(weights: &[usize], lengths: &mut Vec<usize>, max: usize, best: &mut usize) {
+            if lengths.len() == weights.len() {
+                if kraft_sum(lengths, max) <= 1 << max {
+                    *best = (*best).min(cost(weights, lengths));
+                }
+                return;
+            }
+            let start = *lengths.last().unwrap_or(&1);
+            for l in start..=max {
+                lengths.push(l);
+                search(weights, lengths, max, best);
+                lengths.pop();
+            }
+        }
+        let mut best = usize::MAX;
+        search(weights, &mut Vec::new(), max_length, &mut best);
+        best
+    }
+
+    #[test]
+    fn test_limited_codeword_lengths_fibonacci() {
+        let weights = fibonacci(40);
+        let mut unlimited = weights.clone();
+        calculate_codeword_length_inplace(&mut unlimited);
+        assert_eq!(unlimited[unlimited.len() - 1], 39);
+
+        for &max_length in [6, 11, 12, 15, 20].iter() {
+            let mut lengths = weights.clone();
+            calculate_limited_codeword_length_inplace(&mut lengths, max_length).unwrap();
+            assert_eq!(lengths[lengths.len() - 1], max_length);
+            assert!(lengths.windows(2).all(|w| w[0] <= w[1]));
+            assert_eq!(kraft_sum(&lengths, max_length), 1 << max_length);
+            assert!(cost(&weights, &lengths) >= cost(&weights, &unlimited));
+        }
+    }
+
+    #[test]
+    fn test_limited_codeword_lengths_optimal() {
+        for &n in [3, 5, 7].iter() {
+            let weights = fibonacci(n);
+            for max_length in 3..n {
+                let mut lengths = weights.clone();
+                calculate_limited_codeword_length_inplace(&mut lengths, max_length).unwrap();
+                assert_eq!(
+                    cost(&weights, &lengths),
+                    brute_force_cost(&weights, max_length)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_limited_codeword_lengths_unchanged() {
+        let weights = vec![20, 17, 6, 3, 2, 2, 2, 1, 1, 1];
+        let mut unlimited = weights.clone();
+        calculate_codeword_length_inplace(&mut unlimited);
+        let mut limited = weights.clone();
+        calculate_limited_codeword_length_inplace(&mut limited, 6).unwrap();
+        assert_eq!(limited, unlimited);
+
+        let mut too_short = weights.clone();
+        assert!(calculate_limited_codeword_length_inplace(&mut too_short, 3).is_err());
+    }
+
+    #[test]
+    fn test_extended_codewords_limited() {
+        let mut histogram = [1usize; 256];
+        for (count, fib) in histogram.iter_mut().zip(fibonacci(60).into_iter()) {
+            *count = fib;
+        }
+        assert!(generate_extended_codewords(&histogram, 7).is_err());
+        for &max_length in [8, 11, 12, 15].iter() {
+            let (_, length) = generate_extended_codewords(&histogram, max_length).unwrap();
+            assert_eq!(*length.iter().max().unwrap(), max_length);
+            assert_eq!(kraft_sum(&length, max_length), 1 << max_length);
+        }
+    }
 }
@@ -0,0 +1,140 @@
+//! Sync points for random access into the encoded data
+//!
+//! A sync point records the bit offset of a symbol within the encoded data
+//! and the position of the symbol in the original data. Decoding can start at
+//! any sync point, since the codewords do not depend on preceding symbols.
+//!
+//! # Layout of the table
+//! The table is stored at the very end of a file:
+//! - 16 byte per sync point, the bit offset followed by the position (8 byte
+//!   each, big endian)
+//! - 8 byte number of sync points (big endian)
+use crate::error::{HuffmanError, Result};
+use crate::format::read_exact_or_truncated;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+/// Default number of symbols between two sync points
+pub const DEFAULT_SYNC_INTERVAL: usize = 1 << 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncPoint {
+    /// Offset in bits from the start of the encoded data
+    pub bit_offset: u64,
+    /// Position of the symbol in the original data
+    pub position: u64,
+}
+
+/// All sync points of a file, sorted by position
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncTable {
+    pub points: Vec<SyncPoint>,
+}
+
+impl SyncTable {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        for point in self.points.iter() {
+            writer.write_all(&point.bit_offset.to_be_bytes())?;
+            writer.write_all(&point.position.to_be_bytes())?;
+        }
+        writer.write_all(&(self.points.len() as u64).to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Read the table from the end of a file, whose encoded data starts at
+    /// byte `start` and decodes to `symbols` bytes.
+    pub fn load<R: Read + Seek>(reader: &mut R, start: u64, symbols: u64) -> Result<Self> {
+        let corrupt = || HuffmanError::CorruptHeader("Invalid sync point table".to_string());
+        let end = reader.seek(SeekFrom::End(0))?;
+        let count_position = end.checked_sub(8).ok_or_else(corrupt)?;
+        reader.seek(SeekFrom::Start(count_position))?;
+        let mut field = [0u8; 8];
+        read_exact_or_truncated(reader, &mut field)?;
+        let count = u64::from_be_bytes(field);
+        let table_position = count
+            .checked_mul(16)
+            .and_then(|size| count_position.checked_sub(size))
+            .filter(|&position| position >= start)
+            .ok_or_else(corrupt)?;
+        reader.seek(SeekFrom::Start(table_position))?;
+
+        let mut points: Vec<SyncPoint> = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            read_exact_or_truncated(reader, &mut field)?;
+            let bit_offset = u64::from_be_bytes(field);
+            read_exact_or_truncated(reader, &mut field)?;
+            let position = u64::from_be_bytes(field);
+            let ordered = match points.last() {
+                Some(last) => last.position < position && last.bit_offset < bit_offset,
+                None => true,
+            };
+            let inside = position < symbols.max(1)
+                && bit_offset / 8 < table_position - start;
+            if !ordered || !inside {
+                return Err(corrupt());
+            }
+            points.push(SyncPoint {
+                bit_offset,
+                position,
+            });
+        }
+        Ok(SyncTable { points })
+    }
+
+    /// Last sync point at or before `position`. The start of the data is an
+    /// implicit sync point.
+    pub fn nearest(&self, position: u64) -> SyncPoint {
+        let ix = self.points.partition_point(|point| point.position <= position);
+        match ix {
+            0 => SyncPoint {
+                bit_offset: 0,
+                position: 0,
+            },
+            _ => self.points[ix - 1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn table() -> SyncTable {
+        SyncTable {
+            points: (0..5)
+                .map(|i| SyncPoint {
+                    bit_offset: i * 30,
+                    position: i * 10,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn roundtrip_and_nearest() {
+        let mut data = vec![0u8; 20];
+        table().write_to(&mut data).unwrap();
+        let loaded = SyncTable::load(&mut Cursor::new(&data), 0, 50).unwrap();
+        assert_eq!(loaded, table());
+        assert_eq!(loaded.nearest(0).position, 0);
+        assert_eq!(loaded.nearest(9).position, 0);
+        assert_eq!(loaded.nearest(10).bit_offset, 30);
+        assert_eq!(loaded.nearest(49).position, 40);
+        assert_eq!(SyncTable::default().nearest(49).position, 0);
+    }
+
+    #[test]
+    fn reject_invalid_tables() {
+        let mut data = vec![0u8; 20];
+        table().write_to(&mut data).unwrap();
+        // Positions beyond the data
+        assert!(SyncTable::load(&mut Cursor::new(&data), 0, 40).is_err());
+        // Bit offsets beyond the data
+        assert!(SyncTable::load(&mut Cursor::new(&data), 10, 50).is_err());
+        // Count exceeds the file
+        let last = data.len() - 1;
+        data[last] = 9;
+        assert!(SyncTable::load(&mut Cursor::new(&data), 0, 50).is_err());
+        assert!(SyncTable::load(&mut Cursor::new(&data[..4]), 0, 50).is_err());
+    }
+}
@@ -0,0 +1,287 @@
+//! Inspect compressed files without decoding them
+//!
+//! `inspect` parses the preamble and the header of a file and reports the
+//! sizes, the compression ratio and the code table. The `Info` can be printed
+//! for humans or serialised to JSON with `Info::to_json`.
+use crate::block::{BlockHeader, BlockIndex};
+use crate::error::{HuffmanError, Result};
+use crate::filter::Filter;
+use crate::format::{
+    Preamble, FLAG_ADAPTIVE, FLAG_BLOCKS, FLAG_CONTENT_CHECKSUM, FLAG_CONTEXT, FLAG_INDEX,
+    FLAG_SYNC_POINTS, FLAG_UNKNOWN_SIZE,
+};
+use crate::stats::{compression_factor, compression_ratio};
+use crate::transform::Transform;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Names of the flags, in the order of their bits
+const FLAG_NAMES: [(u8, &str); 7] = [
+    (FLAG_CONTENT_CHECKSUM, "checksum"),
+    (FLAG_BLOCKS, "blocks"),
+    (FLAG_INDEX, "index"),
+    (FLAG_SYNC_POINTS, "sync-points"),
+    (FLAG_UNKNOWN_SIZE, "unknown-size"),
+    (FLAG_ADAPTIVE, "adaptive"),
+    (FLAG_CONTEXT, "context"),
+];
+
+/// A Huffman table of a file
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TableInfo {
+    /// Number of bytes coded with the table
+    pub size: u64,
+    /// Number of symbols with a codeword
+    pub symbols: usize,
+    /// Length of the longest codeword
+    pub sentinel: u8,
+    /// Codeword length of every symbol with a codeword
+    pub code_lengths: BTreeMap<u8, u8>,
+}
+
+impl TableInfo {
+    fn new(size: u64, lengths: &[u8]) -> Self {
+        let code_lengths: BTreeMap<u8, u8> = lengths
+            .iter()
+            .enumerate()
+            .filter(|(_, &length)| length > 0)
+            .map(|(symbol, &length)| (symbol as u8, length))
+            .collect();
+        TableInfo {
+            size,
+            symbols: code_lengths.len(),
+            sentinel: code_lengths.values().max().cloned().unwrap_or(0),
+            code_lengths,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Info {
+    pub version: u8,
+    pub flags: Vec<&'static str>,
+    /// Filter applied to the data before the encoding
+    pub filter: Filter,
+    /// Transform replacing the plain coding of the data
+    pub transform: Transform,
+    /// Size of the original data, `None` if it was unknown during compression
+    pub original_size: Option<u64>,
+    /// Size of the compressed file, `None` if it can not be determined
+    pub compressed_size: Option<u64>,
+    pub ratio: Option<f64>,
+    pub factor: Option<f64>,
+    /// Number of blocks, `None` for files without a block index
+    pub blocks: Option<u64>,
+    /// The table of the header or the tables of all blocks, empty for
+    /// adaptive, context and transformed files
+    pub tables: Vec<TableInfo>,
+}
+
+impl Info {
+    /// Information available from the preamble alone. The tables of block
+    /// files are not part of the preamble.
+    pub fn from_preamble(preamble: &Preamble) -> Result<Self> {
+        let header = preamble.parse_header()?;
+        let original_size = if preamble.flags & FLAG_UNKNOWN_SIZE != 0 {
+            None
+        } else {
+            Some(header.readbytes as u64)
+        };
+        let mut tables = Vec::new();
+        let tableless = FLAG_BLOCKS | FLAG_ADAPTIVE | FLAG_CONTEXT;
+        if preamble.flags & tableless == 0
+            && header.transform == Transform::None
+            && header.readbytes > 0
+        {
+            tables.push(TableInfo::new(header.readbytes as u64, &header.lengths));
+        }
+        Ok(Info {
+            version: preamble.version,
+            flags: FLAG_NAMES
+                .iter()
+                .filter(|(flag, _)| preamble.flags & flag != 0)
+                .map(|&(_, name)| name)
+                .collect(),
+            filter: header.filter,
+            transform: header.transform,
+            original_size,
+            compressed_size: None,
+            ratio: None,
+            factor: None,
+            blocks: None,
+            tables,
+        })
+    }
+
+    fn set_compressed_size(&mut self, compressed: u64) {
+        self.compressed_size = Some(compressed);
+        if let Some(original) = self.original_size.filter(|&size| size > 0) {
+            self.ratio = Some(compression_ratio(original, compressed));
+            self.factor = Some(compression_factor(original, compressed));
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).map_err(|err| HuffmanError::Io(err.into()))
+    }
+}
+
+/// Inspect a compressed file. For block files with an index the tables of
+/// all blocks are read and the original size is taken from the index, if the
+/// header does not know it.
+pub fn inspect<R: Read + Seek>(reader: &mut R) -> Result<Info> {
+    reader.seek(SeekFrom::Start(0))?;
+    let preamble = Preamble::read_from(reader)?;
+    let mut info = Info::from_preamble(&preamble)?;
+    let start = reader.stream_position()?;
+    let indexed = FLAG_BLOCKS | FLAG_INDEX;
+    if preamble.flags & indexed == indexed {
+        let trailer = if preamble.flags & FLAG_CONTENT_CHECKSUM != 0 {
+            4
+        } else {
+            0
+        };
+        let index = BlockIndex::load(reader, start, trailer)?;
+        info.blocks = Some(index.entries.len() as u64);
+        info.original_size.get_or_insert(index.decoded_size());
+        let mut position = start;
+        for entry in index.entries.iter() {
+            reader.seek(SeekFrom::Start(position))?;
+            let block = BlockHeader::read_from(reader)?
+                .filter(|block| block.symbols == entry.symbols as usize)
+                .ok_or_else(|| {
+                    HuffmanError::CorruptHeader("Block does not match the index".to_string())
+                })?;
+            info.tables
+                .push(TableInfo::new(block.symbols as u64, &block.lengths));
+            position += entry.size as u64;
+        }
+    }
+    let compressed = reader.seek(SeekFrom::End(0))?;
+    info.set_compressed_size(compressed);
+    Ok(info)
+}
+
+fn optional<T: fmt::Display>(value: &Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "unknown".to_string(),
+    }
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Format version:     {}", self.version)?;
+        writeln!(f, "Flags:              {}", self.flags.join(", "))?;
+        if self.filter != Filter::None {
+            writeln!(f, "Filter:             {}", self.filter)?;
+        }
+        if self.transform != Transform::None {
+            writeln!(f, "Transform:          {}", self.transform)?;
+        }
+        writeln!(f, "Original size:      {}", optional(&self.original_size))?;
+        writeln!(f, "Compressed size:    {}", optional(&self.compressed_size))?;
+        if let (Some(ratio), Some(factor)) = (self.ratio, self.factor) {
+            writeln!(f, "Compression ratio:  {:.2}", ratio)?;
+            writeln!(f, "Compression factor: {:.2}", factor)?;
+        }
+        if let Some(blocks) = self.blocks {
+            writeln!(f, "Blocks:             {}", blocks)?;
+        }
+        for (ix, table) in self.tables.iter().enumerate() {
+            writeln!(f, "Table {}:", ix)?;
+            writeln!(f, "  Size:             {}", table.size)?;
+            writeln!(f, "  Symbols:          {}", table.symbols)?;
+            writeln!(f, "  Sentinel:         {}", table.sentinel)?;
+            writeln!(f, "  Code lengths:")?;
+            for (&symbol, length) in table.code_lengths.iter() {
+                let escaped: String = (symbol as char).escape_default().collect();
+                writeln!(f, "    {:>3} {:<6} {:>2}", symbol, escaped, length)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn inspect_files() {
+        let data = "This is a lovely text in a big world. ".repeat(100);
+        let dir = std::env::temp_dir();
+        let source = dir.join("rscompress-huffman-info.txt");
+        let destination = dir.join("rscompress-huffman-info.pzhf");
+        std::fs::write(&source, &data).unwrap();
+        crate::stream_compress_with_header_information(
+            source.to_str().unwrap(),
+            destination.to_str().unwrap(),
+        )
+        .unwrap();
+        let file = std::fs::read(&destination).unwrap();
+        for path in [source, destination].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+
+        let info = inspect(&mut Cursor::new(&file)).unwrap();
+        assert_eq!(info.flags, vec!["checksum"]);
+        assert_eq!(info.original_size, Some(data.len() as u64));
+        assert_eq!(info.compressed_size, Some(file.len() as u64));
+        assert_eq!(info.blocks, None);
+        assert_eq!(info.tables.len(), 1);
+        let table = &info.tables[0];
+        assert_eq!(table.size, data.len() as u64);
+        assert_eq!(table.symbols, 20);
+        assert_eq!(table.code_lengths.len(), 20);
+        assert_eq!(Some(&table.sentinel), table.code_lengths.values().max());
+        assert!(table.code_lengths[&b' '] < table.code_lengths[&b'T']);
+        let ratio = info.ratio.unwrap();
+        assert!(ratio < 1.0);
+        assert!((ratio * info.factor.unwrap() - 1.0).abs() < 1e-9);
+        let report = info.to_string();
+        assert!(report.contains("Compression factor:"));
+        assert!(report.contains("  Symbols:          20\n"));
+        assert!(report.contains(&format!(
+            "    116 t      {:>2}\n",
+            table.code_lengths[&b't']
+        )));
+
+        let json = info.to_json().unwrap();
+        assert!(json.contains("\"original_size\": 3800"));
+        assert!(json.contains("\"flags\": [\n    \"checksum\"\n  ]"));
+    }
+
+    #[test]
+    fn inspect_blocks() {
+        let data = "This is a lovely text in a big world. ".repeat(100);
+        let file = crate::compress(data.as_bytes(), Vec::new(), 1000, 2).unwrap();
+        let info = inspect(&mut Cursor::new(&file)).unwrap();
+        assert_eq!(
+            info.flags,
+            vec!["checksum", "blocks", "index", "unknown-size"]
+        );
+        // The size is taken from the index
+        assert_eq!(info.original_size, Some(data.len() as u64));
+        assert_eq!(info.blocks, Some(4));
+        let sizes: Vec<u64> = info.tables.iter().map(|table| table.size).collect();
+        assert_eq!(sizes, vec![1000, 1000, 1000, 800]);
+        assert!(info.tables.iter().all(|table| table.symbols == 20));
+        assert!(info.ratio.is_some());
+        assert_eq!(info.filter, Filter::None);
+        assert!(!info.to_string().contains("Filter:"));
+
+        // Without seeking only the preamble is available
+        let preamble = Preamble::read_from(&mut file.as_slice()).unwrap();
+        let info = Info::from_preamble(&preamble).unwrap();
+        assert_eq!(info.original_size, None);
+        assert_eq!(info.compressed_size, None);
+        assert_eq!(info.ratio, None);
+        assert!(info.tables.is_empty());
+
+        assert!(inspect(&mut Cursor::new(&file[..20])).is_err());
+    }
+}
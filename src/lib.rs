@@ -12,6 +12,7 @@
 //! described in the above paper. A second traversal of file then encodes each
 //! byte and saves it on disk.
 
+pub use error::{HuffmanError, Result};
 use log::info;
 use std::fs::File;
 use std::io::prelude::*;
@@ -21,62 +22,487 @@ use std::io::{BufReader, BufWriter};
 //static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 pub const BUF: usize = 4096;
+pub mod block;
+pub mod checksum;
+pub mod deflate;
+pub mod error;
+pub mod filter;
+pub mod format;
 pub mod huffman;
+pub mod info;
 pub mod model;
 pub mod stats;
+pub mod transform;
+pub mod zstd;
 
-pub fn stream_decompress_with_header_information(source: &str, destination: &str) {
-    unimplemented!()
+pub fn stream_decompress_with_header_information(source: &str, destination: &str) -> Result<()> {
+    info!("Starting decompression");
+    info!("Input:  {}", &source);
+    info!("Output: {}", &destination);
+    // Create reader object
+    let sfile = File::open(source)?;
+    let reader = BufReader::with_capacity(BUF, sfile);
+
+    // Create writer object
+    let dfile = File::create(destination)?;
+    let writer = BufWriter::with_capacity(BUF, dfile);
+
+    decompress(reader, writer)?;
+    info!("End decompression");
+    Ok(())
+}
+
+/// Decompress everything from `reader` into `writer`. Neither needs to be
+/// seekable, e.g. for pipes or sockets. Returns the flushed writer.
+pub fn decompress<R: Read, W: Write>(mut reader: R, writer: W) -> Result<W> {
+    // Read header
+    let preamble = format::Preamble::read_from(&mut reader)?;
+    let h = preamble.parse_header()?;
+    info!("Header: {:?}", h);
+    let mut writer = filter::UnfilterWriter::new(writer, h.filter);
+    let unknown_size = preamble.flags & format::FLAG_UNKNOWN_SIZE != 0;
+    let transformed = h.transform != transform::Transform::None;
+    if unknown_size
+        && !transformed
+        && preamble.flags & (format::FLAG_BLOCKS | format::FLAG_ADAPTIVE) == 0
+    {
+        return Err(HuffmanError::UnsupportedFlags(preamble.flags));
+    }
+    let layouts = format::FLAG_BLOCKS
+        | format::FLAG_INDEX
+        | format::FLAG_SYNC_POINTS
+        | format::FLAG_ADAPTIVE
+        | format::FLAG_CONTEXT;
+    if transformed && preamble.flags & layouts != 0 {
+        return Err(HuffmanError::UnsupportedFlags(preamble.flags));
+    }
+
+    //Decompress file
+    let checksum = preamble.flags & format::FLAG_CONTENT_CHECKSUM != 0;
+    if transformed {
+        let (writeout, crc, mut reader) = match h.transform {
+            transform::Transform::BlockSorting { block_size } => {
+                let mut decoder = transform::bwt::BlockSortingReader::new(reader, block_size);
+                let crc = copy_with_checksum(&mut decoder, &mut writer)?;
+                (decoder.writeout, crc, decoder.into_inner())
+            }
+            transform::Transform::Lz77 { window } => {
+                let mut decoder = transform::lz77::Lz77Reader::new(reader, window);
+                let crc = copy_with_checksum(&mut decoder, &mut writer)?;
+                (decoder.writeout, crc, decoder.into_inner())
+            }
+            transform::Transform::None => unreachable!("Checked above"),
+        };
+        if !unknown_size && writeout != h.readbytes as u64 {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Expected {} bytes, decoded {}",
+                h.readbytes, writeout
+            )));
+        }
+        if checksum {
+            format::verify_footer(&mut reader, crc)?;
+        }
+    } else if preamble.flags & format::FLAG_BLOCKS != 0 {
+        let mut blocks = block::BlockReader::new(reader);
+        let crc = copy_with_checksum(&mut blocks, &mut writer)?;
+        if !unknown_size && blocks.writeout != h.readbytes as u64 {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Expected {} bytes, decoded {}",
+                h.readbytes, blocks.writeout
+            )));
+        }
+        let count = blocks.blocks;
+        let mut reader = blocks.into_inner();
+        if preamble.flags & format::FLAG_INDEX != 0 {
+            block::BlockIndex::read_from(&mut reader, count)?;
+        }
+        if checksum {
+            format::verify_footer(&mut reader, crc)?;
+        }
+    } else if preamble.flags & format::FLAG_ADAPTIVE != 0 {
+        let mut decoder = huffman::adaptive::AdaptiveDecoder::new(reader);
+        let crc = copy_with_checksum(&mut decoder, &mut writer)?;
+        if !unknown_size && decoder.writeout != h.readbytes as u64 {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Expected {} bytes, decoded {}",
+                h.readbytes, decoder.writeout
+            )));
+        }
+        if checksum {
+            format::verify_footer(&mut decoder.into_inner(), crc)?;
+        }
+    } else if preamble.flags & format::FLAG_CONTEXT != 0 {
+        let model = huffman::context::ContextModel::read_from(&mut reader)?;
+        let mut decoder =
+            huffman::context::ContextDecoder::new(reader, &model, h.readbytes as u64)?;
+        decoder.use_checksum(checksum);
+        std::io::copy(&mut decoder, &mut writer)?;
+    } else if h.readbytes > 0 {
+        let mut decoder = huffman::decode::Decoder::from_header(h, reader)?;
+        decoder.use_checksum(checksum);
+        std::io::copy(&mut decoder, &mut writer)?;
+    } else if checksum {
+        // Without any data there is no decoder to verify the footer
+        format::verify_footer(&mut reader, checksum::crc32(&[]))?;
+    }
+    Ok(writer.finish()?)
 }
-pub fn stream_compress_with_header_information(source: &str, destination: &str) {
+
+/// Copy everything from `decoder` to `writer`. Returns the CRC-32 of the
+/// copied data.
+fn copy_with_checksum<D: Read, W: Write>(decoder: &mut D, writer: &mut W) -> Result<u32> {
+    let mut buffer = vec![0u8; BUF];
+    let mut crc = checksum::Crc32::new();
+    loop {
+        let read_size = decoder.read(&mut buffer);
+        match read_size {
+            Ok(0) => break, // fully decoded file
+            Ok(n) => {
+                crc.update(&buffer[..n]);
+                writer.write_all(&buffer[..n])?
+            }
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+    }
+    Ok(crc.finalize())
+}
+
+pub fn stream_compress_with_header_information(source: &str, destination: &str) -> Result<()> {
+    stream_compress_seekable(source, destination, 0)
+}
+
+/// Compress `source` with a sync point every `interval` bytes, which allow
+/// random access via `huffman::decode::seek::SeekableDecoder`. An interval of
+/// `0` writes no sync points.
+pub fn stream_compress_seekable(source: &str, destination: &str, interval: usize) -> Result<()> {
     info!("Starting compression");
     info!("Input:  {}", &source);
     info!("Output: {}", &destination);
     // Create reader object
-    let sfile = File::open(source).expect("Failed to open source file");
-    let filesize = std::fs::metadata(source).expect("Can not read filesize").len();
+    let sfile = File::open(source)?;
+    let filesize = std::fs::metadata(source)?.len();
     let mut reader = BufReader::with_capacity(BUF, sfile);
-    let mut buffer: Vec<u8> = Vec::with_capacity(BUF);
-    unsafe { buffer.set_len(BUF) }
+    let mut buffer = vec![0u8; BUF];
 
     // Create writer object
-    let dfile = File::create(destination).expect("Failed to create destination file");
+    let dfile = File::create(destination)?;
     let w = BufWriter::with_capacity(BUF, dfile);
 
     // Create encoder
-    let h = huffman::Huffman::from_reader(&mut reader);
+    let h = huffman::Huffman::from_reader(&mut reader)?;
     let mut writer = huffman::encode::Encoder::new(w, &h);
+    writer.use_checksum(true);
+    writer.use_sync_points(interval);
 
     // Write header
     // TODO The header write can also be done in the encoder
     let mut h = huffman::header::Header::from(&writer);
     h.update_readbytes(filesize);
     info!("Header: {:?}", h);
-    let header = h.to_binary();
-    let header_length = u64_to_bytes(header.len() as u64);
-    writer
-        .plain_write(&writer.magic())
-        .expect("Could not write magic");
-    writer
-        .plain_write(&header_length)
-        .expect("Could not write header length");
-    writer
-        .plain_write(&header)
-        .expect("Could not write header");
+    let mut flags = format::FLAG_CONTENT_CHECKSUM;
+    if interval > 0 {
+        flags |= format::FLAG_SYNC_POINTS;
+    }
+    let preamble = format::Preamble::for_header(flags, &h)?;
+    preamble.write_to(&mut writer.inner)?;
 
     //Compress file
     loop {
         let read_size = reader.read(&mut buffer);
         match read_size {
             Ok(0) => break, // fully read file
-            Ok(n) => writer
-                .write(&buffer[..n])
-                .expect("Could not write buffer to destination"),
-            Err(err) => panic!("Problem with reading source file: {:?}", err),
+            Ok(n) => writer.write(&buffer[..n])?,
+            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
         };
     }
-    writer.flush().expect("Could not flush file to disk!");
-    info!("End compression")
+    writer.flush()?;
+    if let Some(crc) = writer.checksum() {
+        writer.plain_write(&crc.to_be_bytes())?;
+    }
+    if interval > 0 {
+        writer.sync_table.write_to(&mut writer.inner)?;
+    }
+    writer.inner.flush()?;
+    info!("End compression");
+    Ok(())
+}
+
+/// Decompress `source` with up to `threads` blocks decoded in parallel.
+/// Files without a block index are decompressed sequentially.
+pub fn stream_decompress_parallel(source: &str, destination: &str, threads: usize) -> Result<()> {
+    info!("Starting parallel decompression");
+    info!("Input:  {}", &source);
+    info!("Output: {}", &destination);
+    // Create reader object
+    let sfile = File::open(source)?;
+    let mut reader = BufReader::with_capacity(BUF, sfile);
+
+    // Read header
+    let preamble = format::Preamble::read_from(&mut reader)?;
+    let indexed = format::FLAG_BLOCKS | format::FLAG_INDEX;
+    if preamble.flags & indexed != indexed {
+        info!("No block index, falling back to sequential decompression");
+        return stream_decompress_with_header_information(source, destination);
+    }
+    let h = preamble.parse_header()?;
+    info!("Header: {:?}", h);
+
+    // Read index
+    let checksum = preamble.flags & format::FLAG_CONTENT_CHECKSUM != 0;
+    let start = reader.stream_position()?;
+    let trailer = if checksum { 4 } else { 0 };
+    let index = block::BlockIndex::load(&mut reader, start, trailer)?;
+    let unknown_size = preamble.flags & format::FLAG_UNKNOWN_SIZE != 0;
+    if !unknown_size && index.decoded_size() != h.readbytes as u64 {
+        return Err(HuffmanError::CorruptHeader(format!(
+            "Expected {} bytes, index describes {}",
+            h.readbytes,
+            index.decoded_size()
+        )));
+    }
+    info!("Index of {} blocks", index.entries.len());
+    reader.seek(std::io::SeekFrom::Start(start))?;
+
+    // Create writer object
+    let dfile = File::create(destination)?;
+    let writer = BufWriter::with_capacity(BUF, dfile);
+    let mut writer = filter::UnfilterWriter::new(writer, h.filter);
+
+    //Decompress file
+    let crc = block::decompress_parallel(&mut reader, &mut writer, &index, threads)?;
+    if checksum {
+        reader.seek(std::io::SeekFrom::Start(start + index.encoded_size()))?;
+        block::BlockIndex::read_from(&mut reader, index.entries.len() as u64)?;
+        format::verify_footer(&mut reader, crc)?;
+    }
+    writer.finish()?;
+    info!("End parallel decompression");
+    Ok(())
+}
+
+/// Compress `source` in blocks of `block_size` bytes, each with its own
+/// Huffman table. Compresses better than a single table, if the statistics
+/// of the data drift. Up to `threads` blocks are encoded in parallel, the
+/// output does not depend on the number of threads.
+pub fn stream_compress_with_blocks(
+    source: &str,
+    destination: &str,
+    block_size: usize,
+    threads: usize,
+) -> Result<()> {
+    stream_compress_with_filter(source, destination, block_size, threads, filter::Filter::None)
+}
+
+/// Compress `source` in blocks like `stream_compress_with_blocks`, after
+/// applying `filter` to the data. The filter is recorded in the header and
+/// reverted during decompression.
+pub fn stream_compress_with_filter(
+    source: &str,
+    destination: &str,
+    block_size: usize,
+    threads: usize,
+    filter: filter::Filter,
+) -> Result<()> {
+    info!("Starting block compression");
+    info!("Input:  {}", &source);
+    info!("Output: {}", &destination);
+    // Create reader object
+    let sfile = File::open(source)?;
+    let filesize = std::fs::metadata(source)?.len();
+    let reader = BufReader::with_capacity(BUF, sfile);
+
+    // Create writer object
+    let dfile = File::create(destination)?;
+    let writer = BufWriter::with_capacity(BUF, dfile);
+
+    compress_blocks(reader, writer, block_size, threads, Some(filesize), filter)?;
+    info!("End block compression");
+    Ok(())
+}
+
+/// Compress everything from `reader` into `writer` without knowing the size
+/// of the input in advance, e.g. for pipes or sockets. The input is buffered
+/// in blocks of `block_size` bytes, each encoded with its own table.
+/// Returns the flushed writer.
+pub fn compress<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    block_size: usize,
+    threads: usize,
+) -> Result<W> {
+    compress_with_filter(reader, writer, block_size, threads, filter::Filter::None)
+}
+
+/// Compress like `compress`, after applying `filter` to the data
+pub fn compress_with_filter<R: Read, W: Write>(
+    reader: R,
+    writer: W,
+    block_size: usize,
+    threads: usize,
+    filter: filter::Filter,
+) -> Result<W> {
+    compress_blocks(reader, writer, block_size, threads, None, filter)
+}
+
+/// Compress everything from `reader` into `writer` in a single pass with an
+/// adaptive Huffman code. Neither a histogram of the data nor its size is
+/// needed in advance. Returns the flushed writer.
+pub fn compress_adaptive<R: Read, W: Write>(mut reader: R, mut w: W) -> Result<W> {
+    // Write header without a table, the code is built during encoding
+    let h = huffman::header::Header::empty(0, filter::Filter::None, transform::Transform::None);
+    let flags = format::FLAG_CONTENT_CHECKSUM | format::FLAG_ADAPTIVE | format::FLAG_UNKNOWN_SIZE;
+    format::Preamble::for_header(flags, &h)?.write_to(&mut w)?;
+
+    //Compress data
+    let mut writer = huffman::adaptive::AdaptiveEncoder::new(w);
+    writer.use_checksum(true);
+    std::io::copy(&mut reader, &mut writer)?;
+    info!("Adaptive compression of {} bytes", writer.readbytes);
+    Ok(writer.finish()?)
+}
+
+/// Compress with an order-1 context model, see `huffman::context`. The
+/// reader is read twice, once for the statistics of the contexts and once for
+/// the encoding.
+pub fn compress_context<R: Read + Seek, W: Write>(mut reader: R, mut w: W) -> Result<W> {
+    let start = reader.stream_position()?;
+    let histograms = huffman::context::context_histograms(&mut reader)?;
+    let size = reader.stream_position()? - start;
+    reader.seek(std::io::SeekFrom::Start(start))?;
+    let model = huffman::context::ContextModel::from_histograms(&histograms)?;
+
+    // Write header without a table, the table set precedes the data
+    let h = huffman::header::Header::empty(
+        size as usize,
+        filter::Filter::None,
+        transform::Transform::None,
+    );
+    let flags = format::FLAG_CONTENT_CHECKSUM | format::FLAG_CONTEXT;
+    format::Preamble::for_header(flags, &h)?.write_to(&mut w)?;
+    model.write_to(&mut w)?;
+
+    //Compress data
+    let mut writer = huffman::context::ContextEncoder::new(w, &model);
+    writer.use_checksum(true);
+    std::io::copy(&mut reader.take(size), &mut writer)?;
+    if writer.readbytes != size {
+        return Err(HuffmanError::CorruptHeader(format!(
+            "Expected {} bytes, read {}",
+            size, writer.readbytes
+        )));
+    }
+    info!("Context compression of {} bytes", size);
+    Ok(writer.finish()?)
+}
+
+/// Compress everything from `reader` into `writer` with block sorting, see
+/// `transform::bwt`. Compresses text much better than the plain Huffman
+/// coding, but needs the memory for a suffix array of `block_size` entries.
+/// Returns the flushed writer.
+pub fn compress_block_sorting<R: Read, W: Write>(
+    mut reader: R,
+    mut w: W,
+    block_size: usize,
+) -> Result<W> {
+    // Write header without a table, the tables are part of the blocks
+    let h = huffman::header::Header::empty(
+        0,
+        filter::Filter::None,
+        transform::Transform::block_sorting(block_size)?,
+    );
+    let flags = format::FLAG_CONTENT_CHECKSUM | format::FLAG_UNKNOWN_SIZE;
+    format::Preamble::for_header(flags, &h)?.write_to(&mut w)?;
+
+    //Compress data
+    let mut writer = transform::bwt::BlockSortingWriter::new(w, block_size)?;
+    writer.use_checksum(true);
+    std::io::copy(&mut reader, &mut writer)?;
+    info!("Block sorting compression of {} bytes", writer.readbytes);
+    writer.finish()
+}
+
+/// Compress everything from `reader` into `writer` with LZ77, see
+/// `transform::lz77`. Repeated strings up to `window` bytes back are coded
+/// as matches. Returns the flushed writer.
+pub fn compress_lz77<R: Read, W: Write>(mut reader: R, mut w: W, window: usize) -> Result<W> {
+    // Write header without a table, the tables are part of the blocks
+    let h = huffman::header::Header::empty(
+        0,
+        filter::Filter::None,
+        transform::Transform::lz77(window)?,
+    );
+    let flags = format::FLAG_CONTENT_CHECKSUM | format::FLAG_UNKNOWN_SIZE;
+    format::Preamble::for_header(flags, &h)?.write_to(&mut w)?;
+
+    //Compress data
+    let mut writer = transform::lz77::Lz77Writer::new(w, window)?;
+    writer.use_checksum(true);
+    std::io::copy(&mut reader, &mut writer)?;
+    info!("LZ77 compression of {} bytes", writer.readbytes);
+    writer.finish()
+}
+
+/// Compress everything from `reader` into `writer` as a gzip member, see
+/// `deflate`. The output is not in the format of this crate, but can be
+/// decompressed with `gzip -d`. Returns the flushed writer.
+pub fn compress_gzip<R: Read, W: Write>(mut reader: R, w: W) -> Result<W> {
+    let mut writer = deflate::GzipWriter::new(w)?;
+    std::io::copy(&mut reader, &mut writer)?;
+    info!("gzip compression of {} bytes", writer.readbytes);
+    writer.finish()
+}
+
+/// Decompress gzip data from `reader` into `writer`, e.g. written by
+/// `compress_gzip` or `gzip`. Concatenated members are decompressed one after
+/// another. Returns the flushed writer.
+pub fn decompress_gzip<R: Read, W: Write>(reader: R, mut writer: W) -> Result<W> {
+    let mut decoder = deflate::InflateReader::new(reader, deflate::Container::Gzip);
+    std::io::copy(&mut decoder, &mut writer)?;
+    info!("gzip decompression of {} bytes", decoder.writeout);
+    writer.flush()?;
+    Ok(writer)
+}
+
+/// Compress in blocks. If `size` is known, it is stored in the header and
+/// checked against the actual size of the input.
+fn compress_blocks<R: Read, W: Write>(
+    reader: R,
+    mut w: W,
+    block_size: usize,
+    threads: usize,
+    size: Option<u64>,
+    filter: filter::Filter,
+) -> Result<W> {
+    block::validate_block_size(block_size)?;
+    let mut reader = filter::FilterReader::new(reader, filter);
+
+    // Write header without a table, the tables are part of the blocks
+    let h = huffman::header::Header::empty(
+        size.unwrap_or(0) as usize,
+        filter,
+        transform::Transform::None,
+    );
+    info!("Header: {:?}", h);
+    let mut flags = format::FLAG_CONTENT_CHECKSUM | format::FLAG_BLOCKS | format::FLAG_INDEX;
+    if size.is_none() {
+        flags |= format::FLAG_UNKNOWN_SIZE;
+    }
+    format::Preamble::for_header(flags, &h)?.write_to(&mut w)?;
+
+    //Compress file
+    let mut writer = block::BlockWriter::new(w, block_size)?;
+    writer.use_checksum(true);
+    writer.use_threads(threads);
+    writer.use_index(true);
+    std::io::copy(&mut reader, &mut writer)?;
+    if size.is_some_and(|size| size != writer.readbytes) {
+        return Err(HuffmanError::Io(std::io::Error::other(
+            "Source file changed during compression",
+        )));
+    }
+    writer.finish()
 }
 
 fn u64_to_bytes(num: u64) -> [u8; 8] {
@@ -147,4 +573,393 @@ mod tests {
             assert_eq!(num, reverse)
         }
     }
+
+    fn roundtrip_with_blocks(name: &str, data: &[u8], block_size: usize) {
+        let dir = std::env::temp_dir();
+        let source = dir.join(format!("rscompress-huffman-blocks-{}.raw", name));
+        let compressed = dir.join(format!("rscompress-huffman-blocks-{}.pzhf", name));
+        let decompressed = dir.join(format!("rscompress-huffman-blocks-{}.out", name));
+        std::fs::write(&source, data).unwrap();
+
+        stream_compress_with_blocks(
+            source.to_str().unwrap(),
+            compressed.to_str().unwrap(),
+            block_size,
+            4,
+        )
+        .unwrap();
+        stream_decompress_with_header_information(
+            compressed.to_str().unwrap(),
+            decompressed.to_str().unwrap(),
+        )
+        .unwrap();
+        let result = std::fs::read(&decompressed).unwrap();
+        assert_eq!(data, result.as_slice());
+
+        for &threads in [1, 3].iter() {
+            std::fs::remove_file(&decompressed).unwrap();
+            stream_decompress_parallel(
+                compressed.to_str().unwrap(),
+                decompressed.to_str().unwrap(),
+                threads,
+            )
+            .unwrap();
+            let result = std::fs::read(&decompressed).unwrap();
+            assert_eq!(data, result.as_slice());
+        }
+
+        for path in [source, compressed, decompressed].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_stream_roundtrip_blocks() {
+        let mut data = "This is a lovely text in a big world. ".repeat(500).into_bytes();
+        let words: Vec<u8> = vec![20, 17, 6, 3, 2, 2, 2, 1, 1, 1];
+        data.extend(stats::generate_random_byte_vector(100, 110, 20_000, &words));
+        roundtrip_with_blocks("mixed", &data, 4096);
+        roundtrip_with_blocks("single", &data, block::DEFAULT_BLOCK_SIZE);
+        roundtrip_with_blocks("empty", &[], 4096);
+    }
+
+    fn roundtrip_with_header_information(name: &str, data: &[u8]) {
+        let dir = std::env::temp_dir();
+        let source = dir.join(format!("rscompress-huffman-{}.raw", name));
+        let compressed = dir.join(format!("rscompress-huffman-{}.pzhf", name));
+        let decompressed = dir.join(format!("rscompress-huffman-{}.out", name));
+        std::fs::write(&source, data).unwrap();
+
+        stream_compress_with_header_information(
+            source.to_str().unwrap(),
+            compressed.to_str().unwrap(),
+        )
+        .unwrap();
+        stream_decompress_with_header_information(
+            compressed.to_str().unwrap(),
+            decompressed.to_str().unwrap(),
+        )
+        .unwrap();
+        let result = std::fs::read(&decompressed).unwrap();
+
+        for path in [source, compressed, decompressed].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+        assert_eq!(data, result.as_slice());
+    }
+
+    #[test]
+    fn test_stream_roundtrip_text() {
+        let text = "This is a lovely text in a big world. ".repeat(500);
+        roundtrip_with_header_information("text", text.as_bytes());
+    }
+
+    #[test]
+    fn test_stream_roundtrip_random() {
+        let words: Vec<u8> = vec![20, 17, 6, 3, 2, 2, 2, 1, 1, 1];
+        let origin = stats::generate_random_byte_vector(0, words.len() as u8, 100_000, &words);
+        roundtrip_with_header_information("random", &origin);
+    }
+
+    #[test]
+    fn test_stream_seekable() {
+        let dir = std::env::temp_dir();
+        let source = dir.join("rscompress-huffman-seekable.raw");
+        let compressed = dir.join("rscompress-huffman-seekable.pzhf");
+        let decompressed = dir.join("rscompress-huffman-seekable.out");
+        let text = "This is a lovely text in a big world. ".repeat(2000);
+        std::fs::write(&source, text.as_bytes()).unwrap();
+        stream_compress_seekable(source.to_str().unwrap(), compressed.to_str().unwrap(), 500)
+            .unwrap();
+
+        // Sequential decompression ignores the sync points
+        stream_decompress_with_header_information(
+            compressed.to_str().unwrap(),
+            decompressed.to_str().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&decompressed).unwrap(), text.as_bytes());
+
+        let file = BufReader::new(File::open(&compressed).unwrap());
+        let mut decoder = huffman::decode::seek::SeekableDecoder::new(file).unwrap();
+        decoder.seek(std::io::SeekFrom::Start(40_000)).unwrap();
+        let mut buf = [0u8; 38];
+        decoder.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf[..], &text.as_bytes()[40_000..40_038]);
+
+        for path in [source, compressed, decompressed].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_without_seek() {
+        /// Reader which hands out the data in small pieces, like a pipe
+        struct Pipe<'a>(&'a [u8]);
+        impl Read for Pipe<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(self.0.len()).min(1000);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let mut data = "This is a lovely text in a big world. ".repeat(500).into_bytes();
+        data.extend((0..20_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8));
+        for &(block_size, threads) in [(4096, 1), (5000, 3), (block::DEFAULT_BLOCK_SIZE, 2)].iter() {
+            let encoded = compress(Pipe(&data), Vec::new(), block_size, threads).unwrap();
+            let decoded = decompress(Pipe(&encoded), Vec::new()).unwrap();
+            assert_eq!(decoded, data);
+        }
+        let encoded = compress(Pipe(&[]), Vec::new(), 10, 1).unwrap();
+        assert!(decompress(Pipe(&encoded), Vec::new()).unwrap().is_empty());
+
+        // The parallel decompressor ignores the unknown size as well
+        let dir = std::env::temp_dir();
+        let compressed = dir.join("rscompress-huffman-pipe.pzhf");
+        let decompressed = dir.join("rscompress-huffman-pipe.out");
+        let encoded = compress(Pipe(&data), Vec::new(), 4096, 2).unwrap();
+        std::fs::write(&compressed, encoded).unwrap();
+        stream_decompress_parallel(
+            compressed.to_str().unwrap(),
+            decompressed.to_str().unwrap(),
+            4,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&decompressed).unwrap(), data);
+        for path in [compressed, decompressed].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    /// Roundtrip `data`, its first byte and no data through `encode` and
+    /// `decompress`. Returns the encoded `data`.
+    fn check_roundtrips<F: Fn(&[u8]) -> Result<Vec<u8>>>(data: &[u8], encode: F) -> Vec<u8> {
+        check_roundtrips_with(data, encode, |encoded| decompress(encoded, Vec::new()))
+    }
+
+    /// Roundtrip like `check_roundtrips` with the decoder `decode`
+    fn check_roundtrips_with<F, D>(data: &[u8], encode: F, decode: D) -> Vec<u8>
+    where
+        F: Fn(&[u8]) -> Result<Vec<u8>>,
+        D: Fn(&[u8]) -> Result<Vec<u8>>,
+    {
+        for input in [&data[..1], &[]].iter() {
+            let encoded = encode(input).unwrap();
+            assert_eq!(&decode(encoded.as_slice()).unwrap(), input);
+        }
+        let encoded = encode(data).unwrap();
+        assert_eq!(decode(encoded.as_slice()).unwrap(), data);
+        encoded
+    }
+
+    /// Check that a corrupt checksum footer and a truncated stream are
+    /// detected
+    fn check_corruption(mut encoded: Vec<u8>) {
+        let last = encoded.len() - 1;
+        encoded[last] ^= 1;
+        assert!(matches!(
+            decompress(encoded.as_slice(), Vec::new()),
+            Err(HuffmanError::ChecksumMismatch { .. })
+        ));
+        encoded.truncate(last - 8);
+        assert!(decompress(encoded.as_slice(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip_adaptive() {
+        let mut data = "This is a lovely text in a big world. ".repeat(500).into_bytes();
+        data.extend((0..20_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8));
+        let encoded = check_roundtrips(&data, |input| compress_adaptive(input, Vec::new()));
+        let info = info::inspect(&mut std::io::Cursor::new(&encoded)).unwrap();
+        assert_eq!(info.flags, vec!["checksum", "unknown-size", "adaptive"]);
+        assert!(info.tables.is_empty());
+        check_corruption(encoded);
+    }
+
+    /// Small CSV file with many repetitions
+    fn csv() -> Vec<u8> {
+        let data = "id,level,message\n1,INFO,started\n2,WARN,slow\n3,INFO,done\n".repeat(300);
+        data.into_bytes()
+    }
+
+    #[test]
+    fn test_roundtrip_context() {
+        let data = csv();
+        let encoded = check_roundtrips(&data, |input| {
+            compress_context(std::io::Cursor::new(input), Vec::new())
+        });
+        let info = info::inspect(&mut std::io::Cursor::new(&encoded)).unwrap();
+        assert_eq!(info.flags, vec!["checksum", "context"]);
+        assert_eq!(info.original_size, Some(data.len() as u64));
+        assert!(info.tables.is_empty());
+        check_corruption(encoded);
+    }
+
+    #[test]
+    fn test_roundtrip_block_sorting() {
+        let data = csv();
+        check_roundtrips(&data, |input| compress_block_sorting(input, Vec::new(), 1000));
+
+        let context = compress_context(std::io::Cursor::new(&data), Vec::new()).unwrap();
+        let encoded = compress_block_sorting(data.as_slice(), Vec::new(), 100_000).unwrap();
+        assert!(encoded.len() * 10 < context.len());
+        let info = info::inspect(&mut std::io::Cursor::new(&encoded)).unwrap();
+        assert_eq!(info.version, format::VERSION_DESCRIPTORS);
+        assert_eq!(info.flags, vec!["checksum", "unknown-size"]);
+        assert!(info.tables.is_empty());
+        assert!(info
+            .to_string()
+            .contains("Transform:          block sorting in blocks of 100000 bytes\n"));
+        check_corruption(encoded);
+    }
+
+    #[test]
+    fn test_roundtrip_gzip() {
+        let data = csv();
+        let encoded = check_roundtrips_with(
+            &data,
+            |input| compress_gzip(input, Vec::new()),
+            |encoded| decompress_gzip(encoded, Vec::new()),
+        );
+        assert!(encoded.len() * 20 < data.len());
+        assert!(decompress(encoded.as_slice(), Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_roundtrip_lz77() {
+        let data = csv();
+        check_roundtrips(&data, |input| compress_lz77(input, Vec::new(), 100));
+
+        let plain = compress(data.as_slice(), Vec::new(), 100_000, 1).unwrap();
+        let encoded = compress_lz77(data.as_slice(), Vec::new(), 1 << 16).unwrap();
+        assert!(encoded.len() * 20 < plain.len());
+        let info = info::inspect(&mut std::io::Cursor::new(&encoded)).unwrap();
+        assert!(info
+            .to_string()
+            .contains("Transform:          LZ77 with a window of 65536 bytes\n"));
+        check_corruption(encoded);
+    }
+
+    #[test]
+    fn test_roundtrip_filter() {
+        // Slowly changing big endian samples of 2 bytes
+        let data: Vec<u8> = (0..30_000u32)
+            .flat_map(|i| ((20_000 + (i / 7) % 500) as u16).to_be_bytes())
+            .chain(Some(42))
+            .collect();
+        let filter = filter::Filter::delta(2, true).unwrap();
+        let plain = compress(data.as_slice(), Vec::new(), 10_000, 2).unwrap();
+        let filtered = compress_with_filter(data.as_slice(), Vec::new(), 10_000, 2, filter).unwrap();
+        assert!(filtered.len() * 3 < plain.len());
+        assert_eq!(decompress(filtered.as_slice(), Vec::new()).unwrap(), data);
+        let info = info::inspect(&mut std::io::Cursor::new(&filtered)).unwrap();
+        assert_eq!(info.filter, filter);
+        assert_eq!(info.flags, vec!["checksum", "blocks", "index", "unknown-size"]);
+        assert_eq!(info.version, format::VERSION_DESCRIPTORS);
+        assert!(info
+            .to_string()
+            .contains("Filter:             delta of 2 byte big endian elements\n"));
+
+        let dir = std::env::temp_dir();
+        let source = dir.join("rscompress-huffman-filter.raw");
+        let compressed = dir.join("rscompress-huffman-filter.pzhf");
+        let decompressed = dir.join("rscompress-huffman-filter.out");
+        std::fs::write(&source, &data).unwrap();
+        stream_compress_with_filter(
+            source.to_str().unwrap(),
+            compressed.to_str().unwrap(),
+            10_000,
+            2,
+            filter,
+        )
+        .unwrap();
+        stream_decompress_parallel(
+            compressed.to_str().unwrap(),
+            decompressed.to_str().unwrap(),
+            2,
+        )
+        .unwrap();
+        assert_eq!(std::fs::read(&decompressed).unwrap(), data);
+        for path in [source, compressed, decompressed].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_stream_roundtrip_empty() {
+        roundtrip_with_header_information("empty", &[]);
+    }
+
+    #[test]
+    fn test_stream_decompress_corrupt_input() {
+        let dir = std::env::temp_dir();
+        let source = dir.join("rscompress-huffman-corrupt.raw");
+        let compressed = dir.join("rscompress-huffman-corrupt.pzhf");
+        let decompressed = dir.join("rscompress-huffman-corrupt.out");
+        let text = "This is a lovely text in a big world. ".repeat(50);
+        std::fs::write(&source, text.as_bytes()).unwrap();
+        stream_compress_with_header_information(
+            source.to_str().unwrap(),
+            compressed.to_str().unwrap(),
+        )
+        .unwrap();
+        let encoded = std::fs::read(&compressed).unwrap();
+        let decompress = |data: &[u8]| {
+            std::fs::write(&compressed, data).unwrap();
+            stream_decompress_with_header_information(
+                compressed.to_str().unwrap(),
+                decompressed.to_str().unwrap(),
+            )
+        };
+
+        let mut bad_magic = encoded.clone();
+        bad_magic[0] = b'x';
+        assert!(matches!(decompress(&bad_magic), Err(HuffmanError::BadMagic(_))));
+        assert!(matches!(
+            decompress(&encoded[..10]),
+            Err(HuffmanError::TruncatedStream)
+        ));
+        let mut bad_version = encoded.clone();
+        bad_version[4] = format::VERSION_DESCRIPTORS + 1;
+        assert!(matches!(
+            decompress(&bad_version),
+            Err(HuffmanError::UnsupportedVersion(_))
+        ));
+        let mut huge_header = encoded.clone();
+        huge_header[6] = 0xFF;
+        assert!(decompress(&huge_header).is_err());
+        let mut bad_header = encoded.clone();
+        for byte in bad_header[14..40].iter_mut() {
+            *byte = 0xFF;
+        }
+        assert!(matches!(
+            decompress(&bad_header),
+            Err(HuffmanError::ChecksumMismatch { .. })
+        ));
+        assert!(matches!(
+            decompress(&encoded[..encoded.len() - 100]),
+            Err(HuffmanError::TruncatedStream)
+        ));
+        assert!(matches!(
+            decompress(&encoded[..encoded.len() - 2]),
+            Err(HuffmanError::TruncatedStream)
+        ));
+        let mut bad_data = encoded.clone();
+        let middle = bad_data.len() - 40;
+        bad_data[middle] ^= 0x10;
+        assert!(decompress(&bad_data).is_err());
+        let mut bad_footer = encoded.clone();
+        let last = bad_footer.len() - 1;
+        bad_footer[last] ^= 0x01;
+        assert!(matches!(
+            decompress(&bad_footer),
+            Err(HuffmanError::ChecksumMismatch { .. })
+        ));
+
+        for path in [source, compressed, decompressed].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
 }
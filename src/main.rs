@@ -1,97 +1,604 @@
 //! A command line tool for compressing/decompressing files directly from the
 //! command line.
-use rscompress_huffman::huffman::encode::{calculate_length, Encoder};
-use rscompress_huffman::huffman::{generate_extended_codewords, Huffman};
-use rscompress_huffman::stats::generate_histogram;
-use rscompress_huffman::BUF;
+//!
+//! The interface follows `gzip`: files are compressed to `<file>.pzhf` and the
+//! original is removed unless `-k` is given. Without files or for the file `-`
+//! the data is read from stdin and written to stdout.
+use rscompress_huffman::block::DEFAULT_BLOCK_SIZE;
+use rscompress_huffman::filter::Filter;
+use rscompress_huffman::format::Preamble;
+use rscompress_huffman::info::{inspect, Info};
+use rscompress_huffman::transform::{bwt, lz77};
+use rscompress_huffman::{HuffmanError, BUF};
 use std::env;
 use std::fs::File;
 use std::io::prelude::*;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufReader, BufWriter, IsTerminal};
+use std::path::{Path, PathBuf};
 
 use env_logger; // trace < debug < info < warn < error < off
-use log::{info, log_enabled};
+use log::info;
 
-/// Main function (duh!)
+/// Suffix of compressed files
+const SUFFIX: &str = ".pzhf";
+
+/// Suffix of files compressed with `--gzip`
+const GZIP_SUFFIX: &str = ".gz";
+
+const USAGE: &str = "\
+Usage: rscompress-huffman [COMMAND] [OPTIONS] [FILE]...
+
+Commands:
+  compress    Compress FILEs to FILE.pzhf (default)
+  decompress  Decompress FILE.pzhf to FILE
+  info        Show information about compressed FILEs
+  test        Check the integrity of compressed FILEs
+
+Options:
+  -c, --stdout   Write to stdout and keep the input files
+  -k, --keep     Keep the input files
+  -f, --force    Overwrite existing output files
+      --adaptive Compress in a single pass with an adaptive code
+      --context  Compress with a code per preceding byte
+      --bwt      Compress with block sorting, best for text
+      --lz77[=WINDOW]
+                 Compress repeated strings up to WINDOW bytes back
+                 (default 65536, at most 16777216)
+      --gzip     Use the gzip format and FILE.gz instead
+      --delta=N[le|be]
+                 Compress the differences of N byte elements (1, 2, 4, 8),
+                 little endian unless `be` is given
+      --json     Print the information as JSON (info)
+  -h, --help     Print this help
+  -V, --version  Print the version
+
+Without FILEs or for the FILE `-` stdin is read.";
+
+/// Exit code if processing any of the files failed
+const EXIT_ERROR: i32 = 1;
+/// Exit code for invalid arguments
+const EXIT_USAGE: i32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Command {
+    Compress,
+    Decompress,
+    Info,
+    Test,
+}
+
+/// Coding of the data
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// Blocks with their own tables of the filtered data, the default
+    Blocks(Filter),
+    Adaptive,
+    Context,
+    BlockSorting,
+    /// LZ77 with a window of the given size
+    Lz77(usize),
+    Gzip,
+}
+
+#[derive(Debug, PartialEq)]
+struct Options {
+    command: Command,
+    stdout: bool,
+    keep: bool,
+    force: bool,
+    json: bool,
+    mode: Mode,
+    files: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Parsed {
+    Run(Options),
+    Help,
+    Version,
+}
+
+/// Reason why a single file was not processed
+#[derive(Debug)]
+enum FileError {
+    /// Reading, writing or coding the data failed
+    Failed(HuffmanError),
+    /// The file was not touched, e.g. to keep an existing output
+    Refused(String),
+}
+
+impl std::fmt::Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FileError::Failed(err) => write!(f, "{}", err),
+            FileError::Refused(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<HuffmanError> for FileError {
+    fn from(err: HuffmanError) -> Self {
+        FileError::Failed(err)
+    }
+}
+
+impl From<std::io::Error> for FileError {
+    fn from(err: std::io::Error) -> Self {
+        FileError::Failed(err.into())
+    }
+}
+
+/// Parse the arguments without the program name
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Parsed, String> {
+    let mut options = Options {
+        command: Command::Compress,
+        stdout: false,
+        keep: false,
+        force: false,
+        json: false,
+        mode: Mode::Blocks(Filter::None),
+        files: Vec::new(),
+    };
+    // Modes given so far, a repeated option replaces its earlier value
+    let mut modes: Vec<Mode> = Vec::new();
+    let mut set_mode = |mode: Mode| {
+        let kind = std::mem::discriminant(&mode);
+        modes.retain(|other| std::mem::discriminant(other) != kind);
+        modes.push(mode);
+    };
+    let mut filter = Filter::None;
+    let mut args = args.into_iter().peekable();
+    if let Some(first) = args.peek() {
+        let command = match first.as_str() {
+            "compress" => Some(Command::Compress),
+            "decompress" => Some(Command::Decompress),
+            "info" => Some(Command::Info),
+            "test" => Some(Command::Test),
+            _ => None,
+        };
+        if let Some(command) = command {
+            options.command = command;
+            args.next();
+        }
+    }
+    let mut only_files = false;
+    for arg in args {
+        if only_files || arg == "-" || !arg.starts_with('-') {
+            options.files.push(arg);
+            continue;
+        }
+        let flags: Vec<char> = match arg.as_str() {
+            "--" => {
+                only_files = true;
+                continue;
+            }
+            "--json" => {
+                options.json = true;
+                continue;
+            }
+            "--adaptive" => {
+                set_mode(Mode::Adaptive);
+                continue;
+            }
+            "--context" => {
+                set_mode(Mode::Context);
+                continue;
+            }
+            "--bwt" => {
+                set_mode(Mode::BlockSorting);
+                continue;
+            }
+            "--lz77" => {
+                set_mode(Mode::Lz77(lz77::DEFAULT_WINDOW));
+                continue;
+            }
+            window if window.starts_with("--lz77=") => {
+                let window = window["--lz77=".len()..]
+                    .parse()
+                    .ok()
+                    .filter(|&window| lz77::validate_window(window).is_ok())
+                    .ok_or_else(|| format!("invalid window '{}'", window))?;
+                set_mode(Mode::Lz77(window));
+                continue;
+            }
+            "--gzip" => {
+                set_mode(Mode::Gzip);
+                continue;
+            }
+            delta if delta.starts_with("--delta=") => {
+                filter = parse_delta(&delta["--delta=".len()..])
+                    .ok_or_else(|| format!("invalid delta filter '{}'", delta))?;
+                continue;
+            }
+            "--stdout" => vec!['c'],
+            "--keep" => vec!['k'],
+            "--force" => vec!['f'],
+            "--help" => vec!['h'],
+            "--version" => vec!['V'],
+            long if long.starts_with("--") => return Err(format!("unknown option '{}'", long)),
+            short => short.chars().skip(1).collect(),
+        };
+        for flag in flags {
+            match flag {
+                'c' => options.stdout = true,
+                'k' => options.keep = true,
+                'f' => options.force = true,
+                'h' => return Ok(Parsed::Help),
+                'V' => return Ok(Parsed::Version),
+                other => return Err(format!("unknown option '-{}'", other)),
+            }
+        }
+    }
+    if modes.len() > 1 {
+        return Err(
+            "only one of --adaptive, --context, --bwt, --lz77 and --gzip can be given".to_string(),
+        );
+    }
+    if filter != Filter::None && !modes.is_empty() {
+        return Err(
+            "--delta can not be combined with --adaptive, --context, --bwt, --lz77 or --gzip"
+                .to_string(),
+        );
+    }
+    options.mode = modes.pop().unwrap_or(Mode::Blocks(filter));
+    let compress_only = !matches!(options.mode, Mode::Blocks(Filter::None) | Mode::Gzip);
+    if options.command != Command::Compress && compress_only {
+        return Err(
+            "--adaptive, --context, --bwt, --lz77 and --delta only apply to compress".to_string(),
+        );
+    }
+    if options.command == Command::Info && options.mode == Mode::Gzip {
+        return Err("--gzip does not apply to info".to_string());
+    }
+    Ok(Parsed::Run(options))
+}
+
+/// Parse the element width and the optional endianness of `--delta`
+fn parse_delta(value: &str) -> Option<Filter> {
+    let (width, big_endian) = if let Some(width) = value.strip_suffix("be") {
+        (width, true)
+    } else {
+        (value.strip_suffix("le").unwrap_or(value), false)
+    };
+    Filter::delta(width.parse().ok()?, big_endian).ok()
+}
 
+/// Main function (duh!)
 fn main() {
     env_logger::init();
-    let source = env::args().nth(1).expect("No source file found!");
-    let destination = env::args().nth(2).expect("No destination file found");
-    let method = env::args().nth(3).expect("No method found");
-    let operation = env::args().nth(4).expect("No operation defined");
-    if method == "h".to_string() {
-        info!("Huffman with header information");
-        if operation == "c" {
-            info!("Compressing file ... ");
-            rscompress_huffman::stream_compress_with_header_information(&source, &destination)
-        } else if operation == "d" {
-            info!("Decompressing file ... ");
-            rscompress_huffman::stream_decompress_with_header_information(&source, &destination)
-        } else {
-            panic!("Do not understand operation")
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(Parsed::Run(options)) => options,
+        Ok(Parsed::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Ok(Parsed::Version) => {
+            println!("rscompress-huffman {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(msg) => {
+            eprintln!("rscompress-huffman: {}\n\n{}", msg, USAGE);
+            std::process::exit(EXIT_USAGE);
         }
+    };
+    let stdin = vec!["-".to_string()];
+    let files = if options.files.is_empty() {
+        &stdin
     } else {
-        info!("Huffman without header information");
-        old_main();
-    }
-}
-
-fn old_main() {
-    let source = env::args().nth(1).expect("No source file found!");
-    let destination = env::args().nth(2).expect("No destination file found");
-    info!("Starting compression");
-    info!("Input:  {}", &source);
-    info!("Output: {}", &destination);
-    let sfile = File::open(source).expect("Failed to open source file");
-    let dfile = File::create(destination).expect("Failed to create destination file");
-
-    let mut reader = BufReader::with_capacity(BUF, sfile);
-    let mut buffer: Vec<u8> = Vec::with_capacity(BUF);
-    unsafe { buffer.set_len(BUF) }
-
-    info!("Generating histogram...");
-    let histogram = generate_histogram(&mut reader);
-    info!("Generating codewords...");
-    let (codewords, length) = generate_extended_codewords(&histogram);
-
-    let w = BufWriter::with_capacity(BUF, dfile);
-    let h = Huffman::new(codewords, length);
-    let mut writer = Encoder::new(w, &h);
-    if log_enabled!(log::Level::Debug) || log_enabled!(log::Level::Info) {
-        let mut original_file_size = 0;
-        let mut huffmann_file_size = 0;
-        for (count, code) in histogram.iter().zip(codewords.iter()) {
-            original_file_size += count;
-            huffmann_file_size += count * calculate_length(*code);
-        }
-        huffmann_file_size = huffmann_file_size / 8 + 1;
-        info!("Original file size: {}", original_file_size);
-        info!("Huffman file size:  {}", huffmann_file_size);
-        info!(
-            "Compression factor: {:.2}",
-            original_file_size as f32 / huffmann_file_size as f32
-        );
-        info!(
-            "Compression ratio:  {:.2}",
-            huffmann_file_size as f32 / original_file_size as f32
-        );
+        &options.files
+    };
+    let mut code = 0;
+    for file in files.iter() {
+        if let Err(err) = run(&options, file) {
+            eprintln!("rscompress-huffman: {}: {}", display_name(file), err);
+            code = EXIT_ERROR;
+        }
     }
+    std::process::exit(code);
+}
+
+fn display_name(file: &str) -> &str {
+    if file == "-" {
+        "stdin"
+    } else {
+        file
+    }
+}
+
+/// Run the command on a single file
+fn run(options: &Options, file: &str) -> Result<(), FileError> {
+    match options.command {
+        Command::Compress | Command::Decompress => {
+            let compress = options.command == Command::Compress;
+            if file == "-" || options.stdout {
+                let stdout = std::io::stdout();
+                if compress && !options.force && stdout.is_terminal() {
+                    return Err(FileError::Refused(
+                        "compressed data not written to a terminal, use -f to force".to_string(),
+                    ));
+                }
+                let reader = open_input(file)?;
+                let writer = BufWriter::with_capacity(BUF, stdout.lock());
+                code(options.mode, compress, reader, writer)?;
+                return Ok(());
+            }
+            let suffix = if options.mode == Mode::Gzip {
+                GZIP_SUFFIX
+            } else {
+                SUFFIX
+            };
+            let destination = output_name(file, suffix, compress, options.force)?;
+            if destination.exists() && !options.force {
+                return Err(FileError::Refused(format!(
+                    "{} already exists, use -f to overwrite",
+                    destination.display()
+                )));
+            }
+            let dest = destination
+                .to_str()
+                .expect("Output name derived from input");
+            let action = if compress {
+                "Compressing"
+            } else {
+                "Decompressing"
+            };
+            info!("{} {} to {} with {:?}", action, file, dest, options.mode);
+            let result = match (compress, options.mode) {
+                // Files store their size and are decoded in parallel
+                (true, Mode::Blocks(filter)) => rscompress_huffman::stream_compress_with_filter(
+                    file,
+                    dest,
+                    DEFAULT_BLOCK_SIZE,
+                    threads(),
+                    filter,
+                ),
+                (false, Mode::Blocks(_)) => {
+                    rscompress_huffman::stream_decompress_parallel(file, dest, threads())
+                }
+                // Files are read twice instead of being held in memory
+                (true, Mode::Context) => code_file(file, dest, |reader, writer| {
+                    rscompress_huffman::compress_context(reader, writer).map(drop)
+                }),
+                (compress, mode) => code_file(file, dest, |reader, writer| {
+                    code(mode, compress, reader, writer)
+                }),
+            };
+            if let Err(err) = result {
+                // Do not leave a partial output behind
+                let _ = std::fs::remove_file(&destination);
+                return Err(err.into());
+            }
+            if !options.keep {
+                std::fs::remove_file(file)?;
+            }
+            Ok(())
+        }
+        Command::Info => {
+            let info = if file == "-" {
+                Info::from_preamble(&Preamble::read_from(&mut open_input(file)?)?)?
+            } else {
+                inspect(&mut BufReader::with_capacity(BUF, File::open(file)?))?
+            };
+            if options.json {
+                writeln!(std::io::stdout(), "{}", info.to_json()?)?;
+            } else {
+                writeln!(std::io::stdout(), "{}:\n{}", display_name(file), info)?;
+            }
+            Ok(())
+        }
+        Command::Test => {
+            code(options.mode, false, open_input(file)?, std::io::sink())?;
+            writeln!(std::io::stdout(), "{}: OK", display_name(file))?;
+            Ok(())
+        }
+    }
+}
+
+/// Number of threads for the blocks
+fn threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
 
-    reader
-        .seek(std::io::SeekFrom::Start(0))
-        .expect("Can not move to start of file");
-    info!("Starting writing...");
-    loop {
-        let read_size = reader.read(&mut buffer);
-        match read_size {
-            Ok(0) => break, // fully read file
-            Ok(n) => writer
-                .write(&buffer[..n])
-                .expect("Could not write buffer to destination"),
-            Err(err) => panic!("Problem with reading source file: {:?}", err),
+/// Compress or decompress everything from `reader` into `writer` with `mode`
+fn code<R: Read, W: Write>(
+    mode: Mode,
+    compress: bool,
+    mut reader: R,
+    writer: W,
+) -> Result<(), HuffmanError> {
+    if !compress {
+        return match mode {
+            Mode::Gzip => rscompress_huffman::decompress_gzip(reader, writer).map(drop),
+            _ => rscompress_huffman::decompress(reader, writer).map(drop),
         };
     }
-    writer.flush().expect("Could not flush file to disk!");
-    info!("End compression")
+    match mode {
+        Mode::Blocks(filter) => rscompress_huffman::compress_with_filter(
+            reader,
+            writer,
+            DEFAULT_BLOCK_SIZE,
+            threads(),
+            filter,
+        ),
+        Mode::Adaptive => rscompress_huffman::compress_adaptive(reader, writer),
+        Mode::Context => {
+            // The context model needs two passes over the data
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            rscompress_huffman::compress_context(std::io::Cursor::new(data), writer)
+        }
+        Mode::BlockSorting => {
+            rscompress_huffman::compress_block_sorting(reader, writer, bwt::DEFAULT_BLOCK_SIZE)
+        }
+        Mode::Lz77(window) => rscompress_huffman::compress_lz77(reader, writer, window),
+        Mode::Gzip => rscompress_huffman::compress_gzip(reader, writer),
+    }
+    .map(drop)
+}
+
+/// Run `coding` from the file `source` into the file `destination`
+fn code_file<F>(source: &str, destination: &str, coding: F) -> Result<(), HuffmanError>
+where
+    F: FnOnce(BufReader<File>, BufWriter<File>) -> Result<(), HuffmanError>,
+{
+    let reader = BufReader::with_capacity(BUF, File::open(source)?);
+    let writer = BufWriter::with_capacity(BUF, File::create(destination)?);
+    coding(reader, writer)
+}
+
+/// Open a file or stdin for `-`
+fn open_input(file: &str) -> Result<Box<dyn Read>, HuffmanError> {
+    if file == "-" {
+        Ok(Box::new(BufReader::with_capacity(BUF, std::io::stdin())))
+    } else {
+        Ok(Box::new(BufReader::with_capacity(BUF, File::open(file)?)))
+    }
+}
+
+/// Name of the output file: `<file><suffix>` for compression, `<file>`
+/// without the suffix for decompression
+fn output_name(
+    file: &str,
+    suffix: &str,
+    compress: bool,
+    force: bool,
+) -> Result<PathBuf, FileError> {
+    if compress {
+        if file.ends_with(suffix) && !force {
+            return Err(FileError::Refused(format!(
+                "already has {} suffix, use -f to compress again",
+                suffix
+            )));
+        }
+        Ok(PathBuf::from(format!("{}{}", file, suffix)))
+    } else {
+        match file.strip_suffix(suffix) {
+            Some(stem) if !stem.is_empty() && !stem.ends_with('/') => Ok(Path::new(stem).into()),
+            _ => Err(FileError::Refused(format!(
+                "unknown suffix, expected {}",
+                suffix
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Parsed, String> {
+        parse_args(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn arguments() {
+        let expected = Options {
+            command: Command::Decompress,
+            stdout: true,
+            keep: true,
+            force: false,
+            json: false,
+            mode: Mode::Blocks(Filter::None),
+            files: vec!["a.pzhf".to_string(), "-".to_string(), "-k".to_string()],
+        };
+        let parsed = parse(&["decompress", "-ck", "a.pzhf", "-", "--", "-k"]);
+        assert_eq!(parsed, Ok(Parsed::Run(expected)));
+
+        // Commands are only recognised as the first argument
+        match parse(&["--force", "info"]) {
+            Ok(Parsed::Run(options)) => {
+                assert_eq!(options.command, Command::Compress);
+                assert!(options.force);
+                assert_eq!(options.files, vec!["info".to_string()]);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        match parse(&["info", "--json", "a.pzhf"]) {
+            Ok(Parsed::Run(options)) => {
+                assert_eq!(options.command, Command::Info);
+                assert!(options.json);
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert_eq!(parse(&["test", "-kh"]), Ok(Parsed::Help));
+        assert_eq!(parse(&["-V"]), Ok(Parsed::Version));
+        match parse(&["--delta=4be", "a"]) {
+            Ok(Parsed::Run(options)) => {
+                assert_eq!(options.mode, Mode::Blocks(Filter::delta(4, true).unwrap()));
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        match parse(&["--delta=2"]) {
+            Ok(Parsed::Run(options)) => {
+                assert_eq!(options.mode, Mode::Blocks(Filter::delta(2, false).unwrap()));
+            }
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert!(parse(&["--delta=3"]).is_err());
+        assert!(parse(&["--delta=4xe"]).is_err());
+        assert!(parse(&["--delta=4", "--adaptive"]).is_err());
+        assert!(parse(&["--delta=4", "--bwt"]).is_err());
+        assert!(parse(&["--bwt", "--context"]).is_err());
+        match parse(&["--bwt", "a"]) {
+            Ok(Parsed::Run(options)) => assert_eq!(options.mode, Mode::BlockSorting),
+            other => panic!("Unexpected {:?}", other),
+        }
+        match parse(&["--lz77", "a"]) {
+            Ok(Parsed::Run(options)) => assert_eq!(options.mode, Mode::Lz77(1 << 16)),
+            other => panic!("Unexpected {:?}", other),
+        }
+        match parse(&["--lz77=1024"]) {
+            Ok(Parsed::Run(options)) => assert_eq!(options.mode, Mode::Lz77(1024)),
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert!(parse(&["--lz77=0"]).is_err());
+        // A repeated option replaces its earlier value
+        match parse(&["--lz77=1024", "--lz77=2048"]) {
+            Ok(Parsed::Run(options)) => assert_eq!(options.mode, Mode::Lz77(2048)),
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert!(parse(&["--lz77", "--bwt"]).is_err());
+        match parse(&["--gzip", "a"]) {
+            Ok(Parsed::Run(options)) => assert_eq!(options.mode, Mode::Gzip),
+            other => panic!("Unexpected {:?}", other),
+        }
+        assert!(parse(&["--gzip", "--lz77"]).is_err());
+        assert!(parse(&["--gzip", "--delta=2"]).is_err());
+        // Modes of the compression are not silently ignored
+        assert!(parse(&["decompress", "--bwt", "a.pzhf"]).is_err());
+        assert!(parse(&["decompress", "--context"]).is_err());
+        assert!(parse(&["test", "--lz77"]).is_err());
+        assert!(parse(&["info", "--adaptive"]).is_err());
+        assert!(parse(&["decompress", "--delta=2"]).is_err());
+        assert!(parse(&["info", "--gzip"]).is_err());
+        assert!(matches!(parse(&["test", "--gzip"]), Ok(Parsed::Run(_))));
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["--fast"]).is_err());
+    }
+
+    #[test]
+    fn output_names() {
+        assert_eq!(
+            output_name("a.txt", SUFFIX, true, false).unwrap(),
+            PathBuf::from("a.txt.pzhf")
+        );
+        assert!(output_name("a.pzhf", SUFFIX, true, false).is_err());
+        assert!(output_name("a.pzhf", SUFFIX, true, true).is_ok());
+        assert_eq!(
+            output_name("a.txt", GZIP_SUFFIX, true, false).unwrap(),
+            PathBuf::from("a.txt.gz")
+        );
+        assert!(output_name("a.gz", GZIP_SUFFIX, true, false).is_err());
+        assert_eq!(
+            output_name("dir/a.pzhf", SUFFIX, false, false).unwrap(),
+            PathBuf::from("dir/a")
+        );
+        assert!(output_name("a.txt", SUFFIX, false, false).is_err());
+        assert!(output_name(".pzhf", SUFFIX, false, false).is_err());
+        let err = output_name("a.pzhf", SUFFIX, true, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "already has .pzhf suffix, use -f to compress again"
+        );
+    }
 }
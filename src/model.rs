@@ -1,7 +1,14 @@
 use std::collections::BTreeMap;
 
+/// Largest supported alphabet. Symbols are decoded into `u16`.
+pub const MAX_ALPHABET_SIZE: usize = 1 << 16;
+
 pub trait Model {
-    fn encode(&self, sym: u8) -> (usize, usize);
+    fn encode(&self, sym: usize) -> (usize, usize);
     fn sentinel(&self) -> usize;
-    fn to_btreemap(&self) -> BTreeMap<usize, (u8, u8)>;
+    fn to_btreemap(&self) -> BTreeMap<usize, (u16, u8)>;
+    /// Number of symbols of the alphabet, i.e. symbols are `0..alphabet_size()`
+    fn alphabet_size(&self) -> usize {
+        256
+    }
 }
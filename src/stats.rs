@@ -1,7 +1,8 @@
 //! This module implements some statistical helper functions.
 //! Mostly due to the histogram needed for defining the Huffman tree.
+use crate::error::Result;
 use crate::BUF;
-use std::io::Read;
+use std::io::{ErrorKind, Read};
 
 fn update_histogram(take: usize, buffer: &[u8], histogram: &mut [usize]) {
     for byte in buffer.iter().take(take) {
@@ -10,7 +11,7 @@ fn update_histogram(take: usize, buffer: &[u8], histogram: &mut [usize]) {
 }
 
 /// Build a histogram for each byte.
-pub fn generate_histogram(reader: &mut impl Read) -> [usize; 256] {
+pub fn generate_histogram(reader: &mut impl Read) -> Result<[usize; 256]> {
     let mut buffer: Vec<u8> = Vec::with_capacity(BUF);
     unsafe { buffer.set_len(BUF) };
     let mut histogram: [usize; 256] = [0; 256];
@@ -21,10 +22,23 @@ pub fn generate_histogram(reader: &mut impl Read) -> [usize; 256] {
         match read_size {
             Ok(0) => break, // fully read file
             Ok(n) => update_histogram(n, &buffer, &mut histogram),
-            Err(err) => panic!("Problem with reading source file: {:?}", err),
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
         };
     }
-    histogram
+    Ok(histogram)
+}
+
+/// Compression ratio, i.e. the compressed size relative to the original size.
+/// Smaller is better.
+pub fn compression_ratio(original: u64, compressed: u64) -> f64 {
+    compressed as f64 / original as f64
+}
+
+/// Compression factor, i.e. the original size relative to the compressed
+/// size. Larger is better.
+pub fn compression_factor(original: u64, compressed: u64) -> f64 {
+    original as f64 / compressed as f64
 }
 
 use rand::distributions::WeightedIndex;
@@ -49,7 +63,7 @@ mod tests {
     #[test]
     fn testing_histogram() {
         let mut data = Cursor::new(vec![3, 32, 34, 34, 34, 2, 0]);
-        let hist = generate_histogram(&mut data);
+        let hist = generate_histogram(&mut data).unwrap();
 
         assert_eq!(hist[233], 0);
         assert_eq!(hist[3], 1);
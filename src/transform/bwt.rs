@@ -0,0 +1,587 @@
+//! Block sorting, i.e. Burrows-Wheeler transform, move-to-front and zero
+//! run-length coding in front of the Huffman coding
+//!
+//! Order-0 Huffman coding ignores the context of a byte. The Burrows-Wheeler
+//! transform sorts all suffixes of a block and outputs the byte preceding
+//! every suffix. Bytes followed by similar contexts end up next to each
+//! other, therefore the output consists of long runs of few distinct bytes.
+//! Move-to-front replaces every byte by its position in a list of recently
+//! used bytes, which turns the runs into runs of zeros. The zeros are
+//! run-length coded, the remaining positions are coded as they are.
+//!
+//! # Burrows-Wheeler transform
+//! The suffixes are sorted via a suffix array. The block is terminated by a
+//! virtual sentinel smaller than every byte, which is not part of the output.
+//! Instead, the primary index stores the row of the sentinel, i.e. the row
+//! of the whole block. The inverse walks the rows backwards from the row of
+//! the empty suffix until it reaches the primary index.
+//!
+//! # Zero run-length coding
+//! The symbols form an alphabet of `ALPHABET_SIZE` symbols. A run of `n`
+//! zeros is written in bijective base 2 with the digits `RUN_A` (1) and
+//! `RUN_B` (2), least significant digit first. Any other position `p` is
+//! written as the symbol `p + 1`.
+//!
+//! # Layout of a block
+//! - 4 byte size of the block (big endian)
+//! - 4 byte primary index (big endian)
+//! - 4 byte number of symbols after the run-length coding (big endian)
+//! - 4 byte length of the encoded data (big endian)
+//! - 2 byte length of the table (big endian)
+//! - x byte run-length encoded codeword lengths of all symbols, see
+//!   `huffman::header::encode_alphabet_lengths`
+//! - x byte encoded symbols, padded to a full byte
+//!
+//! The last block is followed by an end marker, i.e. a block of size zero
+//! without further fields. The CRC-32 of the original data follows the end
+//! marker, if enabled via `BlockSortingWriter::use_checksum`.
+use crate::checksum::Crc32;
+use crate::error::{HuffmanError, Result};
+use crate::format::read_exact_or_truncated;
+use crate::huffman::decode::{Decoder, MAX_SENTINEL};
+use crate::huffman::encode::Encoder;
+use crate::huffman::header::{decode_alphabet_lengths, encode_alphabet_lengths, model_lengths};
+use crate::huffman::Huffman;
+use log::debug;
+use std::io::{Read, Write};
+
+/// Default number of bytes per block, as for `bzip2 -9`
+pub const DEFAULT_BLOCK_SIZE: usize = 900_000;
+
+/// Largest number of bytes per block. Bounds the memory needed for the
+/// suffix array.
+pub const MAX_BLOCK_SIZE: usize = 1 << 24;
+
+/// First digit of a run of zeros, worth 1
+pub const RUN_A: u16 = 0;
+
+/// Second digit of a run of zeros, worth 2
+pub const RUN_B: u16 = 1;
+
+/// `RUN_A`, `RUN_B` and the move-to-front positions `1..=255`
+pub const ALPHABET_SIZE: usize = 257;
+
+/// Check that `block_size` is usable for block sorting
+pub fn validate_block_size(block_size: usize) -> Result<()> {
+    if block_size == 0 || block_size > MAX_BLOCK_SIZE {
+        return Err(HuffmanError::InvalidCode(format!(
+            "Block size {} not in 1..={}",
+            block_size, MAX_BLOCK_SIZE
+        )));
+    }
+    Ok(())
+}
+
+/// Start positions of all suffixes of `data` in lexicographic order. A
+/// suffix sorts before all longer suffixes it is a prefix of.
+///
+/// Uses prefix doubling: after each round the suffixes are sorted by their
+/// first `2k` bytes, using the ranks of the first `k` bytes of a suffix and
+/// of the suffix `k` bytes later as keys of a radix sort.
+pub fn suffix_array(data: &[u8]) -> Vec<u32> {
+    let n = data.len();
+    let mut sa: Vec<u32> = (0..n as u32).collect();
+    sa.sort_by_key(|&i| data[i as usize]);
+    let mut rank: Vec<u32> = data.iter().map(|&b| b as u32).collect();
+    let mut next = vec![0u32; n];
+    let mut count = vec![0usize; n.max(256) + 1];
+    let mut k = 1;
+    while k < n {
+        // Order by the second key: suffixes without `k` further bytes first
+        let mut ix = 0;
+        for i in n - k..n {
+            next[ix] = i as u32;
+            ix += 1;
+        }
+        for &i in sa.iter().filter(|&&i| i as usize >= k) {
+            next[ix] = i - k as u32;
+            ix += 1;
+        }
+        // Stable counting sort by the first key
+        count.iter_mut().for_each(|c| *c = 0);
+        for &r in rank.iter() {
+            count[r as usize + 1] += 1;
+        }
+        for r in 1..count.len() {
+            count[r] += count[r - 1];
+        }
+        for &i in next.iter() {
+            let r = rank[i as usize] as usize;
+            sa[count[r]] = i;
+            count[r] += 1;
+        }
+        // Rank by both keys
+        let second = |i: usize| if i + k < n { rank[i + k] + 1 } else { 0 };
+        next[sa[0] as usize] = 0;
+        for w in 1..n {
+            let (previous, current) = (sa[w - 1] as usize, sa[w] as usize);
+            let differs = rank[previous] != rank[current] || second(previous) != second(current);
+            next[current] = next[previous] + differs as u32;
+        }
+        std::mem::swap(&mut rank, &mut next);
+        if rank[sa[n - 1] as usize] as usize == n - 1 {
+            break;
+        }
+        k *= 2;
+    }
+    sa
+}
+
+/// Burrows-Wheeler transform of `data`. Returns the byte preceding every
+/// suffix in sorted order, without the sentinel, and the primary index.
+pub fn bwt(data: &[u8]) -> (Vec<u8>, usize) {
+    let n = data.len();
+    if n == 0 {
+        return (Vec::new(), 0);
+    }
+    let mut last = Vec::with_capacity(n);
+    // Row 0 is the empty suffix, preceded by the last byte
+    last.push(data[n - 1]);
+    let mut primary = 0;
+    for (row, &i) in suffix_array(data).iter().enumerate() {
+        match i as usize {
+            0 => primary = row + 1,
+            i => last.push(data[i - 1]),
+        }
+    }
+    (last, primary)
+}
+
+/// Revert `bwt`
+pub fn inverse_bwt(last: &[u8], primary: usize) -> Result<Vec<u8>> {
+    let n = last.len();
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    if primary == 0 || primary > n {
+        return Err(HuffmanError::InvalidCode(format!(
+            "Primary index {} not in 1..={}",
+            primary, n
+        )));
+    }
+    // The row `primary` holds the sentinel, which is smaller than all bytes
+    let byte = |row: usize| {
+        if row < primary {
+            last[row]
+        } else {
+            last[row - 1]
+        }
+    };
+    // First row of the suffixes starting with every byte
+    let mut start = [0usize; 256];
+    start[0] = 1;
+    for &b in last.iter().filter(|&&b| b < u8::MAX) {
+        start[b as usize + 1] += 1;
+    }
+    for b in 1..256 {
+        start[b] += start[b - 1];
+    }
+    // Row of the suffix starting one byte earlier
+    let mut previous = vec![0u32; n + 1];
+    for row in (0..=n).filter(|&row| row != primary) {
+        let b = byte(row) as usize;
+        previous[row] = start[b] as u32;
+        start[b] += 1;
+    }
+    let mut data = vec![0u8; n];
+    let mut row = 0;
+    for value in data.iter_mut().rev() {
+        if row == primary {
+            return Err(HuffmanError::InvalidCode(
+                "Primary index reached too early".to_string(),
+            ));
+        }
+        *value = byte(row);
+        row = previous[row] as usize;
+    }
+    Ok(data)
+}
+
+/// Replace every byte by its position in the list of recently used bytes
+pub fn move_to_front(data: &mut [u8]) {
+    let mut order: Vec<u8> = (0..=255).collect();
+    for value in data.iter_mut() {
+        let position = order.iter().position(|&b| b == *value).unwrap_or(0);
+        order[..=position].rotate_right(1);
+        *value = position as u8;
+    }
+}
+
+/// Revert `move_to_front`
+pub fn inverse_move_to_front(data: &mut [u8]) {
+    let mut order: Vec<u8> = (0..=255).collect();
+    for value in data.iter_mut() {
+        let position = *value as usize;
+        order[..=position].rotate_right(1);
+        *value = order[0];
+    }
+}
+
+/// Code the runs of zeros of `positions`, see the module documentation
+pub fn encode_runs(positions: &[u8]) -> Vec<u16> {
+    let mut symbols = Vec::with_capacity(positions.len() / 2);
+    let mut ix = 0;
+    while ix < positions.len() {
+        if positions[ix] != 0 {
+            symbols.push(positions[ix] as u16 + 1);
+            ix += 1;
+            continue;
+        }
+        let run = positions[ix..].iter().take_while(|&&p| p == 0).count();
+        let mut digits = run + 1;
+        while digits > 1 {
+            symbols.push(if digits & 1 == 0 { RUN_A } else { RUN_B });
+            digits >>= 1;
+        }
+        ix += run;
+    }
+    symbols
+}
+
+/// Revert `encode_runs`. Fails if the symbols do not decode to exactly
+/// `size` positions.
+pub fn decode_runs(symbols: &[u16], size: usize) -> Result<Vec<u8>> {
+    let overflow = || HuffmanError::InvalidCode(format!("More than {} positions", size));
+    let mut positions = Vec::with_capacity(size);
+    let mut run = 0usize;
+    let mut weight = 1usize;
+    for &sym in symbols.iter() {
+        if sym == RUN_A || sym == RUN_B {
+            run += weight * (sym - RUN_A + 1) as usize;
+            weight *= 2;
+            if positions.len() + run > size {
+                return Err(overflow());
+            }
+            continue;
+        }
+        if sym as usize >= ALPHABET_SIZE {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Symbol {} is not part of the alphabet",
+                sym
+            )));
+        }
+        positions.resize(positions.len() + run, 0);
+        run = 0;
+        weight = 1;
+        if positions.len() == size {
+            return Err(overflow());
+        }
+        positions.push((sym - 1) as u8);
+    }
+    positions.resize(positions.len() + run, 0);
+    if positions.len() != size {
+        return Err(HuffmanError::InvalidCode(format!(
+            "Expected {} positions, decoded {}",
+            size,
+            positions.len()
+        )));
+    }
+    Ok(positions)
+}
+
+/// Transform and encode `data` as a single block
+pub fn compress_block(data: &[u8]) -> Result<Vec<u8>> {
+    if data.is_empty() {
+        return Err(HuffmanError::InvalidCode("Empty block".to_string()));
+    }
+    validate_block_size(data.len())?;
+    let (mut last, primary) = bwt(data);
+    move_to_front(&mut last);
+    let symbols = encode_runs(&last);
+    let model = Huffman::from_symbols(&symbols, ALPHABET_SIZE)?;
+    let mut enc = Encoder::new(Vec::new(), &model);
+    enc.write_symbols(&symbols)?;
+    enc.flush()?;
+    let mut table = Vec::new();
+    encode_alphabet_lengths(&model_lengths(&model), &mut table)?;
+
+    let mut block = Vec::with_capacity(18 + table.len() + enc.inner.len());
+    block.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    block.extend_from_slice(&(primary as u32).to_be_bytes());
+    block.extend_from_slice(&(symbols.len() as u32).to_be_bytes());
+    block.extend_from_slice(&(enc.inner.len() as u32).to_be_bytes());
+    block.extend_from_slice(&(table.len() as u16).to_be_bytes());
+    block.extend_from_slice(&table);
+    block.extend_from_slice(&enc.inner);
+    debug!(
+        "Block sorting of {} bytes into {} symbols, table {} bytes, data {} bytes",
+        data.len(),
+        symbols.len(),
+        table.len(),
+        enc.inner.len()
+    );
+    Ok(block)
+}
+
+/// Read the next block from `reader` and append the original data to
+/// `output`. Blocks larger than `block_size` are rejected. Returns the
+/// number of decoded bytes, `0` for the end marker.
+pub fn decompress_block<R: Read>(
+    reader: &mut R,
+    block_size: usize,
+    output: &mut Vec<u8>,
+) -> Result<usize> {
+    let mut field = [0u8; 4];
+    read_exact_or_truncated(reader, &mut field)?;
+    let size = u32::from_be_bytes(field) as usize;
+    if size == 0 {
+        return Ok(0);
+    }
+    let mut fields = [0usize; 3];
+    for value in fields.iter_mut() {
+        read_exact_or_truncated(reader, &mut field)?;
+        *value = u32::from_be_bytes(field) as usize;
+    }
+    let [primary, symbols, encoded] = fields;
+    if size > block_size || symbols > size || encoded > (symbols * MAX_SENTINEL).div_ceil(8) {
+        return Err(HuffmanError::CorruptHeader(format!(
+            "Block of {} bytes with {} symbols and {} bytes encoded data",
+            size, symbols, encoded
+        )));
+    }
+    let mut table_length = [0u8; 2];
+    read_exact_or_truncated(reader, &mut table_length)?;
+    let mut table = vec![0u8; u16::from_be_bytes(table_length) as usize];
+    read_exact_or_truncated(reader, &mut table)?;
+    let mut bytes = table.iter().cloned();
+    let lengths = decode_alphabet_lengths(&mut bytes, ALPHABET_SIZE)?;
+    if bytes.next().is_some() {
+        return Err(HuffmanError::CorruptHeader(
+            "Trailing bytes after the block table".to_string(),
+        ));
+    }
+    let model = Huffman::from_lengths(&lengths)?;
+    let mut data = vec![0u8; encoded];
+    read_exact_or_truncated(reader, &mut data)?;
+
+    let mut decoder = Decoder::with_model(data.as_slice(), &model, symbols)?;
+    let mut decoded = vec![0u16; symbols];
+    let mut position = 0;
+    while position < symbols {
+        match decoder.read_symbols(&mut decoded[position..])? {
+            0 => return Err(HuffmanError::TruncatedStream),
+            n => position += n,
+        }
+    }
+    let mut last = decode_runs(&decoded, size)?;
+    inverse_move_to_front(&mut last);
+    output.extend_from_slice(&inverse_bwt(&last, primary)?);
+    Ok(size)
+}
+
+/// Splits everything written into blocks of `block_size` bytes and encodes
+/// them with block sorting. `finish` must be called to encode the last block.
+pub struct BlockSortingWriter<W: Write> {
+    inner: W,
+    block_size: usize,
+    buffer: Vec<u8>,
+    checksum: Option<Crc32>,
+    pub readbytes: u64,
+}
+
+impl<W: Write> BlockSortingWriter<W> {
+    pub fn new(writer: W, block_size: usize) -> Result<Self> {
+        validate_block_size(block_size)?;
+        Ok(BlockSortingWriter {
+            inner: writer,
+            block_size,
+            buffer: Vec::new(),
+            checksum: None,
+            readbytes: 0,
+        })
+    }
+    /// Write a CRC-32 of all data after the end marker
+    pub fn use_checksum(&mut self, enabled: bool) {
+        self.checksum = if enabled { Some(Crc32::new()) } else { None };
+    }
+    fn write_block(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.inner.write_all(&compress_block(&self.buffer)?)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+    /// Encode the last block, write the end marker and the checksum. Returns
+    /// the flushed writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.write_block()?;
+        self.inner.write_all(&[0u8; 4])?;
+        if let Some(crc) = self.checksum {
+            self.inner.write_all(&crc.finalize().to_be_bytes())?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for BlockSortingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.block_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if let Some(crc) = self.checksum.as_mut() {
+            crc.update(&buf[..n]);
+        }
+        self.readbytes += n as u64;
+        if self.buffer.len() == self.block_size {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    /// Only flushes the inner writer, since every flush would end a block
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decodes the blocks written by a `BlockSortingWriter`
+pub struct BlockSortingReader<R: Read> {
+    inner: R,
+    block_size: usize,
+    block: Vec<u8>,
+    position: usize,
+    finished: bool,
+    pub writeout: u64,
+}
+
+impl<R: Read> BlockSortingReader<R> {
+    /// Blocks larger than `block_size` are rejected as corrupt
+    pub fn new(reader: R, block_size: usize) -> Self {
+        BlockSortingReader {
+            inner: reader,
+            block_size,
+            block: Vec::new(),
+            position: 0,
+            finished: false,
+            writeout: 0,
+        }
+    }
+    /// Returns the inner reader, positioned after the end marker once all
+    /// data has been read.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for BlockSortingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.block.len() && !self.finished {
+            self.block.clear();
+            self.position = 0;
+            if decompress_block(&mut self.inner, self.block_size, &mut self.block)? == 0 {
+                self.finished = true;
+            }
+        }
+        let n = buf.len().min(self.block.len() - self.position);
+        buf[..n].copy_from_slice(&self.block[self.position..self.position + n]);
+        self.position += n;
+        self.writeout += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random bytes with `bits` significant bits
+    fn noise(count: usize, bits: u32) -> Vec<u8> {
+        (0..count as u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> (32 - bits)) as u8)
+            .collect()
+    }
+
+    fn naive_suffix_array(data: &[u8]) -> Vec<u32> {
+        let mut sa: Vec<u32> = (0..data.len() as u32).collect();
+        sa.sort_by(|&a, &b| data[a as usize..].cmp(&data[b as usize..]));
+        sa
+    }
+
+    #[test]
+    fn suffix_arrays() {
+        assert_eq!(suffix_array(b"banana"), [5, 3, 1, 0, 4, 2]);
+        for data in [
+            b"".to_vec(),
+            b"a".to_vec(),
+            vec![7u8; 1000],
+            b"abracadabra".repeat(50),
+            noise(2000, 2),
+        ]
+        .iter()
+        {
+            assert_eq!(suffix_array(data), naive_suffix_array(data));
+        }
+    }
+
+    #[test]
+    fn transforms() {
+        let (last, primary) = bwt(b"banana");
+        assert_eq!((last.as_slice(), primary), (&b"annbaa"[..], 4));
+        for data in [
+            b"banana".to_vec(),
+            b"x".to_vec(),
+            vec![0u8; 300],
+            noise(5000, 8),
+        ]
+        .iter()
+        {
+            let (mut last, primary) = bwt(data);
+            assert_eq!(&inverse_bwt(&last, primary).unwrap(), data);
+            move_to_front(&mut last);
+            let symbols = encode_runs(&last);
+            assert!(symbols.iter().all(|&s| (s as usize) < ALPHABET_SIZE));
+            let mut positions = decode_runs(&symbols, data.len()).unwrap();
+            assert_eq!(positions, last);
+            inverse_move_to_front(&mut positions);
+            assert_eq!(inverse_bwt(&positions, primary).unwrap(), *data);
+        }
+        assert_eq!(encode_runs(&[0, 0, 0, 5, 0, 0]), [RUN_A, RUN_A, 6, RUN_B]);
+        assert!(decode_runs(&[RUN_B, 2], 2).is_err());
+        assert!(inverse_bwt(b"abc", 0).is_err());
+        assert!(inverse_bwt(b"abc", 4).is_err());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let text = b"she sells sea shells by the sea shore, ".repeat(3000);
+        let mut data = text.clone();
+        data.extend(noise(20_000, 8));
+        let mut writer = BlockSortingWriter::new(Vec::new(), 50_000).unwrap();
+        writer.use_checksum(true);
+        writer.write_all(&data).unwrap();
+        assert_eq!(writer.readbytes, data.len() as u64);
+        let encoded = writer.finish().unwrap();
+
+        let mut reader = BlockSortingReader::new(encoded.as_slice(), 50_000);
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        let mut footer = reader.into_inner();
+        crate::format::verify_footer(&mut footer, crate::checksum::crc32(&data)).unwrap();
+        assert!(footer.is_empty());
+
+        // The repeated text collapses into a few runs
+        let block = compress_block(&text).unwrap();
+        assert!(block.len() < text.len() / 100, "{} bytes", block.len());
+
+        // Blocks larger than announced are rejected
+        let mut reader = BlockSortingReader::new(encoded.as_slice(), 40_000);
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn corrupt_blocks() {
+        let data = b"abracadabra".repeat(100);
+        let block = compress_block(&data).unwrap();
+        for ix in 0..block.len() {
+            for &flip in [0x01u8, 0x80].iter() {
+                let mut corrupt = block.clone();
+                corrupt[ix] ^= flip;
+                // Must not panic, the output may still be wrong
+                let _ = decompress_block(&mut corrupt.as_slice(), data.len(), &mut Vec::new());
+            }
+        }
+        let mut truncated = &block[..block.len() - 1];
+        assert!(decompress_block(&mut truncated, data.len(), &mut Vec::new()).is_err());
+    }
+}
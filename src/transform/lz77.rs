@@ -0,0 +1,692 @@
+//! LZ77 with canonical Huffman coding of literals, lengths and distances
+//!
+//! Huffman coding alone can not exploit repeated strings. LZ77 replaces a
+//! string which already occurred within the last `window` bytes by a match,
+//! i.e. its length and its distance to the previous occurrence. Matches are
+//! found via hash chains: the positions of all 3 byte strings are chained
+//! per hash of the string, newest first. A match is deferred by one byte if
+//! the next position starts a longer match (lazy matching).
+//!
+//! # Symbols
+//! Literals and match lengths share one alphabet of `LITLEN_ALPHABET`
+//! symbols: the bytes `0..=255`, followed by the lengths `MIN_MATCH..=
+//! MAX_MATCH`. Every length is followed by a distance symbol, which selects
+//! a range of distances via `distance_slot`. The offset within the range
+//! follows the distance symbol as raw extra bits. Both alphabets get their
+//! own canonical Huffman code per block.
+//!
+//! # Layout of a block
+//! - 4 byte size of the block (big endian)
+//! - 4 byte number of literal and length symbols (big endian)
+//! - 4 byte number of matches (big endian)
+//! - 4 byte length of the encoded literals and lengths (big endian)
+//! - 4 byte length of the encoded distances (big endian)
+//! - 2 byte length of both tables (big endian)
+//! - x byte run-length encoded codeword lengths of the literals and lengths,
+//!   followed by those of the distances, see
+//!   `huffman::header::encode_alphabet_lengths`
+//! - x byte encoded literals and lengths, padded to a full byte
+//! - x byte encoded distances and their extra bits, padded to a full byte
+//!
+//! Matches may refer to the previous blocks. The last block is followed by
+//! an end marker, i.e. a block of size zero without further fields. The
+//! CRC-32 of the original data follows the end marker, if enabled via
+//! `Lz77Writer::use_checksum`.
+use crate::checksum::Crc32;
+use crate::error::{HuffmanError, Result};
+use crate::format::read_exact_or_truncated;
+use crate::huffman::decode::{Decoder, MAX_SENTINEL};
+use crate::huffman::encode::Encoder;
+use crate::huffman::header::{decode_alphabet_lengths, encode_alphabet_lengths, model_lengths};
+use crate::huffman::Huffman;
+use log::debug;
+use std::io::{Read, Write};
+
+/// Default size of the window, i.e. the largest distance of a match
+pub const DEFAULT_WINDOW: usize = 1 << 16;
+
+/// Largest size of the window
+pub const MAX_WINDOW: usize = 1 << 24;
+
+/// Number of bytes per block
+pub const BLOCK_SIZE: usize = 1 << 20;
+
+/// Shortest match
+pub const MIN_MATCH: usize = 3;
+
+/// Longest match
+pub const MAX_MATCH: usize = 258;
+
+/// Bytes followed by the match lengths
+pub const LITLEN_ALPHABET: usize = 256 + MAX_MATCH - MIN_MATCH + 1;
+
+/// Distance slots needed for distances up to `MAX_WINDOW`
+pub const DISTANCE_ALPHABET: usize = 48;
+
+/// Number of bits of the hash of a 3 byte string
+const HASH_BITS: u32 = 20;
+
+/// Longest chain searched for a match
+const MAX_CHAIN: usize = 128;
+
+/// Matches of `MIN_MATCH` bytes further back cost more than the literals
+const TOO_FAR: usize = 4096;
+
+/// Check that `window` is usable for LZ77
+pub fn validate_window(window: usize) -> Result<()> {
+    if window == 0 || window > MAX_WINDOW {
+        return Err(HuffmanError::InvalidCode(format!(
+            "Window {} not in 1..={}",
+            window, MAX_WINDOW
+        )));
+    }
+    Ok(())
+}
+
+/// Distance slot of `distance`, its number of extra bits and the value of
+/// the extra bits. Slots `0..4` are the distances `1..=4`, afterwards every
+/// power of two is split into two slots.
+pub fn distance_slot(distance: usize) -> (u16, usize, usize) {
+    let d = distance - 1;
+    if d < 4 {
+        return (d as u16, 0, 0);
+    }
+    let msb = (usize::BITS - 1 - d.leading_zeros()) as usize;
+    let extra = msb - 1;
+    let slot = 2 * msb + ((d >> extra) & 1);
+    (slot as u16, extra, d & ((1 << extra) - 1))
+}
+
+/// Smallest distance of `slot` and its number of extra bits
+pub fn slot_distance(slot: u16) -> (usize, usize) {
+    let slot = slot as usize;
+    if slot < 4 {
+        return (slot + 1, 0);
+    }
+    let extra = slot / 2 - 1;
+    (((2 | (slot & 1)) << extra) + 1, extra)
+}
+
+/// A literal byte or a repetition of `length` bytes starting `distance`
+/// bytes earlier
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Match { length: usize, distance: usize },
+}
+
+/// Hash chains over the positions of a buffer of history and new data. The
+/// chains are kept across blocks: once the front of the buffer is dropped,
+/// `slide` moves the positions along.
+pub struct MatchFinder {
+    window: usize,
+    /// Most recent position + 1 per hash, `0` for none
+    head: Vec<u32>,
+    /// Previous position + 1 with the same hash
+    chain: Vec<u32>,
+    /// The positions before this one are inserted
+    inserted: usize,
+}
+
+impl MatchFinder {
+    pub fn new(window: usize) -> Self {
+        MatchFinder {
+            window,
+            head: vec![0u32; 1 << HASH_BITS],
+            chain: Vec::new(),
+            inserted: 0,
+        }
+    }
+
+    fn hash(data: &[u8], position: usize) -> usize {
+        let bytes = &data[position..position + MIN_MATCH];
+        let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], position: usize) {
+        if position + MIN_MATCH <= data.len() {
+            let hash = MatchFinder::hash(data, position);
+            self.chain[position] = self.head[hash];
+            self.head[hash] = position as u32 + 1;
+            self.inserted = position + 1;
+        }
+    }
+
+    /// Longest match for `position`, which must not reach beyond `end`
+    fn longest(&self, data: &[u8], position: usize, end: usize) -> Option<(usize, usize)> {
+        if position + MIN_MATCH > end {
+            return None;
+        }
+        let limit = (end - position).min(MAX_MATCH);
+        let target = &data[position..position + limit];
+        let mut best = (0, 0);
+        let mut candidate = self.head[MatchFinder::hash(data, position)];
+        for _ in 0..MAX_CHAIN {
+            if candidate == 0 {
+                break;
+            }
+            let start = candidate as usize - 1;
+            if position - start > self.window {
+                break;
+            }
+            let length = data[start..]
+                .iter()
+                .zip(target.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, position - start);
+                if length == limit {
+                    break;
+                }
+            }
+            candidate = self.chain[start];
+        }
+        if best.0 > MIN_MATCH || (best.0 == MIN_MATCH && best.1 <= TOO_FAR) {
+            Some(best)
+        } else {
+            None
+        }
+    }
+
+    /// Split `data[start..]` into tokens. `data[..start]` must be the data
+    /// of the previous calls, minus the bytes dropped via `slide`.
+    pub fn tokenize(&mut self, data: &[u8], start: usize) -> Vec<Token> {
+        self.chain.resize(data.len(), 0);
+        // The last positions of the previous data had too few bytes
+        for position in self.inserted..start {
+            self.insert(data, position);
+        }
+        let end = data.len();
+        let mut tokens = Vec::new();
+        let mut position = start;
+        while position < end {
+            let found = self.longest(data, position, end);
+            self.insert(data, position);
+            // Defer the match, if the next position starts a longer one
+            let deferred = found.is_some_and(|(length, _)| {
+                self.longest(data, position + 1, end)
+                    .is_some_and(|next| next.0 > length)
+            });
+            match found {
+                Some((length, distance)) if !deferred => {
+                    tokens.push(Token::Match { length, distance });
+                    for skipped in position + 1..position + length {
+                        self.insert(data, skipped);
+                    }
+                    position += length;
+                }
+                _ => {
+                    tokens.push(Token::Literal(data[position]));
+                    position += 1;
+                }
+            }
+        }
+        tokens
+    }
+
+    /// Forget the first `count` positions, after the first `count` bytes
+    /// were dropped from the data
+    pub fn slide(&mut self, count: usize) {
+        let count = count.min(self.chain.len());
+        self.chain.drain(..count);
+        for link in self.head.iter_mut().chain(self.chain.iter_mut()) {
+            *link = link.saturating_sub(count as u32);
+        }
+        self.inserted = self.inserted.saturating_sub(count);
+    }
+}
+
+/// Split `data[start..]` into tokens. Matches may refer to `data[..start]`
+/// within the last `window` bytes.
+pub fn tokenize(data: &[u8], start: usize, window: usize) -> Vec<Token> {
+    let mut finder = MatchFinder::new(window);
+    finder.inserted = start.saturating_sub(window);
+    finder.tokenize(data, start)
+}
+
+/// Encode `data[start..]` as a single block, see `tokenize`
+pub fn compress_block(data: &[u8], start: usize, window: usize) -> Result<Vec<u8>> {
+    validate_window(window)?;
+    let mut finder = MatchFinder::new(window);
+    finder.inserted = start.saturating_sub(window);
+    encode_block(data, start, &mut finder)
+}
+
+/// Encode `data[start..]` as a single block with the matches of `finder`
+fn encode_block(data: &[u8], start: usize, finder: &mut MatchFinder) -> Result<Vec<u8>> {
+    let size = data.len() - start;
+    if size == 0 || size > BLOCK_SIZE {
+        return Err(HuffmanError::InvalidCode(format!(
+            "Block size {} not in 1..={}",
+            size, BLOCK_SIZE
+        )));
+    }
+    let tokens = finder.tokenize(data, start);
+    let mut litlens = Vec::with_capacity(tokens.len());
+    let mut slots = Vec::new();
+    for token in tokens.iter() {
+        match *token {
+            Token::Literal(byte) => litlens.push(byte as u16),
+            Token::Match { length, distance } => {
+                litlens.push((256 + length - MIN_MATCH) as u16);
+                slots.push(distance_slot(distance).0);
+            }
+        }
+    }
+
+    let litlen_model = Huffman::from_symbols(&litlens, LITLEN_ALPHABET)?;
+    let mut table = Vec::new();
+    encode_alphabet_lengths(&model_lengths(&litlen_model), &mut table)?;
+    let mut litlen_enc = Encoder::new(Vec::new(), &litlen_model);
+    litlen_enc.write_symbols(&litlens)?;
+    litlen_enc.flush()?;
+    let distances = if slots.is_empty() {
+        encode_alphabet_lengths(&[0u8; DISTANCE_ALPHABET], &mut table)?;
+        Vec::new()
+    } else {
+        let distance_model = Huffman::from_symbols(&slots, DISTANCE_ALPHABET)?;
+        encode_alphabet_lengths(&model_lengths(&distance_model), &mut table)?;
+        let mut distance_enc = Encoder::new(Vec::new(), &distance_model);
+        for token in tokens.iter() {
+            if let Token::Match { distance, .. } = *token {
+                let (slot, extra, value) = distance_slot(distance);
+                distance_enc.write_symbols(&[slot])?;
+                distance_enc.write_bits(value, extra)?;
+            }
+        }
+        distance_enc.flush()?;
+        distance_enc.inner
+    };
+
+    let mut block = Vec::with_capacity(22 + table.len() + litlen_enc.inner.len() + distances.len());
+    for field in [
+        size,
+        litlens.len(),
+        slots.len(),
+        litlen_enc.inner.len(),
+        distances.len(),
+    ]
+    .iter()
+    {
+        block.extend_from_slice(&(*field as u32).to_be_bytes());
+    }
+    block.extend_from_slice(&(table.len() as u16).to_be_bytes());
+    block.extend_from_slice(&table);
+    block.extend_from_slice(&litlen_enc.inner);
+    block.extend_from_slice(&distances);
+    debug!(
+        "LZ77 block of {} bytes into {} symbols and {} matches, table {} bytes, data {} bytes",
+        size,
+        litlens.len(),
+        slots.len(),
+        table.len(),
+        litlen_enc.inner.len() + distances.len()
+    );
+    Ok(block)
+}
+
+/// Read the next block from `reader` and append the decoded data to
+/// `output`, which must hold the preceding data of at least `window` bytes
+/// or all of it. Returns the number of decoded bytes, `0` for the end marker.
+pub fn decompress_block<R: Read>(
+    reader: &mut R,
+    window: usize,
+    output: &mut Vec<u8>,
+) -> Result<usize> {
+    let mut field = [0u8; 4];
+    read_exact_or_truncated(reader, &mut field)?;
+    let size = u32::from_be_bytes(field) as usize;
+    if size == 0 {
+        return Ok(0);
+    }
+    let mut fields = [0usize; 4];
+    for value in fields.iter_mut() {
+        read_exact_or_truncated(reader, &mut field)?;
+        *value = u32::from_be_bytes(field) as usize;
+    }
+    let [symbols, matches, litlen_size, distance_size] = fields;
+    // A distance needs at most 22 extra bits
+    if size > BLOCK_SIZE
+        || symbols > size
+        || matches > symbols
+        || litlen_size > (symbols * MAX_SENTINEL).div_ceil(8)
+        || distance_size > (matches * (MAX_SENTINEL + 22)).div_ceil(8)
+    {
+        return Err(HuffmanError::CorruptHeader(format!(
+            "Block of {} bytes with {} symbols and {} matches",
+            size, symbols, matches
+        )));
+    }
+    let mut table_length = [0u8; 2];
+    read_exact_or_truncated(reader, &mut table_length)?;
+    let mut table = vec![0u8; u16::from_be_bytes(table_length) as usize];
+    read_exact_or_truncated(reader, &mut table)?;
+    let mut bytes = table.iter().cloned();
+    let litlen_lengths = decode_alphabet_lengths(&mut bytes, LITLEN_ALPHABET)?;
+    let distance_lengths = decode_alphabet_lengths(&mut bytes, DISTANCE_ALPHABET)?;
+    if bytes.next().is_some() {
+        return Err(HuffmanError::CorruptHeader(
+            "Trailing bytes after the block tables".to_string(),
+        ));
+    }
+    let mut litlen_data = vec![0u8; litlen_size];
+    read_exact_or_truncated(reader, &mut litlen_data)?;
+    let mut distance_data = vec![0u8; distance_size];
+    read_exact_or_truncated(reader, &mut distance_data)?;
+
+    let litlen_model = Huffman::from_lengths(&litlen_lengths)?;
+    let mut litlens = Decoder::with_model(litlen_data.as_slice(), &litlen_model, symbols)?;
+    let distance_model;
+    let mut distances = if matches > 0 {
+        distance_model = Huffman::from_lengths(&distance_lengths)?;
+        Some(Decoder::with_model(
+            distance_data.as_slice(),
+            &distance_model,
+            matches,
+        )?)
+    } else {
+        None
+    };
+    let end = output.len() + size;
+    let mut sym = [0u16; 1];
+    for _ in 0..symbols {
+        if litlens.read_symbols(&mut sym)? == 0 {
+            return Err(HuffmanError::TruncatedStream);
+        }
+        if output.len() == end {
+            return Err(HuffmanError::InvalidCode(format!(
+                "More than {} bytes in the block",
+                size
+            )));
+        }
+        if sym[0] < 256 {
+            output.push(sym[0] as u8);
+            continue;
+        }
+        let length = sym[0] as usize - 256 + MIN_MATCH;
+        let decoder = distances
+            .as_mut()
+            .ok_or_else(|| HuffmanError::InvalidCode("Match without distance".to_string()))?;
+        if decoder.read_symbols(&mut sym)? == 0 {
+            return Err(HuffmanError::InvalidCode(
+                "More matches than announced".to_string(),
+            ));
+        }
+        let (base, extra) = slot_distance(sym[0]);
+        let distance = base + decoder.read_bits(extra)?;
+        if distance > window || distance > output.len() || output.len() + length > end {
+            return Err(HuffmanError::InvalidCode(format!(
+                "Match of {} bytes at distance {} out of range",
+                length, distance
+            )));
+        }
+        let from = output.len() - distance;
+        for ix in from..from + length {
+            output.push(output[ix]);
+        }
+    }
+    if output.len() != end {
+        return Err(HuffmanError::InvalidCode(format!(
+            "Expected {} bytes, decoded {}",
+            size,
+            output.len() + size - end
+        )));
+    }
+    Ok(size)
+}
+
+/// Encodes everything written with LZ77 in blocks of `BLOCK_SIZE` bytes.
+/// `finish` must be called to encode the last block.
+pub struct Lz77Writer<W: Write> {
+    inner: W,
+    window: usize,
+    /// Up to `window` bytes of history followed by the bytes of the next
+    /// block, starting at `start`
+    buffer: Vec<u8>,
+    start: usize,
+    finder: MatchFinder,
+    checksum: Option<Crc32>,
+    pub readbytes: u64,
+}
+
+impl<W: Write> Lz77Writer<W> {
+    pub fn new(writer: W, window: usize) -> Result<Self> {
+        validate_window(window)?;
+        Ok(Lz77Writer {
+            inner: writer,
+            window,
+            buffer: Vec::new(),
+            start: 0,
+            finder: MatchFinder::new(window),
+            checksum: None,
+            readbytes: 0,
+        })
+    }
+    /// Write a CRC-32 of all data after the end marker
+    pub fn use_checksum(&mut self, enabled: bool) {
+        self.checksum = if enabled { Some(Crc32::new()) } else { None };
+    }
+    fn write_block(&mut self) -> Result<()> {
+        if self.buffer.len() == self.start {
+            return Ok(());
+        }
+        self.inner
+            .write_all(&encode_block(&self.buffer, self.start, &mut self.finder)?)?;
+        let history = self.buffer.len().saturating_sub(self.window);
+        self.buffer.drain(..history);
+        self.finder.slide(history);
+        self.start = self.buffer.len();
+        Ok(())
+    }
+    /// Encode the last block, write the end marker and the checksum. Returns
+    /// the flushed writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.write_block()?;
+        self.inner.write_all(&[0u8; 4])?;
+        if let Some(crc) = self.checksum {
+            self.inner.write_all(&crc.finalize().to_be_bytes())?;
+        }
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Lz77Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.start + BLOCK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if let Some(crc) = self.checksum.as_mut() {
+            crc.update(&buf[..n]);
+        }
+        self.readbytes += n as u64;
+        if self.buffer.len() == self.start + BLOCK_SIZE {
+            self.write_block()?;
+        }
+        Ok(n)
+    }
+
+    /// Only flushes the inner writer, since every flush would end a block
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decodes the blocks written by a `Lz77Writer`
+pub struct Lz77Reader<R: Read> {
+    inner: R,
+    window: usize,
+    /// Up to `window` bytes of history followed by the decoded bytes of the
+    /// current block, starting at `position`
+    buffer: Vec<u8>,
+    position: usize,
+    finished: bool,
+    pub writeout: u64,
+}
+
+impl<R: Read> Lz77Reader<R> {
+    /// Matches reaching further back than `window` are rejected as corrupt
+    pub fn new(reader: R, window: usize) -> Self {
+        Lz77Reader {
+            inner: reader,
+            window,
+            buffer: Vec::new(),
+            position: 0,
+            finished: false,
+            writeout: 0,
+        }
+    }
+    /// Returns the inner reader, positioned after the end marker once all
+    /// data has been read.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for Lz77Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.position == self.buffer.len() && !self.finished {
+            let history = self.buffer.len().saturating_sub(self.window);
+            self.buffer.drain(..history);
+            self.position = self.buffer.len();
+            if decompress_block(&mut self.inner, self.window, &mut self.buffer)? == 0 {
+                self.finished = true;
+            }
+        }
+        let n = buf.len().min(self.buffer.len() - self.position);
+        buf[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        self.writeout += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pseudo-random bytes with `bits` significant bits
+    fn noise(count: usize, bits: u32) -> Vec<u8> {
+        (0..count as u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761).rotate_left(7) >> (32 - bits)) as u8)
+            .collect()
+    }
+
+    fn roundtrip(data: &[u8], window: usize) -> Vec<u8> {
+        let mut writer = Lz77Writer::new(Vec::new(), window).unwrap();
+        writer.use_checksum(true);
+        writer.write_all(data).unwrap();
+        assert_eq!(writer.readbytes, data.len() as u64);
+        let encoded = writer.finish().unwrap();
+
+        let mut reader = Lz77Reader::new(encoded.as_slice(), window);
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+        let mut footer = reader.into_inner();
+        crate::format::verify_footer(&mut footer, crate::checksum::crc32(data)).unwrap();
+        assert!(footer.is_empty());
+        encoded
+    }
+
+    #[test]
+    fn distance_slots() {
+        let boundaries = (3..24).flat_map(|bits| (1 << bits) - 1..(1 << bits) + 3);
+        for distance in (1..=100_000).chain(boundaries).chain(Some(MAX_WINDOW)) {
+            let (slot, extra, value) = distance_slot(distance);
+            assert!((slot as usize) < DISTANCE_ALPHABET);
+            assert!(value >> extra == 0);
+            assert_eq!(slot_distance(slot), (distance - value, extra));
+        }
+        assert_eq!(distance_slot(5), (4, 1, 0));
+        assert_eq!(distance_slot(MAX_WINDOW), (47, 22, (1 << 22) - 1));
+    }
+
+    #[test]
+    fn tokens() {
+        let data = b"abcabcabcabcx";
+        assert_eq!(
+            tokenize(data, 0, 100),
+            [
+                Token::Literal(b'a'),
+                Token::Literal(b'b'),
+                Token::Literal(b'c'),
+                Token::Match {
+                    length: 9,
+                    distance: 3
+                },
+                Token::Literal(b'x'),
+            ]
+        );
+        // The window limits the distance
+        let tokens = tokenize(data, 0, 2);
+        assert!(tokens.iter().all(|t| matches!(t, Token::Literal(_))));
+        // Matches may refer to the history
+        assert_eq!(
+            tokenize(b"abcdabcd", 4, 100),
+            [Token::Match {
+                length: 4,
+                distance: 4
+            }]
+        );
+    }
+
+    #[test]
+    fn sliding_match_finder() {
+        let text = b"It was the best of times, it was the worst of times, ".repeat(100);
+        let mut data = noise(3000, 3);
+        data.extend_from_slice(&text);
+        let window = 1000;
+        let mut finder = MatchFinder::new(window);
+        let mut buffer = Vec::new();
+        let mut dropped = 0;
+        for chunk in data.chunks(1234) {
+            let start = buffer.len();
+            buffer.extend_from_slice(chunk);
+            let tokens = finder.tokenize(&buffer, start);
+            let all = &data[..dropped + buffer.len()];
+            assert_eq!(tokens, tokenize(all, dropped + start, window));
+            let history = buffer.len().saturating_sub(window);
+            buffer.drain(..history);
+            finder.slide(history);
+            dropped += history;
+        }
+    }
+
+    #[test]
+    fn roundtrips() {
+        let text = b"It was the best of times, it was the worst of times, ".repeat(500);
+        let encoded = roundtrip(&text, DEFAULT_WINDOW);
+        assert!(encoded.len() < text.len() / 50, "{} bytes", encoded.len());
+        roundtrip(&noise(20_000, 8), DEFAULT_WINDOW);
+        roundtrip(&vec![0u8; 70_000], 1);
+        roundtrip(b"ab", 4);
+        roundtrip(b"", 4);
+
+        // Matches across the blocks
+        let mut data = noise(BLOCK_SIZE - 1000, 8);
+        let first = roundtrip(&data, 1 << 20).len();
+        data.extend_from_within(100..10_000);
+        let encoded = roundtrip(&data, 1 << 20);
+        assert!(encoded.len() < first + 1000, "{} bytes", encoded.len());
+    }
+
+    #[test]
+    fn corrupt_blocks() {
+        let data = b"abracadabra, abracadabra!".repeat(40);
+        let block = compress_block(&data, 0, 100).unwrap();
+        for ix in 0..block.len() {
+            for &flip in [0x01u8, 0x80].iter() {
+                let mut corrupt = block.clone();
+                corrupt[ix] ^= flip;
+                // Must not panic, the output may still be wrong
+                let _ = decompress_block(&mut corrupt.as_slice(), 100, &mut Vec::new());
+            }
+        }
+        let mut truncated = &block[..block.len() - 1];
+        assert!(decompress_block(&mut truncated, 100, &mut Vec::new()).is_err());
+        // Distances beyond the window of the reader
+        assert!(decompress_block(&mut block.as_slice(), 10, &mut Vec::new()).is_err());
+    }
+}
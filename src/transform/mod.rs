@@ -0,0 +1,133 @@
+//! Transforms replacing the plain Huffman coding of the data
+//!
+//! Unlike a `filter`, a transform changes the layout of the encoded data:
+//! the data is transformed and coded in a representation of its own, and
+//! the inverse transform runs after the decoding. The transform is recorded
+//! in the header, see `huffman::header`.
+//!
+//! # Binary layout
+//! - 1 byte transform ID, `2` for block sorting, `3` for LZ77. The IDs do
+//!   not overlap with the IDs of the filters.
+//! - Block sorting: 4 byte block size (big endian)
+//! - LZ77: 4 byte window size (big endian)
+//!
+//! Data without a transform has no transform descriptor at all.
+pub mod bwt;
+pub mod lz77;
+
+use crate::error::{HuffmanError, Result};
+use serde::Serialize;
+use std::fmt;
+
+/// Transform ID of block sorting
+const BLOCK_SORTING: u8 = 2;
+
+/// Transform ID of LZ77
+const LZ77: u8 = 3;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum Transform {
+    #[default]
+    None,
+    /// Burrows-Wheeler transform, move-to-front and zero run-length coding
+    /// of blocks of up to `block_size` bytes, see `bwt`
+    BlockSorting { block_size: usize },
+    /// LZ77 with matches up to `window` bytes back, see `lz77`
+    Lz77 { window: usize },
+}
+
+impl Transform {
+    /// Block sorting of blocks with up to `block_size` bytes
+    pub fn block_sorting(block_size: usize) -> Result<Self> {
+        bwt::validate_block_size(block_size)?;
+        Ok(Transform::BlockSorting { block_size })
+    }
+
+    /// LZ77 with a window of `window` bytes
+    pub fn lz77(window: usize) -> Result<Self> {
+        lz77::validate_window(window)?;
+        Ok(Transform::Lz77 { window })
+    }
+
+    /// Append the descriptor of the transform, nothing for `Transform::None`
+    pub fn to_binary(&self, result: &mut Vec<u8>) {
+        let (id, field) = match *self {
+            Transform::None => return,
+            Transform::BlockSorting { block_size } => (BLOCK_SORTING, block_size),
+            Transform::Lz77 { window } => (LZ77, window),
+        };
+        result.push(id);
+        result.extend_from_slice(&(field as u32).to_be_bytes());
+    }
+
+    /// Read the descriptor of a transform
+    pub fn from_binary<I: Iterator<Item = u8>>(bytes: &mut I) -> Result<Self> {
+        let mut next = || {
+            bytes
+                .next()
+                .ok_or_else(|| HuffmanError::CorruptHeader("Missing transform".to_string()))
+        };
+        let id = next()?;
+        if id != BLOCK_SORTING && id != LZ77 {
+            return Err(HuffmanError::CorruptHeader(format!(
+                "Unknown transform {}",
+                id
+            )));
+        }
+        let mut field = [0u8; 4];
+        for byte in field.iter_mut() {
+            *byte = next()?;
+        }
+        let field = u32::from_be_bytes(field) as usize;
+        match id {
+            BLOCK_SORTING => Transform::block_sorting(field),
+            _ => Transform::lz77(field),
+        }
+        .map_err(|err| HuffmanError::CorruptHeader(err.to_string()))
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Transform::None => write!(f, "none"),
+            Transform::BlockSorting { block_size } => {
+                write!(f, "block sorting in blocks of {} bytes", block_size)
+            }
+            Transform::Lz77 { window } => write!(f, "LZ77 with a window of {} bytes", window),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptors() {
+        let transform = Transform::block_sorting(900_000).unwrap();
+        let mut binary = Vec::new();
+        transform.to_binary(&mut binary);
+        assert_eq!(binary, [2, 0x00, 0x0D, 0xBB, 0xA0]);
+        assert_eq!(
+            Transform::from_binary(&mut binary.into_iter()).unwrap(),
+            transform
+        );
+        let transform = Transform::lz77(1 << 16).unwrap();
+        let mut binary = Vec::new();
+        transform.to_binary(&mut binary);
+        assert_eq!(binary, [3, 0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(
+            Transform::from_binary(&mut binary.into_iter()).unwrap(),
+            transform
+        );
+        let mut binary = Vec::new();
+        Transform::None.to_binary(&mut binary);
+        assert!(binary.is_empty());
+        assert!(Transform::block_sorting(0).is_err());
+        assert!(Transform::lz77(lz77::MAX_WINDOW + 1).is_err());
+        assert!(Transform::from_binary(&mut vec![2u8, 0, 0, 0, 0].into_iter()).is_err());
+        assert!(Transform::from_binary(&mut vec![2u8, 0, 1].into_iter()).is_err());
+        assert!(Transform::from_binary(&mut vec![9u8].into_iter()).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Backward bitstreams of Zstandard
+//!
+//! The writer packs the bits starting at the least significant bit of every
+//! byte and closes the stream with a single `1` bit, followed by zeros up to
+//! the next full byte. The reader starts after this end mark in the last byte
+//! and reads towards the first byte, i.e. the value written last is read
+//! first. Each value is read with its most significant bit first.
+use crate::error::{HuffmanError, Result};
+
+/// Writes the bits forward for reading them backward
+#[derive(Debug, Default)]
+pub struct BackwardWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: usize,
+}
+
+impl BackwardWriter {
+    pub fn new() -> Self {
+        BackwardWriter::default()
+    }
+
+    /// Append the lowest `count` bits of `value`, `count` must not exceed 32
+    pub fn write_bits(&mut self, value: usize, count: usize) {
+        debug_assert!(count <= 32 && value >> count == 0);
+        self.buffer |= (value as u64) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Append the end mark and return the bytes of the stream
+    pub fn finish(mut self) -> Vec<u8> {
+        self.write_bits(1, 1);
+        if self.count > 0 {
+            self.write_bits(0, 8 - self.count);
+        }
+        self.bytes
+    }
+}
+
+/// Reads the bits written by a `BackwardWriter`, starting with the last
+pub struct BackwardReader<'a> {
+    data: &'a [u8],
+    /// Number of bits left, negative once more bits were read than written
+    position: isize,
+}
+
+impl<'a> BackwardReader<'a> {
+    /// Start before the end mark in the last byte of `data`
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        match data.last() {
+            Some(&last) if last != 0 => Ok(BackwardReader {
+                data,
+                position: 8 * data.len() as isize - last.leading_zeros() as isize - 1,
+            }),
+            _ => Err(HuffmanError::InvalidCode(
+                "Bitstream without end mark".to_string(),
+            )),
+        }
+    }
+
+    /// Number of bits left, negative once more bits were read than written
+    pub fn remaining(&self) -> isize {
+        self.position
+    }
+
+    /// The next `count` bits without consuming them, up to 32. Bits before
+    /// the start of the stream are zero.
+    pub fn peek_bits(&self, count: usize) -> usize {
+        if count == 0 || self.position <= 0 {
+            return 0;
+        }
+        let end = self.position as usize;
+        let start = end.saturating_sub(count);
+        let mut word = 0u64;
+        for (ix, &byte) in self.data[start / 8..end.div_ceil(8)].iter().enumerate() {
+            word |= (byte as u64) << (8 * ix);
+        }
+        let value = (word >> (start % 8)) & ((1 << (end - start)) - 1);
+        (value as usize) << (count - (end - start))
+    }
+
+    /// Consume `count` bits, which were written as a single value
+    pub fn read_bits(&mut self, count: usize) -> usize {
+        let value = self.peek_bits(count);
+        self.position -= count as isize;
+        value
+    }
+
+    /// Consume `count` bits of a value returned by `peek_bits`
+    pub fn skip_bits(&mut self, count: usize) {
+        self.position -= count as isize;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let values = [
+            (5usize, 3usize),
+            (0, 0),
+            (1, 1),
+            (0x1234, 13),
+            (0, 7),
+            (3, 2),
+        ];
+        let mut writer = BackwardWriter::new();
+        for &(value, count) in values.iter() {
+            writer.write_bits(value, count);
+        }
+        let bytes = writer.finish();
+        assert_eq!(bytes.len(), 4);
+        let mut reader = BackwardReader::new(&bytes).unwrap();
+        assert_eq!(reader.remaining(), 26);
+        for &(value, count) in values.iter().rev() {
+            assert_eq!(reader.read_bits(count), value);
+        }
+        assert_eq!(reader.remaining(), 0);
+        // Zeros before the start of the stream
+        assert_eq!(reader.read_bits(4), 0);
+        assert_eq!(reader.remaining(), -4);
+    }
+
+    #[test]
+    fn end_mark() {
+        // The end mark is the highest set bit of the last byte
+        let mut reader = BackwardReader::new(&[0b1010_1100, 0b0000_0101]).unwrap();
+        assert_eq!(reader.remaining(), 10);
+        assert_eq!(reader.peek_bits(4), 0b0110);
+        assert_eq!(reader.read_bits(2), 0b01);
+        assert_eq!(reader.read_bits(12), 0b1010_1100_0000);
+        assert!(BackwardReader::new(&[1, 0]).is_err());
+        assert!(BackwardReader::new(&[]).is_err());
+    }
+}
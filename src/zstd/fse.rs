@@ -0,0 +1,350 @@
+//! Finite State Entropy coding of the Huffman weights
+//!
+//! Zstandard describes Huffman codes with many symbols by the weights of the
+//! symbols, compressed with FSE (tANS). The distribution of the weights is
+//! written as normalized counts which add up to `1 << table_log`. Both the
+//! encoder and the decoder spread the symbols over a table of this size.
+//! Each table position is a state: it decodes to a symbol and reads a few
+//! bits to select the next state.
+//!
+//! The weights are coded with two interleaved states in a backward
+//! bitstream: the first state decodes the weights with even index, the
+//! second state those with odd index. The end of the weights is detected by
+//! reading past the start of the bitstream.
+use super::bitstream::{BackwardReader, BackwardWriter};
+use crate::error::{HuffmanError, Result};
+
+/// Largest table log for the weights of a Huffman code
+pub const MAX_TABLE_LOG: usize = 6;
+
+/// Smallest table log which can be described
+pub const MIN_TABLE_LOG: usize = 5;
+
+/// Largest weight symbol of a distribution
+pub const MAX_SYMBOL: usize = 12;
+
+/// Most weights decoded from a description
+pub const MAX_WEIGHTS: usize = 255;
+
+/// Normalized count of symbols with a probability below `1 >> table_log`
+const LESS_THAN_ONE: i16 = -1;
+
+/// Position of the most significant set bit
+fn highbit(value: usize) -> usize {
+    (usize::BITS - 1 - value.leading_zeros()) as usize
+}
+
+/// Bits of a normalized count description, read starting at the least
+/// significant bit of every byte
+struct ForwardReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ForwardReader<'a> {
+    /// The next `count` bits, zero beyond the end of the data
+    fn peek(&self, count: usize) -> usize {
+        (0..count)
+            .map(|ix| {
+                let bit = self.position + ix;
+                let byte = self.data.get(bit / 8).copied().unwrap_or(0);
+                ((byte >> (bit % 8)) as usize & 1) << ix
+            })
+            .sum()
+    }
+
+    fn read(&mut self, count: usize) -> usize {
+        let value = self.peek(count);
+        self.position += count;
+        value
+    }
+}
+
+/// Normalize `counts` to a sum of `1 << table_log`. Every symbol which
+/// occurs gets a normalized count of at least 1.
+pub fn normalize(counts: &[usize], table_log: usize) -> Result<Vec<i16>> {
+    let total: usize = counts.iter().sum();
+    let size = 1usize << table_log;
+    let used = counts.iter().filter(|&&count| count > 0).count();
+    if used < 2 || used > size {
+        return Err(HuffmanError::InvalidCode(format!(
+            "Can not normalize {} symbols to {} states",
+            used, size
+        )));
+    }
+    let mut norm: Vec<i16> = counts
+        .iter()
+        .map(|&count| match count {
+            0 => 0,
+            _ => ((count * size + total / 2) / total).max(1) as i16,
+        })
+        .collect();
+    let mut sum: usize = norm.iter().map(|&n| n as usize).sum();
+    while sum != size {
+        // Adjust the symbol with the largest count, which loses the least
+        let (ix, _) = norm
+            .iter()
+            .enumerate()
+            .filter(|&(_, &n)| sum < size || n > 1)
+            .max_by_key(|&(ix, &n)| (counts[ix], n))
+            .expect("At least two symbols occur");
+        if sum < size {
+            norm[ix] += 1;
+            sum += 1;
+        } else {
+            norm[ix] -= 1;
+            sum -= 1;
+        }
+    }
+    Ok(norm)
+}
+
+/// Append the description of the normalized counts
+pub fn write_counts(norm: &[i16], table_log: usize, result: &mut Vec<u8>) {
+    // The description is read forward, starting at the least significant
+    // bit of every byte
+    let mut buffer = 0u64;
+    let mut filled = 0;
+    let mut write = |value: usize, count: usize| {
+        buffer |= (value as u64) << filled;
+        filled += count;
+        while filled >= 8 {
+            result.push(buffer as u8);
+            buffer >>= 8;
+            filled -= 8;
+        }
+    };
+    write(table_log - MIN_TABLE_LOG, 4);
+    let mut remaining = (1i32 << table_log) + 1;
+    let mut threshold = 1i32 << table_log;
+    let mut nbits = table_log + 1;
+    let mut previous_zero = false;
+    let mut symbol = 0;
+    while symbol < norm.len() && remaining > 1 {
+        if previous_zero {
+            let start = symbol;
+            while norm[symbol] == 0 {
+                symbol += 1;
+            }
+            let mut zeros = symbol - start;
+            while zeros >= 3 {
+                write(3, 2);
+                zeros -= 3;
+            }
+            write(zeros, 2);
+        }
+        let mut count = norm[symbol] as i32;
+        symbol += 1;
+        let max = 2 * threshold - 1 - remaining;
+        remaining -= count.abs();
+        count += 1;
+        if count >= threshold {
+            count += max;
+        }
+        write(count as usize, nbits - (count < max) as usize);
+        previous_zero = count == 1;
+        while remaining < threshold {
+            nbits -= 1;
+            threshold >>= 1;
+        }
+    }
+    // Flush the last partial byte
+    write(0, 7);
+}
+
+/// Read the description of normalized counts. Returns the counts, the table
+/// log and the number of bytes of the description.
+pub fn read_counts(data: &[u8], max_table_log: usize) -> Result<(Vec<i16>, usize, usize)> {
+    let corrupt = |msg: &str| HuffmanError::CorruptHeader(msg.to_string());
+    let mut bits = ForwardReader { data, position: 0 };
+    let table_log = bits.read(4) + MIN_TABLE_LOG;
+    if table_log > max_table_log {
+        return Err(HuffmanError::CorruptHeader(format!(
+            "Table log {} exceeds {}",
+            table_log, max_table_log
+        )));
+    }
+    let mut norm = Vec::new();
+    let mut remaining = (1i32 << table_log) + 1;
+    let mut threshold = 1i32 << table_log;
+    let mut nbits = table_log + 1;
+    let mut previous_zero = false;
+    while remaining > 1 {
+        if previous_zero {
+            loop {
+                let zeros = bits.read(2);
+                norm.extend(std::iter::repeat_n(0i16, zeros));
+                if zeros != 3 {
+                    break;
+                }
+                if bits.position > 8 * data.len() {
+                    return Err(HuffmanError::TruncatedStream);
+                }
+            }
+        }
+        if norm.len() > MAX_SYMBOL {
+            return Err(corrupt("Too many symbols in the distribution"));
+        }
+        let max = 2 * threshold - 1 - remaining;
+        let mut count = bits.peek(nbits - 1) as i32;
+        if count < max {
+            bits.position += nbits - 1;
+        } else {
+            count = bits.peek(nbits) as i32;
+            if count >= threshold {
+                count -= max;
+            }
+            bits.position += nbits;
+        }
+        count -= 1;
+        if count.abs() > remaining - 1 {
+            return Err(corrupt("Normalized counts exceed the table"));
+        }
+        remaining -= count.abs();
+        norm.push(count as i16);
+        previous_zero = count == 0;
+        while remaining < threshold {
+            nbits -= 1;
+            threshold >>= 1;
+        }
+    }
+    if remaining != 1 {
+        return Err(corrupt("Normalized counts do not fill the table"));
+    }
+    let length = bits.position.div_ceil(8);
+    if length > data.len() {
+        return Err(HuffmanError::TruncatedStream);
+    }
+    Ok((norm, table_log, length))
+}
+
+/// Table positions of the symbols. Symbols with a normalized count of `-1`
+/// take the last positions, the others are spread over the table.
+fn spread(norm: &[i16], table_log: usize) -> Result<Vec<u8>> {
+    let size = 1usize << table_log;
+    let mut symbols = vec![0u8; size];
+    let mut high = size;
+    for (sym, _) in norm
+        .iter()
+        .enumerate()
+        .filter(|&(_, &n)| n == LESS_THAN_ONE)
+    {
+        high -= 1;
+        symbols[high] = sym as u8;
+    }
+    let step = (size >> 1) + (size >> 3) + 3;
+    let mut position = 0;
+    for (sym, &n) in norm.iter().enumerate().filter(|&(_, &n)| n > 0) {
+        for _ in 0..n {
+            symbols[position] = sym as u8;
+            position = (position + step) & (size - 1);
+            while position >= high {
+                position = (position + step) & (size - 1);
+            }
+        }
+    }
+    if position != 0 {
+        return Err(HuffmanError::InvalidCode(
+            "Normalized counts do not spread over the table".to_string(),
+        ));
+    }
+    Ok(symbols)
+}
+
+/// Decoded symbol, number of bits to read and base of the next state
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    symbol: u8,
+    nbits: usize,
+    base: usize,
+}
+
+fn decode_table(norm: &[i16], table_log: usize) -> Result<Vec<Entry>> {
+    let size = 1usize << table_log;
+    let symbols = spread(norm, table_log)?;
+    let mut next: Vec<usize> = norm.iter().map(|&n| n.max(1) as usize).collect();
+    Ok(symbols
+        .iter()
+        .map(|&symbol| {
+            let state = next[symbol as usize];
+            next[symbol as usize] += 1;
+            let nbits = table_log - highbit(state);
+            Entry {
+                symbol,
+                nbits,
+                base: (state << nbits) - size,
+            }
+        })
+        .collect())
+}
+
+/// Compress the weights, `None` if they can not be compressed with FSE, i.e.
+/// less than two weights or a single distinct weight
+pub fn compress_weights(weights: &[u8]) -> Option<Vec<u8>> {
+    if weights.len() < 2 {
+        return None;
+    }
+    let mut counts = [0usize; MAX_SYMBOL + 1];
+    for &weight in weights {
+        counts[weight as usize] += 1;
+    }
+    let used = counts.iter().rposition(|&count| count > 0)? + 1;
+    let table_log = MAX_TABLE_LOG;
+    let norm = normalize(&counts[..used], table_log).ok()?;
+    let size = 1usize << table_log;
+    let symbols = spread(&norm, table_log).ok()?;
+    // Encoder states `size..2 * size` per symbol, ordered like the decoder
+    // assigns them
+    let mut states: Vec<Vec<usize>> = vec![Vec::new(); used];
+    for (position, &sym) in symbols.iter().enumerate() {
+        states[sym as usize].push(size + position);
+    }
+
+    // The encoder runs backward. The last weight of each state is given by
+    // its final state, which the decoder reads first.
+    let mut writer = BackwardWriter::new();
+    let mut state = [0usize; 2];
+    let last = weights.len() - 1;
+    state[last % 2] = states[weights[last] as usize][0];
+    state[(last - 1) % 2] = states[weights[last - 1] as usize][0];
+    for ix in (0..last - 1).rev() {
+        let sym = weights[ix] as usize;
+        let count = norm[sym].max(1) as usize;
+        let current = &mut state[ix % 2];
+        let mut nbits = 0;
+        while *current >> nbits >= 2 * count {
+            nbits += 1;
+        }
+        writer.write_bits(*current & ((1 << nbits) - 1), nbits);
+        *current = states[sym][(*current >> nbits) - count];
+    }
+    writer.write_bits(state[1] - size, table_log);
+    writer.write_bits(state[0] - size, table_log);
+
+    let mut result = Vec::new();
+    write_counts(&norm, table_log, &mut result);
+    result.extend_from_slice(&writer.finish());
+    Some(result)
+}
+
+/// Decompress the weights compressed by `compress_weights`
+pub fn decompress_weights(data: &[u8]) -> Result<Vec<u8>> {
+    let (norm, table_log, length) = read_counts(data, MAX_TABLE_LOG)?;
+    let table = decode_table(&norm, table_log)?;
+    let mut bits = BackwardReader::new(&data[length..])?;
+    let mut state = [bits.read_bits(table_log), bits.read_bits(table_log)];
+    let mut weights = Vec::new();
+    for ix in 0.. {
+        if weights.len() + 2 > MAX_WEIGHTS {
+            return Err(HuffmanError::CorruptHeader("Too many weights".to_string()));
+        }
+        let entry = table[state[ix % 2]];
+        weights.push(entry.symbol);
+        state[ix % 2] = entry.base + bits.read_bits(entry.nbits);
+        if bits.remaining() < 0 {
+            weights.push(table[state[(ix + 1) % 2]].symbol);
+            break;
+        }
+    }
+    Ok(weights)
+}
@@ -0,0 +1,429 @@
+//! Huffman codes and streams of Zstandard (HUF)
+//!
+//! # Codes
+//! A code is described by the weights of the symbols `0..=last`. A symbol
+//! of weight `w > 0` has a codeword of `max_bits + 1 - w` bits, weight `0`
+//! marks unused symbols. The codewords are assigned in increasing order of
+//! (weight, symbol), i.e. the longest codewords come first. This is the
+//! reverse of the canonical order of `huffman::Huffman`.
+//!
+//! # Description
+//! The weight of the last symbol is not stored, it follows from the others
+//! since the code is complete. The first byte selects the representation of
+//! the other weights:
+//! - `< 128`: number of bytes of the FSE compressed weights, see `fse`
+//! - `>= 128`: `byte - 127` weights with 4 bits each, the first weight in
+//!   the high bits of the first byte
+//!
+//! # Streams
+//! The symbols are coded into a backward bitstream, see `bitstream`, in
+//! reverse order such that they are decoded in their original order. Larger
+//! data is split into 4 segments of `(size + 3) / 4` bytes, the last segment
+//! takes the rest. Each segment is coded into its own stream and a jump table
+//! with the sizes of the first 3 streams (2 bytes each, little endian)
+//! precedes the streams.
+use super::bitstream::{BackwardReader, BackwardWriter};
+use super::fse;
+use crate::error::{HuffmanError, Result};
+use crate::huffman::decode::table::DecodeTable;
+use crate::huffman::decode::validate_lookup;
+use crate::huffman::header::model_lengths;
+use crate::huffman::Huffman;
+use crate::model::Model;
+use std::collections::BTreeMap;
+
+/// Longest codeword
+pub const MAX_BITS: usize = 11;
+
+/// Largest number of weights of the direct representation
+const MAX_DIRECT_WEIGHTS: usize = 128;
+
+/// Size of the jump table in front of 4 streams
+pub const JUMP_TABLE_SIZE: usize = 6;
+
+/// Huffman code of up to 256 symbols with codewords of at most `MAX_BITS`
+/// bits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HufTable {
+    /// Codeword lengths of the symbols up to the last used symbol
+    lengths: Vec<u8>,
+    codes: Vec<usize>,
+    max_bits: usize,
+}
+
+impl Model for HufTable {
+    fn encode(&self, sym: usize) -> (usize, usize) {
+        (self.codes[sym], self.lengths[sym] as usize)
+    }
+    fn sentinel(&self) -> usize {
+        self.max_bits
+    }
+    fn to_btreemap(&self) -> BTreeMap<usize, (u16, u8)> {
+        self.lengths
+            .iter()
+            .enumerate()
+            .filter(|&(_, &length)| length > 0)
+            .map(|(sym, &length)| {
+                (
+                    self.codes[sym] << (self.max_bits - length as usize),
+                    (sym as u16, length),
+                )
+            })
+            .collect()
+    }
+    fn alphabet_size(&self) -> usize {
+        self.lengths.len()
+    }
+}
+
+impl HufTable {
+    /// Code for the bytes counted in `histogram`, built with the codeword
+    /// lengths of `Huffman`. At least two bytes must occur.
+    pub fn from_histogram(histogram: &[usize]) -> Result<Self> {
+        let last = histogram.iter().rposition(|&count| count > 0).unwrap_or(0);
+        let model = Huffman::from_histogram(&histogram[..last + 1], MAX_BITS)?;
+        HufTable::from_lengths(&model_lengths(&model))
+    }
+
+    /// Code with the given codeword lengths, which must describe a complete
+    /// code of at least two symbols
+    pub fn from_lengths(lengths: &[u8]) -> Result<Self> {
+        let last = lengths.iter().rposition(|&length| length > 0).unwrap_or(0);
+        let lengths = &lengths[..last + 1];
+        let max_bits = *lengths.iter().max().unwrap_or(&0) as usize;
+        if lengths.len() > 256 || max_bits > MAX_BITS {
+            return Err(HuffmanError::InvalidCode(format!(
+                "{} symbols with codewords of up to {} bits",
+                lengths.len(),
+                max_bits
+            )));
+        }
+        let kraft: usize = lengths
+            .iter()
+            .filter(|&&length| length > 0)
+            .map(|&length| 1 << (MAX_BITS - length as usize))
+            .sum();
+        if kraft != 1 << MAX_BITS || lengths.iter().filter(|&&l| l > 0).count() < 2 {
+            return Err(HuffmanError::InvalidCode(
+                "Codeword lengths do not describe a complete code".to_string(),
+            ));
+        }
+        // Starting codeword per length, the longest codewords start at 0
+        let mut count = [0usize; MAX_BITS + 1];
+        for &length in lengths.iter() {
+            count[length as usize] += 1;
+        }
+        let mut next = [0usize; MAX_BITS + 1];
+        let mut code = 0;
+        for length in (1..=max_bits).rev() {
+            next[length] = code;
+            code = (code + count[length]) >> 1;
+        }
+        let codes = lengths
+            .iter()
+            .map(|&length| match length {
+                0 => 0,
+                _ => {
+                    next[length as usize] += 1;
+                    next[length as usize] - 1
+                }
+            })
+            .collect();
+        Ok(HufTable {
+            lengths: lengths.to_vec(),
+            codes,
+            max_bits,
+        })
+    }
+
+    /// Code with the given weights of all symbols, including the last one
+    pub fn from_weights(weights: &[u8]) -> Result<Self> {
+        let total: usize = weights
+            .iter()
+            .filter(|&&weight| weight > 0)
+            .map(|&weight| 1 << (weight - 1))
+            .sum();
+        if weights.iter().any(|&weight| weight as usize > MAX_BITS) || !total.is_power_of_two() {
+            return Err(HuffmanError::InvalidCode(
+                "Weights do not describe a complete code".to_string(),
+            ));
+        }
+        let max_bits = total.trailing_zeros() as usize;
+        let lengths: Vec<u8> = weights
+            .iter()
+            .map(|&weight| match weight {
+                0 => 0,
+                _ => (max_bits + 1 - weight as usize) as u8,
+            })
+            .collect();
+        HufTable::from_lengths(&lengths)
+    }
+
+    /// Weights of the symbols up to the last used symbol
+    pub fn weights(&self) -> Vec<u8> {
+        self.lengths
+            .iter()
+            .map(|&length| match length {
+                0 => 0,
+                _ => (self.max_bits + 1 - length as usize) as u8,
+            })
+            .collect()
+    }
+
+    /// Length of the longest codeword
+    pub fn max_bits(&self) -> usize {
+        self.max_bits
+    }
+
+    /// Append the description of the code, with FSE compressed weights if
+    /// that is shorter
+    pub fn write_description(&self, result: &mut Vec<u8>) -> Result<()> {
+        let weights = self.weights();
+        let weights = &weights[..weights.len() - 1];
+        let compressed = fse::compress_weights(weights).filter(|c| c.len() < 128);
+        let direct = weights.len() <= MAX_DIRECT_WEIGHTS;
+        match compressed {
+            Some(compressed) if !direct || compressed.len() < weights.len().div_ceil(2) => {
+                result.push(compressed.len() as u8);
+                result.extend_from_slice(&compressed);
+            }
+            _ if direct => {
+                result.push((weights.len() + 127) as u8);
+                for pair in weights.chunks(2) {
+                    result.push(pair[0] << 4 | pair.get(1).copied().unwrap_or(0));
+                }
+            }
+            _ => {
+                return Err(HuffmanError::InvalidCode(format!(
+                    "Weights of {} symbols can not be described",
+                    self.lengths.len()
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a description of a code. Returns the code and the number of
+    /// bytes of the description.
+    pub fn read_description(data: &[u8]) -> Result<(Self, usize)> {
+        let header = *data.first().ok_or(HuffmanError::TruncatedStream)? as usize;
+        let (mut weights, length) = if header < 128 {
+            let compressed = data
+                .get(1..1 + header)
+                .ok_or(HuffmanError::TruncatedStream)?;
+            (fse::decompress_weights(compressed)?, 1 + header)
+        } else {
+            let count = header - 127;
+            let length = 1 + count.div_ceil(2);
+            let packed = data.get(1..length).ok_or(HuffmanError::TruncatedStream)?;
+            let weights = packed
+                .iter()
+                .flat_map(|&byte| [byte >> 4, byte & 0x0F])
+                .take(count)
+                .collect();
+            (weights, length)
+        };
+        // The last weight completes the code
+        let total: usize = weights
+            .iter()
+            .filter(|&&weight| weight > 0)
+            .map(|&weight| 1usize << (weight - 1).min(MAX_BITS as u8))
+            .sum();
+        if total == 0 || weights.len() > 255 {
+            return Err(HuffmanError::CorruptHeader(
+                "Weights without symbols".to_string(),
+            ));
+        }
+        let rest = (total + 1).next_power_of_two() - total;
+        if !rest.is_power_of_two() {
+            return Err(HuffmanError::CorruptHeader(
+                "Weights can not be completed".to_string(),
+            ));
+        }
+        weights.push(rest.trailing_zeros() as u8 + 1);
+        let table = HufTable::from_weights(&weights)
+            .map_err(|err| HuffmanError::CorruptHeader(err.to_string()))?;
+        Ok((table, length))
+    }
+}
+
+/// Encode `data` into a single stream
+pub fn encode_stream(table: &HufTable, data: &[u8]) -> Result<Vec<u8>> {
+    let mut writer = BackwardWriter::new();
+    for &byte in data.iter().rev() {
+        let (code, length) = table
+            .lengths
+            .get(byte as usize)
+            .filter(|&&length| length > 0)
+            .map(|&length| (table.codes[byte as usize], length as usize))
+            .ok_or_else(|| HuffmanError::InvalidCode(format!("Byte {} has no codeword", byte)))?;
+        writer.write_bits(code, length);
+    }
+    Ok(writer.finish())
+}
+
+/// Encode `data` into a single stream or into 4 streams with a jump table
+pub fn encode_streams(table: &HufTable, data: &[u8], four: bool) -> Result<Vec<u8>> {
+    if !four {
+        return encode_stream(table, data);
+    }
+    let segment = data.len().div_ceil(4);
+    let mut result = vec![0u8; JUMP_TABLE_SIZE];
+    for (ix, chunk) in data
+        .chunks(segment.max(1))
+        .chain([&[][..]; 4])
+        .take(4)
+        .enumerate()
+    {
+        let stream = encode_stream(table, chunk)?;
+        if ix < 3 {
+            if stream.len() > u16::MAX as usize {
+                return Err(HuffmanError::InvalidCode(format!(
+                    "Stream of {} bytes exceeds the jump table",
+                    stream.len()
+                )));
+            }
+            result[2 * ix..2 * ix + 2].copy_from_slice(&(stream.len() as u16).to_le_bytes());
+        }
+        result.extend_from_slice(&stream);
+    }
+    Ok(result)
+}
+
+/// Decoder of the streams of a `HufTable`, via the lookup table of the
+/// `huffman::decode` module
+pub struct StreamDecoder {
+    table: DecodeTable,
+    max_bits: usize,
+}
+
+impl StreamDecoder {
+    pub fn new(table: &HufTable) -> Result<Self> {
+        let bt = table.to_btreemap();
+        validate_lookup(&bt, table.max_bits)?;
+        Ok(StreamDecoder {
+            table: DecodeTable::new(&bt, table.max_bits)?,
+            max_bits: table.max_bits,
+        })
+    }
+
+    /// Decode a single stream of `size` bytes and append them to `output`.
+    /// The stream must end with the last byte.
+    pub fn decode_stream(&self, data: &[u8], size: usize, output: &mut Vec<u8>) -> Result<()> {
+        let mut reader = BackwardReader::new(data)?;
+        for _ in 0..size {
+            let (sym, length) = self.table.lookup(reader.peek_bits(self.max_bits) as u64)?;
+            reader.skip_bits(length as usize);
+            output.push(sym as u8);
+        }
+        match reader.remaining() {
+            0 => Ok(()),
+            remaining if remaining < 0 => Err(HuffmanError::TruncatedStream),
+            remaining => Err(HuffmanError::InvalidCode(format!(
+                "{} bits left after the last symbol",
+                remaining
+            ))),
+        }
+    }
+
+    /// Decode `size` bytes from a single stream or from 4 streams with a
+    /// jump table
+    pub fn decode_streams(&self, data: &[u8], size: usize, four: bool) -> Result<Vec<u8>> {
+        let mut output = Vec::with_capacity(size);
+        if !four {
+            self.decode_stream(data, size, &mut output)?;
+            return Ok(output);
+        }
+        let jump = data
+            .get(..JUMP_TABLE_SIZE)
+            .ok_or(HuffmanError::TruncatedStream)?;
+        let mut start = JUMP_TABLE_SIZE;
+        let segment = size.div_ceil(4);
+        for ix in 0..4 {
+            let end = if ix < 3 {
+                start + u16::from_le_bytes([jump[2 * ix], jump[2 * ix + 1]]) as usize
+            } else {
+                data.len()
+            };
+            let stream = data.get(start..end).ok_or(HuffmanError::TruncatedStream)?;
+            let count = segment.min(size - output.len());
+            self.decode_stream(stream, count, &mut output)?;
+            start = end;
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights_and_codes() {
+        // Example of RFC 8878, section 4.2.1
+        let table = HufTable::from_weights(&[4, 3, 2, 0, 1, 1]).unwrap();
+        assert_eq!(table.max_bits(), 4);
+        assert_eq!(table.encode(0), (0b1, 1));
+        assert_eq!(table.encode(1), (0b01, 2));
+        assert_eq!(table.encode(2), (0b001, 3));
+        assert_eq!(table.encode(4), (0b0000, 4));
+        assert_eq!(table.encode(5), (0b0001, 4));
+        assert_eq!(table.weights(), [4, 3, 2, 0, 1, 1]);
+
+        let mut description = Vec::new();
+        table.write_description(&mut description).unwrap();
+        assert_eq!(description, [132, 0x43, 0x20, 0x10]);
+        assert_eq!(
+            HufTable::read_description(&description).unwrap(),
+            (table, 4)
+        );
+        assert!(HufTable::from_weights(&[4, 3, 2, 0, 1]).is_err());
+        assert!(HufTable::from_lengths(&[1]).is_err());
+        assert!(HufTable::read_description(&[132, 0x43]).is_err());
+    }
+
+    #[test]
+    fn descriptions() {
+        // Many symbols need the FSE compressed weights
+        let mut histogram: Vec<usize> = (0..256).map(|i| 1 + (i * i) % 97).collect();
+        histogram[17] = 0;
+        let table = HufTable::from_histogram(&histogram).unwrap();
+        let mut description = Vec::new();
+        table.write_description(&mut description).unwrap();
+        assert!((description[0] as usize) < 128);
+        assert!(description.len() < 100, "{} bytes", description.len());
+        assert_eq!(
+            HufTable::read_description(&description).unwrap(),
+            (table, description.len())
+        );
+        for ix in 1..description.len() {
+            // Must not panic, the code may still be different
+            let _ = HufTable::read_description(&description[..ix]);
+        }
+        // A flat distribution of 256 symbols can not be described
+        assert!(HufTable::from_histogram(&[1; 256])
+            .unwrap()
+            .write_description(&mut Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn streams() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i * i % 7 + i % 3) as u8).collect();
+        let mut histogram = [0usize; 256];
+        for &byte in data.iter() {
+            histogram[byte as usize] += 1;
+        }
+        let table = HufTable::from_histogram(&histogram).unwrap();
+        let decoder = StreamDecoder::new(&table).unwrap();
+        for &four in [false, true].iter() {
+            for size in [0, 1, 5, 5000].iter() {
+                let encoded = encode_streams(&table, &data[..*size], four).unwrap();
+                let decoded = decoder.decode_streams(&encoded, *size, four).unwrap();
+                assert_eq!(decoded, &data[..*size]);
+                assert!(decoder.decode_streams(&encoded, size + 1, four).is_err());
+            }
+        }
+        assert!(encode_stream(&table, &[200]).is_err());
+    }
+}
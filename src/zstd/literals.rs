@@ -0,0 +1,288 @@
+//! Literals sections of Zstandard blocks
+//!
+//! A section starts with a header of 1 to 5 bytes, little endian. Its lowest
+//! 2 bits give the type of the section, the next 2 bits the size format:
+//! - raw and RLE: the regenerated size with 5, 12 or 20 bits, followed by
+//!   the bytes or the single repeated byte
+//! - compressed and treeless: the regenerated and the compressed size with
+//!   10, 10, 14 or 18 bits each. Size format `0` uses a single stream, the
+//!   others 4 streams. A compressed section starts with the description of
+//!   its `HufTable`, a treeless section reuses the table of an earlier one.
+use super::huf::{encode_streams, HufTable, StreamDecoder};
+use crate::error::{HuffmanError, Result};
+
+/// Largest number of literals of a section, the block size of Zstandard
+pub const MAX_LITERALS: usize = 128 * 1024;
+
+const RAW: u8 = 0;
+const RLE: u8 = 1;
+const COMPRESSED: u8 = 2;
+const TREELESS: u8 = 3;
+
+/// Literals of fewer bytes are coded into a single stream
+const MIN_FOUR_STREAMS: usize = 256;
+
+/// Header of a raw or RLE section
+fn write_raw_header(section_type: u8, size: usize, result: &mut Vec<u8>) {
+    let (format, length) = match size {
+        0..=31 => (0, 1),
+        32..=4095 => (1, 2),
+        _ => (3, 3),
+    };
+    let value = section_type as usize | format << 2 | size << (3 + (format & 1));
+    result.extend_from_slice(&value.to_le_bytes()[..length]);
+}
+
+/// Code `literals` into a literals section with a new table, as raw bytes
+/// or as a single repeated byte, whichever is shortest
+pub fn compress_literals(literals: &[u8]) -> Result<Vec<u8>> {
+    if literals.len() > MAX_LITERALS {
+        return Err(HuffmanError::InvalidCode(format!(
+            "{} literals exceed the limit of {}",
+            literals.len(),
+            MAX_LITERALS
+        )));
+    }
+    let mut result = Vec::new();
+    if !literals.is_empty() && literals.iter().all(|&byte| byte == literals[0]) {
+        write_raw_header(RLE, literals.len(), &mut result);
+        result.push(literals[0]);
+        return Ok(result);
+    }
+    write_raw_header(RAW, literals.len(), &mut result);
+    result.extend_from_slice(literals);
+
+    let mut histogram = [0usize; 256];
+    for &byte in literals.iter() {
+        histogram[byte as usize] += 1;
+    }
+    let compressed = HufTable::from_histogram(&histogram).and_then(|table| {
+        let four = literals.len() >= MIN_FOUR_STREAMS;
+        let mut compressed = Vec::new();
+        table.write_description(&mut compressed)?;
+        compressed.extend_from_slice(&encode_streams(&table, literals, four)?);
+        Ok((compressed, four))
+    });
+    if let Ok((compressed, four)) = compressed {
+        let size = literals.len().max(compressed.len());
+        let (format, bits): (u64, usize) = match size {
+            _ if !four => (0, 10),
+            0..=1023 => (1, 10),
+            1024..=16383 => (2, 14),
+            _ => (3, 18),
+        };
+        let length = (4 + 2 * bits).div_ceil(8);
+        if compressed.len() < (1 << bits) && length + compressed.len() < result.len() {
+            let value = COMPRESSED as u64
+                | format << 2
+                | (literals.len() as u64) << 4
+                | (compressed.len() as u64) << (4 + bits);
+            result.clear();
+            result.extend_from_slice(&value.to_le_bytes()[..length]);
+            result.extend_from_slice(&compressed);
+        }
+    }
+    Ok(result)
+}
+
+/// Little endian value of the first `length` bytes of `data`
+fn read_header(data: &[u8], length: usize) -> Result<u64> {
+    let bytes = data.get(..length).ok_or(HuffmanError::TruncatedStream)?;
+    Ok(bytes
+        .iter()
+        .rev()
+        .fold(0, |value, &byte| value << 8 | byte as u64))
+}
+
+/// Decode the literals section at the start of `section`. `table` is the
+/// table of the last compressed section, which is replaced by the table of a
+/// compressed section and used by a treeless section. Returns the literals
+/// and the number of bytes of the section.
+pub fn decompress_literals(
+    section: &[u8],
+    table: &mut Option<HufTable>,
+) -> Result<(Vec<u8>, usize)> {
+    let first = *section.first().ok_or(HuffmanError::TruncatedStream)?;
+    let section_type = first & 3;
+    let format = (first >> 2) & 3;
+    if section_type == RAW || section_type == RLE {
+        let (size, length) = match format {
+            0 | 2 => ((first >> 3) as usize, 1),
+            1 => ((read_header(section, 2)? >> 4) as usize, 2),
+            _ => ((read_header(section, 3)? >> 4) as usize, 3),
+        };
+        return if section_type == RAW {
+            let literals = section
+                .get(length..length + size)
+                .ok_or(HuffmanError::TruncatedStream)?;
+            Ok((literals.to_vec(), length + size))
+        } else {
+            let byte = *section.get(length).ok_or(HuffmanError::TruncatedStream)?;
+            Ok((vec![byte; size], length + 1))
+        };
+    }
+
+    let bits: usize = [10, 10, 14, 18][format as usize];
+    let length = (4 + 2 * bits).div_ceil(8);
+    let value = read_header(section, length)?;
+    let mask = (1 << bits) - 1;
+    let size = ((value >> 4) & mask) as usize;
+    let compressed = ((value >> (4 + bits)) & mask) as usize;
+    if size > MAX_LITERALS {
+        return Err(HuffmanError::CorruptHeader(format!(
+            "{} literals exceed the limit of {}",
+            size, MAX_LITERALS
+        )));
+    }
+    let mut data = section
+        .get(length..length + compressed)
+        .ok_or(HuffmanError::TruncatedStream)?;
+    if section_type == COMPRESSED {
+        let (new_table, description) = HufTable::read_description(data)?;
+        *table = Some(new_table);
+        data = &data[description..];
+    } else if section_type == TREELESS && table.is_none() {
+        return Err(HuffmanError::CorruptHeader(
+            "Treeless literals without a previous table".to_string(),
+        ));
+    }
+    let decoder = StreamDecoder::new(table.as_ref().expect("Table was set"))?;
+    let literals = decoder.decode_streams(data, size, format != 0)?;
+    Ok((literals, length + compressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+
+    /// Bytes with 5 random bits each, which Zstandard codes as literals
+    fn noise(size: usize) -> Vec<u8> {
+        let mut state = 0x2545_F491u32;
+        (0..size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                b'a' + (state >> 27) as u8
+            })
+            .collect()
+    }
+
+    fn sections() -> Vec<(Vec<u8>, u8)> {
+        let text = b"Zstandard codes literals with Huffman codes".repeat(50);
+        vec![
+            (Vec::new(), RAW),
+            (vec![7; 3000], RLE),
+            (b"ab".to_vec(), RAW),
+            (noise(100), COMPRESSED),
+            (noise(3000), COMPRESSED),
+            (text, COMPRESSED),
+            (noise(MAX_LITERALS), COMPRESSED),
+            ((0..=255).collect(), RAW),
+        ]
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut table = None;
+        for (literals, section_type) in sections() {
+            let section = compress_literals(&literals).unwrap();
+            assert_eq!(section[0] & 3, section_type, "{} bytes", literals.len());
+            let (decoded, length) = decompress_literals(&section, &mut table).unwrap();
+            assert_eq!(decoded, literals);
+            assert_eq!(length, section.len());
+            for ix in 0..section.len().min(50) {
+                assert!(decompress_literals(&section[..ix], &mut table).is_err());
+            }
+        }
+        assert!(compress_literals(&noise(MAX_LITERALS + 1)).is_err());
+    }
+
+    #[test]
+    fn treeless() {
+        let literals = noise(1000);
+        let section = compress_literals(&literals).unwrap();
+        let mut table = None;
+        let mut treeless = section.clone();
+        treeless[0] |= TREELESS;
+        assert!(decompress_literals(&treeless, &mut table).is_err());
+        decompress_literals(&section, &mut table).unwrap();
+        // The same streams, without the description
+        let description = HufTable::read_description(&section[3..]).unwrap().1;
+        let compressed = section.len() - 3 - description;
+        let value = TREELESS as u64 | 1 << 2 | 1000 << 4 | (compressed as u64) << 14;
+        treeless = value.to_le_bytes()[..3].to_vec();
+        treeless.extend_from_slice(&section[3 + description..]);
+        let (decoded, length) = decompress_literals(&treeless, &mut table).unwrap();
+        assert_eq!(decoded, literals);
+        assert_eq!(length, treeless.len());
+    }
+
+    /// Zstandard frame of a single block with the literals section and no
+    /// sequences
+    fn frame(section: &[u8], size: usize) -> Vec<u8> {
+        // A window of 128 KiB, a single segment frame would limit the block to
+        // the size of the data
+        let mut frame = vec![0x28, 0xB5, 0x2F, 0xFD, 0x80, 7 << 3];
+        frame.extend_from_slice(&(size as u32).to_le_bytes());
+        let block = (section.len() + 1) << 3 | 2 << 1 | 1;
+        frame.extend_from_slice(&block.to_le_bytes()[..3]);
+        frame.extend_from_slice(section);
+        frame.push(0);
+        frame
+    }
+
+    /// Output of `zstd` with `args` for `input`, `None` if zstd is not
+    /// installed
+    fn zstd(args: &[&str], input: &[u8]) -> Option<Vec<u8>> {
+        let mut child = Command::new("zstd")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .ok()?;
+        let mut stdin = child.stdin.take().unwrap();
+        let input = input.to_vec();
+        let feeder = std::thread::spawn(move || stdin.write_all(&input));
+        let output = child.wait_with_output().unwrap();
+        feeder.join().unwrap().unwrap();
+        assert!(output.status.success(), "zstd rejected the input");
+        Some(output.stdout)
+    }
+
+    #[test]
+    fn zstd_decodes_sections() {
+        // Zstandard codes empty blocks as raw blocks, a compressed block has
+        // at least 3 bytes
+        for (literals, _) in sections().into_iter().filter(|(l, _)| !l.is_empty()) {
+            let section = compress_literals(&literals).unwrap();
+            match zstd(&["-dc"], &frame(&section, literals.len())) {
+                Some(decoded) => assert_eq!(decoded, literals),
+                None => return,
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_zstd_sections() {
+        let literals = noise(20000);
+        let frame = match zstd(&["-c", "--no-check", "--single-thread"], &literals) {
+            Some(frame) => frame,
+            None => return,
+        };
+        // Skip the frame header and the window descriptor
+        let header = if frame[4] & 0x20 != 0 { 5 } else { 6 };
+        let header = header
+            + [0, 1, 2, 4][frame[4] as usize & 3]
+            + [(frame[4] & 0x20 != 0) as usize, 2, 4, 8][frame[4] as usize >> 6];
+        let block = read_header(&frame[header..], 3).unwrap() as usize;
+        assert_eq!(block & 7, 2 << 1 | 1, "single compressed block");
+        let mut table = None;
+        let (decoded, length) = decompress_literals(&frame[header + 3..], &mut table).unwrap();
+        assert_eq!(frame[header + 3] & 3, COMPRESSED);
+        assert_eq!(decoded, literals);
+        assert_eq!(frame[header + 3 + length], 0, "no sequences");
+    }
+}
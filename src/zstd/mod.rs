@@ -0,0 +1,22 @@
+//! Huffman coded literals of Zstandard (RFC 8878)
+//!
+//! Zstandard codes the literals of every block with its own Huffman format
+//! (HUF), which this module implements without the rest of the format:
+//! - `bitstream`: the backward bitstreams of all entropy coded data
+//! - `fse`: FSE coding of the weights which describe larger Huffman codes
+//! - `huf`: the `HufTable` with its description and the streams of
+//!   codewords, either a single stream or 4 streams with a jump table
+//! - `literals`: the literals sections of a block, which hold raw, repeated
+//!   or Huffman coded literals
+//!
+//! Literals sections written by `compress_literals` form a valid Zstandard
+//! block together with an empty sequences section, and
+//! `decompress_literals` reads the sections written by Zstandard. The
+//! streams are decoded with the lookup table of `huffman::decode::table`.
+pub mod bitstream;
+pub mod fse;
+pub mod huf;
+pub mod literals;
+
+pub use huf::HufTable;
+pub use literals::{compress_literals, decompress_literals};